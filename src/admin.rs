@@ -0,0 +1,208 @@
+use std::net::SocketAddr;
+use crate::event_log::{log_event, Level};
+use crate::game::{GameServerState, ServerState};
+
+// commands only admins can use, checked ahead of the rest of the client logic
+const ADMIN_COMMANDS : [&str; 4] = ["/admin", "/rooms", "/close", "/broadcast"];
+
+/// Whether the line is one of the admin commands, whoever sent it
+pub fn is_admin_command(line : &str) -> bool {
+    line.split_whitespace().next().is_some_and(|command| ADMIN_COMMANDS.contains(&command))
+}
+
+/// Runs an admin command for the user at the given address, users that aren't
+/// admins are told the command doesn't exist, apart from '/admin' to log in
+/// when the server has an admin password
+pub fn admin_logic(addr : SocketAddr, line : &str, game_server_state : &mut GameServerState) {
+    let line = line.trim();
+    let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+    let argument = argument.trim();
+    let Some(user_state) = game_server_state.user_state.get_mut(&addr) else {
+        return;
+    };
+    if !user_state.is_admin {
+        if command == "/admin" && game_server_state.config.admin_password.is_some() {
+            user_state.state = ServerState::AdminLogin;
+        } else {
+            user_state.queue("Unknown command\r\n");
+        }
+        return;
+    }
+    let reply = match command {
+        "/admin" => "You're already logged in as admin\r\n".to_string(),
+        "/rooms" => room_listing(game_server_state),
+        "/close" => match argument.parse::<i32>() {
+            Ok(room_key) if close_room(room_key, game_server_state) => {
+                log_event(Level::Info, &format!("Room {} closed by admin {}", room_key, addr));
+                format!("Closed room {}\r\n", room_key)
+            },
+            Ok(room_key) => format!("There's no room {}\r\n", room_key),
+            Err(_) => "Usage: /close <room number>\r\n".to_string()
+        },
+        "/broadcast" if !argument.is_empty() => {
+            log_event(Level::Info, &format!("Broadcast by admin {}: {:?}", addr, argument));
+            broadcast(argument, game_server_state);
+            "Broadcast sent\r\n".to_string()
+        },
+        _ => "Usage: /broadcast <message>\r\n".to_string()
+    };
+    if let Some(user_state) = game_server_state.user_state.get_mut(&addr) {
+        user_state.queue(&reply);
+    }
+}
+
+/// Every room with its number of players, including rooms nobody has entered yet
+fn room_listing(game_server_state : &GameServerState) -> String {
+    let mut rooms : Vec<_> = game_server_state.game_rooms.iter().collect();
+    rooms.sort_by_key(|(room_key, _)| **room_key);
+    if rooms.is_empty() {
+        return "No rooms open\r\n".to_string();
+    }
+    rooms.iter()
+        .map(|(room_key, room)| {
+            format!("{}: {:>15} ({} players)\r\n", room_key, room.name, room.game.players().len())
+        })
+        .collect()
+}
+
+/// Removes a room, sending everyone in it or entering it back to the lobby
+/// returns false if there's no such room
+fn close_room(room_key : i32, game_server_state : &mut GameServerState) -> bool {
+    if game_server_state.game_rooms.remove(&room_key).is_none() {
+        return false;
+    }
+    let mut moved = Vec::new();
+    for user_state in game_server_state.user_state.values_mut() {
+        if user_state.game_room_key == Some(room_key) {
+            user_state.game_room_key = None;
+            user_state.player = None;
+            user_state.state = ServerState::LobbySelection;
+            user_state.queue("The room was closed by an admin\r\n");
+            moved.push(user_state.socket_addr);
+        } else if user_state.pending_room_key == Some(room_key) {
+            user_state.pending_room_key = None;
+            user_state.state = ServerState::LobbySelection;
+            moved.push(user_state.socket_addr);
+        }
+    }
+    for addr in moved {
+        game_server_state.mark_dirty(addr);
+    }
+    true
+}
+
+/// Sends an announcement to every user on the server, players get it with their
+/// room's chat and everyone else straight away
+fn broadcast(message : &str, game_server_state : &mut GameServerState) {
+    let announcement = format!("Server announcement: {}", message);
+    let mut addrs = Vec::new();
+    for user_state in game_server_state.user_state.values_mut() {
+        match user_state.player.as_mut() {
+            Some(player) => player.queue_chat(format!("* {}", announcement)),
+            None => user_state.queue(&format!("{}\r\n", announcement))
+        }
+        addrs.push(user_state.socket_addr);
+    }
+    for addr in addrs {
+        game_server_state.mark_dirty(addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::GameServerState;
+    use crate::game::tests::{create_room, login, new_addr, send};
+    use crate::game::ServerState;
+
+    fn output(game_server_state : &mut GameServerState, addr : std::net::SocketAddr) -> String {
+        String::from_utf8(std::mem::take(&mut game_server_state.user_state.get_mut(&addr).unwrap().outbound))
+            .unwrap()
+    }
+
+    fn admin_server() -> GameServerState {
+        let mut game_server_state = GameServerState::new();
+        game_server_state.config.admin_password = Some("letmein".to_string());
+        game_server_state
+    }
+
+    #[test]
+    fn admin_commands_need_a_login() {
+        let mut game_server_state = admin_server();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "/rooms");
+        assert_eq!(output(&mut game_server_state, alice), "Unknown command\r\n");
+
+        send(&mut game_server_state, alice, "/admin");
+        assert_eq!(game_server_state.user_state[&alice].state, ServerState::AdminLogin);
+        send(&mut game_server_state, alice, "wrong");
+        assert_eq!(output(&mut game_server_state, alice), "Wrong admin password\r\n");
+        assert_eq!(game_server_state.user_state[&alice].state, ServerState::LobbySelection);
+
+        send(&mut game_server_state, alice, "/admin");
+        send(&mut game_server_state, alice, "letmein");
+        assert!(game_server_state.user_state[&alice].is_admin);
+        assert_eq!(game_server_state.user_state[&alice].state, ServerState::LobbySelection);
+        output(&mut game_server_state, alice);
+        send(&mut game_server_state, alice, "/rooms");
+        assert_eq!(output(&mut game_server_state, alice), "No rooms open\r\n");
+    }
+
+    #[test]
+    fn no_admin_without_a_password() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "/admin");
+        assert_eq!(output(&mut game_server_state, alice), "Unknown command\r\n");
+        assert_eq!(game_server_state.user_state[&alice].state, ServerState::LobbySelection);
+    }
+
+    #[test]
+    fn admin_lists_and_closes_rooms() {
+        let mut game_server_state = admin_server();
+        let admin = new_addr();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.prompt_for(alice);
+        login(&mut game_server_state, admin, "root");
+        send(&mut game_server_state, admin, "/admin");
+        send(&mut game_server_state, admin, "letmein");
+        output(&mut game_server_state, admin);
+
+        send(&mut game_server_state, admin, "/rooms");
+        assert_eq!(output(&mut game_server_state, admin), "1:    alice's Room (1 players)\r\n");
+        send(&mut game_server_state, admin, "/close 2");
+        assert_eq!(output(&mut game_server_state, admin), "There's no room 2\r\n");
+        send(&mut game_server_state, admin, "/close 1");
+        assert_eq!(output(&mut game_server_state, admin), "Closed room 1\r\n");
+        assert!(game_server_state.game_rooms.is_empty());
+        let alice_state = &game_server_state.user_state[&alice];
+        assert_eq!((alice_state.state, alice_state.game_room_key), (ServerState::LobbySelection, None));
+        assert_eq!(output(&mut game_server_state, alice), "The room was closed by an admin\r\n");
+    }
+
+    #[test]
+    fn admin_broadcast_reaches_everyone() {
+        let mut game_server_state = admin_server();
+        let admin = new_addr();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.prompt_for(alice);
+        login(&mut game_server_state, bob, "bob");
+        login(&mut game_server_state, admin, "root");
+        send(&mut game_server_state, admin, "/admin");
+        send(&mut game_server_state, admin, "letmein");
+        output(&mut game_server_state, admin);
+
+        send(&mut game_server_state, admin, "/broadcast restarting in 5 minutes");
+        assert_eq!(output(&mut game_server_state, admin),
+                   "Server announcement: restarting in 5 minutes\r\nBroadcast sent\r\n");
+        assert_eq!(output(&mut game_server_state, bob), "Server announcement: restarting in 5 minutes\r\n");
+        assert!(game_server_state.prompt_for(alice).unwrap()
+            .contains("* Server announcement: restarting in 5 minutes"));
+    }
+}
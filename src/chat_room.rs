@@ -0,0 +1,138 @@
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::Instant;
+use crate::codenames::{ChatLimit, GameResult};
+use crate::game::{Game, User, get_user_state};
+use crate::prompts::Prompts;
+
+/// A room without a game, everything typed in it goes to everyone else there
+#[derive(Default)]
+pub struct ChatRoom {
+    players : HashSet<SocketAddr>,
+    // how fast each player can chat
+    chat_limits : HashMap<SocketAddr, ChatLimit>,
+    // players that have been told how the room works
+    prompted : HashSet<SocketAddr>
+}
+
+impl ChatRoom {
+    /// Sends the line to everyone in the room except the given player
+    fn send_others(&self, user_addr : SocketAddr, line : &str,
+                   user_state_map : &mut HashMap<SocketAddr, User>) {
+        for addr in self.players.iter().filter(|addr| **addr != user_addr) {
+            if let Some(user) = user_state_map.get_mut(addr) {
+                user.queue(line);
+            }
+        }
+    }
+}
+
+impl Game for ChatRoom {
+    fn prompt(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+              _prompts : &Prompts) -> Option<String> {
+        self.players.insert(user_addr);
+        if !self.prompted.insert(user_addr) {
+            return None;
+        }
+        let mut names : Vec<&str> = self.players.iter()
+            .filter_map(|addr| user_state_map.get(addr))
+            .map(|user| user.user_name.as_str())
+            .collect();
+        names.sort();
+        Some("This is a chat room, anything you type goes to everyone here\r\n".to_string() +
+             &format!("In the room: {}\r\n", names.join(", ")))
+    }
+
+    fn logic(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+             line : &Option<String>) -> Option<GameResult> {
+        self.players.insert(user_addr);
+        let chat_line = line.as_deref().map(str::trim).filter(|line| !line.is_empty())?;
+        let sender = get_user_state(user_state_map, user_addr);
+        let limit = self.chat_limits.entry(user_addr).or_insert_with(ChatLimit::new);
+        if !limit.take(Instant::now()) {
+            if !limit.warned {
+                limit.warned = true;
+                sender.queue("You're sending messages too fast\r\n");
+            }
+            return None;
+        }
+        let chat_line = format!("{}: {}\r\n", sender.user_name, chat_line);
+        self.send_others(user_addr, &chat_line, user_state_map);
+        None
+    }
+
+    fn disconnect(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>) {
+        self.players.remove(&user_addr);
+        self.chat_limits.remove(&user_addr);
+        self.prompted.remove(&user_addr);
+        if let Some(user_name) = user_state_map.get(&user_addr).map(|user| user.user_name.to_string()) {
+            self.send_others(user_addr, &format!("* {} has left\r\n", user_name), user_state_map);
+        }
+    }
+
+    fn rename(&mut self, old_name : &str, new_name : &str, user_state_map : &mut HashMap<SocketAddr, User>) {
+        for addr in &self.players {
+            if let Some(user) = user_state_map.get_mut(addr) {
+                user.queue(&format!("* {} is now known as {}\r\n", old_name, new_name));
+            }
+        }
+    }
+
+    fn players(&self) -> &HashSet<SocketAddr> {
+        &self.players
+    }
+
+    fn status(&self) -> &'static str {
+        "Chatting"
+    }
+
+    fn listing_tag(&self) -> Option<String> {
+        Some("chat".to_string())
+    }
+
+    fn has_ended(&self) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::GameServerState;
+    use crate::game::tests::{login, new_addr, send};
+
+    #[test]
+    fn chat_rooms_relay_lines_to_everyone_else() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "0 chat");
+        send(&mut game_server_state, alice, "");
+        game_server_state.handle_input(alice, None).unwrap();
+        assert!(game_server_state.prompt_for(alice).unwrap().starts_with("This is a chat room"));
+        login(&mut game_server_state, bob, "bob");
+        assert!(game_server_state.prompt_for(bob).unwrap().contains("alice's Room (chat) - 1/"));
+        send(&mut game_server_state, bob, "1");
+        game_server_state.handle_input(bob, None).unwrap();
+        assert!(game_server_state.prompt_for(bob).unwrap().ends_with("In the room: alice, bob\r\n"));
+
+        send(&mut game_server_state, bob, "hi alice");
+        assert_eq!(game_server_state.user_state[&alice].outbound, b"bob: hi alice\r\n");
+        assert!(game_server_state.user_state[&bob].outbound.is_empty());
+        // a chat room has no game to draw, so there's nothing more to prompt with
+        assert_eq!(game_server_state.prompt_for(alice), None);
+
+        game_server_state.user_state.get_mut(&alice).unwrap().outbound.clear();
+        game_server_state.remove_client(bob);
+        assert_eq!(game_server_state.user_state[&alice].outbound, b"* bob has left\r\n");
+    }
+}
@@ -1,12 +1,22 @@
+use std::any::Any;
+use std::cmp::min;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::net::{SocketAddr, TcpStream};
-use rand::thread_rng;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthChar;
 use rand::prelude::IteratorRandom;
-use crate::game::{GameRoom, User, ServerState, get_user_state};
+use crate::game::{Game, User, ServerState, get_user_state, resolve_command};
+use crate::prompts::Prompts;
 
 // State of the Codenames game room
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 enum CodenamesState {
     WaitingToStart,
     RedTurn,
@@ -14,8 +24,16 @@ enum CodenamesState {
     GameEnd
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-enum CodenamesTeam {
+// Part of a team's turn, the spymaster gives a clue and then the team guesses
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+enum TurnPhase {
+    #[default]
+    AwaitingClue,
+    Guessing
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CodenamesTeam {
     Red,
     Blue,
     Floating
@@ -24,18 +42,20 @@ enum CodenamesTeam {
 impl fmt::Display for CodenamesTeam {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            CodenamesTeam::Red => write!(f, "{}", "Red"),
-            CodenamesTeam::Blue => write!(f, "{}", "Blue"),
-            CodenamesTeam::Floating => write!(f, "{}", "Floating")
+            CodenamesTeam::Red => write!(f, "Red"),
+            CodenamesTeam::Blue => write!(f, "Blue"),
+            CodenamesTeam::Floating => write!(f, "Floating")
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-enum CodenamesRole {
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CodenamesRole {
     Spymaster,
     Teammate,
-    Spectator
+    Spectator,
+    Unassigned, // hasn't picked a role yet, sees what a teammate would
+    Observer // came in from the lobby to watch, sees what a teammate would and can't play
 }
 
 #[derive(Clone)]
@@ -43,38 +63,112 @@ pub struct CodenamesPlayer {
     team : CodenamesTeam,
     role : CodenamesRole,
     chat_queue : VecDeque<String>,
-    state_prompted : Option<CodenamesState> // last state prompted
+    state_prompted : Option<CodenamesState>, // last state prompted
+    chat_limit : ChatLimit
 }
 
-impl Default for CodenamesPlayer {
-    fn default() -> Self {
-        Self {
-            team: CodenamesTeam::Floating,
-            role: CodenamesRole::Spectator,
+impl CodenamesPlayer {
+    pub fn new(team : CodenamesTeam, role : CodenamesRole) -> CodenamesPlayer {
+        CodenamesPlayer {
+            team,
+            role,
             chat_queue: VecDeque::new(),
-            state_prompted: None
+            state_prompted: None,
+            chat_limit: ChatLimit::new()
+        }
+    }
+
+    /// Queues a message for the player's next prompt, the oldest
+    /// messages are dropped if too many pile up
+    pub fn queue_chat(&mut self, chat_line : String) {
+        self.chat_queue.push_back(chat_line);
+        while self.chat_queue.len() > MAX_CHAT_QUEUE {
+            self.chat_queue.pop_front();
         }
     }
+
+    /// Shows the room again on the next prompt, even if nothing changed
+    pub fn reprompt(&mut self) {
+        self.state_prompted = None;
+    }
+
+    /// Whether the player came in to watch instead of play
+    pub fn is_observer(&self) -> bool {
+        self.role == CodenamesRole::Observer
+    }
+}
+
+impl Default for CodenamesPlayer {
+    fn default() -> Self {
+        CodenamesPlayer::new(CodenamesTeam::Floating, CodenamesRole::Unassigned)
+    }
 }
 
 impl Default for &CodenamesPlayer {
     fn default() -> Self {
         static PLAYER: CodenamesPlayer = CodenamesPlayer {
             team: CodenamesTeam::Floating,
-            role: CodenamesRole::Spectator,
+            role: CodenamesRole::Unassigned,
             chat_queue: VecDeque::new(),
-            state_prompted: None
+            state_prompted: None,
+            chat_limit: ChatLimit::new()
         };
         &PLAYER
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// Token bucket limiting how fast a player can chat, a message takes a token
+/// and tokens come back one every CHAT_REFILL up to CHAT_BURST
+#[derive(Clone)]
+pub(crate) struct ChatLimit {
+    tokens : u32,
+    // when tokens were last added back, None before the first message
+    refilled : Option<Instant>,
+    // whether the player has been told they're going too fast since their last message got through
+    pub(crate) warned : bool
+}
+
+impl ChatLimit {
+    pub(crate) const fn new() -> ChatLimit {
+        ChatLimit { tokens: CHAT_BURST, refilled: None, warned: false }
+    }
+
+    /// Takes a token for a message, returns false if there's none left
+    pub(crate) fn take(&mut self, now : Instant) -> bool {
+        let refilled = *self.refilled.get_or_insert(now);
+        let earned = (now.duration_since(refilled).as_millis() / CHAT_REFILL.as_millis()) as u32;
+        self.tokens = min(CHAT_BURST, self.tokens + earned);
+        // a full bucket doesn't bank time towards more tokens
+        self.refilled = Some(if self.tokens == CHAT_BURST { now } else { refilled + CHAT_REFILL * earned });
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        self.warned = false;
+        true
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 enum CodenamesCardType {
     RedAgent,
     BlueAgent,
     Assassin,
-    Bystander
+    Bystander,
+    // scores for whichever team finds it, only dealt in the double agent variant
+    DoubleAgent
+}
+
+impl CodenamesCardType {
+    fn name(&self) -> &'static str {
+        match self {
+            CodenamesCardType::RedAgent => "Red agent",
+            CodenamesCardType::BlueAgent => "Blue agent",
+            CodenamesCardType::Assassin => "Assassin",
+            CodenamesCardType::Bystander => "Bystander",
+            CodenamesCardType::DoubleAgent => "Double agent"
+        }
+    }
 }
 
 impl fmt::Display for CodenamesCardType {
@@ -83,30 +177,448 @@ impl fmt::Display for CodenamesCardType {
             CodenamesCardType::RedAgent => write!(f, "R"),
             CodenamesCardType::BlueAgent => write!(f, "B"),
             CodenamesCardType::Assassin => write!(f, "A"),
-            CodenamesCardType::Bystander => write!(f, " ")
+            CodenamesCardType::Bystander => write!(f, " "),
+            CodenamesCardType::DoubleAgent => write!(f, "D")
         }
     }
 }
 
+/// The card mix a room is played with, picked when the room is created
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum RoomVariant {
+    #[default]
+    Standard,
+    // two of the bystanders are double agents that count for whichever team finds them
+    DoubleAgent
+}
+
+impl RoomVariant {
+    /// The variant for the name typed when creating a room
+    pub fn parse(name : &str) -> Option<RoomVariant> {
+        match name {
+            "standard" => Some(RoomVariant::Standard),
+            "double" => Some(RoomVariant::DoubleAgent),
+            _ => None
+        }
+    }
+
+    /// How many of each card type are dealt onto a board with the given number of cards,
+    /// the room's own mix if it picked one, otherwise red gets the same share as the
+    /// 9 of 25 in a standard game, blue one less and there's one assassin
+    /// double agents take the place of some of the bystanders
+    fn card_counts(&self, cards : usize, card_mix : Option<CardMix>) -> Result<[(CodenamesCardType, usize); 5], String> {
+        let double_agents = match self {
+            RoomVariant::Standard => 0,
+            RoomVariant::DoubleAgent => 2
+        };
+        let card_mix = card_mix.unwrap_or_else(|| {
+            let red_agents = (cards * 9 + 12) / 25;
+            CardMix { red_agents, blue_agents: red_agents - 1, bystanders: cards - 2 * red_agents, assassins: 1 }
+        });
+        if card_mix.bystanders < double_agents {
+            return Err(format!("The {} variant needs at least {} bystanders", self, double_agents));
+        }
+        Ok([(CodenamesCardType::BlueAgent, card_mix.blue_agents),
+            (CodenamesCardType::RedAgent, card_mix.red_agents),
+            (CodenamesCardType::Bystander, card_mix.bystanders - double_agents),
+            (CodenamesCardType::DoubleAgent, double_agents),
+            (CodenamesCardType::Assassin, card_mix.assassins)])
+    }
+}
+
+impl fmt::Display for RoomVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RoomVariant::Standard => write!(f, "standard"),
+            RoomVariant::DoubleAgent => write!(f, "double agent")
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 struct CodenamesCard {
     word : String,
     card_type : CodenamesCardType,
     flipped : bool
 }
 
+/// The outcome of a finished game, handed to the server's end-of-game hook
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameResult {
+    // Floating if the game ended in a draw
+    pub winner : CodenamesTeam,
+    // neither team had a spymaster left to carry on
+    pub drawn : bool,
+    pub red_score : i32,
+    pub blue_score : i32,
+    pub assassin_loss : bool,
+    // user name, team and role of everyone in the room when the game ended
+    pub players : Vec<(String, CodenamesTeam, CodenamesRole)>
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 struct CodenamesClue {
-    cards_to_match : i32,
+    // None for an unlimited clue
+    cards_to_match : Option<i32>,
     clue: String
 }
 
+impl CodenamesClue {
+    /// The clue as everyone sees it, like "fruit, 2"
+    fn describe(&self) -> String {
+        format!("{}, {}", self.clue, self.cards_to_match.map_or("unlimited".to_string(), |n| n.to_string()))
+    }
+
+    /// Guesses allowed for this clue, one more than the number of cards it matches
+    /// None when guessing is unlimited, which is the case for 0 and unlimited clues
+    fn max_guesses(&self) -> Option<i32> {
+        self.cards_to_match.filter(|n| *n > 0).map(|n| n + 1)
+    }
+
+    /// Whether the team has to guess at least once before ending its turn
+    fn requires_guess(&self) -> bool {
+        self.max_guesses().is_some()
+    }
+}
+
+/// Number of single character insertions, deletions and substitutions to turn one word into the other
+fn edit_distance(a : &str, b : &str) -> usize {
+    let b : Vec<char> = b.chars().collect();
+    let mut row : Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Whether the clue looks like a form of the word, one being the start or end of the
+/// other or the two being fewer than max_distance edits apart, ignoring case
+fn clue_resembles(clue : &str, word : &str, max_distance : usize) -> bool {
+    let (clue, word) = (clue.trim().to_lowercase(), word.to_lowercase());
+    let (shorter, longer) = if clue.chars().count() <= word.chars().count() { (&clue, &word) } else { (&word, &clue) };
+    (shorter.chars().count() >= MIN_ROOT_LEN && (longer.starts_with(shorter.as_str()) || longer.ends_with(shorter.as_str())))
+        || edit_distance(&clue, &word) < max_distance
+}
+
+/// Parses the number half of a clue, "unlimited" or a non-negative number
+fn parse_clue_number(number : &str) -> Option<Option<i32>> {
+    match number.trim() {
+        "unlimited" => Some(None),
+        n => n.parse::<i32>().ok().filter(|n| *n >= 0).map(Some)
+    }
+}
+
 const LONGEST_CODENAMES_WORD: usize = 14;
-const BOARD_DIM : usize = 5;
+const DEFAULT_BOARD_DIM : usize = 5;
 const FLIPPED_LEN : usize = 2;
 const MARKER_LEN : usize = 2;
-const CELL_WIDTH : usize = LONGEST_CODENAMES_WORD + FLIPPED_LEN + MARKER_LEN;
-// add 1 to cell width for each | separator
-// add 1 to the end for the last |
-const LINE_WIDTH : usize = (CELL_WIDTH + 1) * BOARD_DIM + 1;
+// width of the row numbers in front of a board with coordinates shown
+const ROW_LABEL_LEN : usize = 2;
+// how long a seat is held for a player who dropped mid game
+pub(crate) const RECONNECT_GRACE : Duration = Duration::from_secs(60);
+// messages a player can send in a burst, and how often they get another one
+const CHAT_BURST : u32 = 5;
+const CHAT_REFILL : Duration = Duration::from_secs(1);
+// most messages waiting in a player's chat queue before the oldest are dropped
+const MAX_CHAT_QUEUE : usize = 200;
+// number of chat messages a room keeps to show players that join later
+const CHAT_HISTORY_LEN : usize = 50;
+// how long a vote to kick a player or restart the game counts towards it
+const KICK_VOTE_WINDOW : Duration = Duration::from_secs(120);
+// one word commands before the game starts, these can be shortened
+const WAITING_COMMANDS : [&str; 11] = ["start", "balance", "teammate", "spymaster", "spectator",
+                                       "red", "blue", "show", "log", "teams", "who"];
+// limits for the turn timer setting, in seconds
+const MIN_TURN_SECS : u64 = 10;
+const MAX_TURN_SECS : u64 = 600;
+// limits for the word column width of a room's board cells
+const MIN_WORD_WIDTH : usize = 6;
+const MAX_WORD_WIDTH : usize = 30;
+// limits for the number of rows and columns of a room's board
+const MIN_BOARD_DIM : usize = 4;
+const MAX_BOARD_DIM : usize = 6;
+// clues closer than this many edits to a board word get a warning, and the limits for the setting
+const DEFAULT_CLUE_DISTANCE : usize = 2;
+const MAX_CLUE_DISTANCE : usize = 4;
+// shortest word that counts as the root of a longer one, so "a" or "in" don't match everything
+const MIN_ROOT_LEN : usize = 3;
+
+type Board = Vec<Vec<CodenamesCard>>;
+
+/// Word lists boards can be dealt from, by language code
+pub type WordLists = HashMap<String, Vec<String>>;
+
+// language of the embedded word list, rooms use it unless they pick another
+pub const DEFAULT_LANGUAGE : &str = "en";
+
+/// Centers the word in the given number of terminal columns, cutting it short if it
+/// doesn't fit, counting the columns each character takes up so wide characters
+/// like CJK or emoji don't throw off the alignment the way padding by chars would
+fn center_in_columns(word : &str, columns : usize) -> String {
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in word.chars() {
+        let width = c.width().unwrap_or(0);
+        if used + width > columns {
+            break;
+        }
+        fitted.push(c);
+        used += width;
+    }
+    // same split as the ^ format, any odd column goes on the right
+    let left = (columns - used) / 2;
+    format!("{:left$}{}{:right$}", "", fitted, "", left=left, right=columns - used - left)
+}
+
+/// Width of a rendered board line for the given word column width and number of columns
+fn line_width(word_width : usize, board_dim : usize) -> usize {
+    let cell_width = word_width + FLIPPED_LEN + MARKER_LEN;
+    // add 1 to cell width for each | separator
+    // add 1 to the end for the last |
+    (cell_width + 1) * board_dim + 1
+}
+
+/// Letter a board column is labelled with, A for the first
+fn column_letter(column : usize) -> char {
+    char::from(b'A' + column as u8)
+}
+
+/// The flipped mark and the card type marker shown for a card to the given role
+fn card_marks(card : &CodenamesCard, role : CodenamesRole) -> (&'static str, String) {
+    let flipped = if card.flipped { "X" } else { " " };
+    // Spymasters and spectators always see everything, teammates and players
+    // without a role only see a card's type once it's flipped over
+    let show_type = card.flipped || match role {
+        CodenamesRole::Spymaster | CodenamesRole::Spectator => true,
+        CodenamesRole::Teammate | CodenamesRole::Unassigned | CodenamesRole::Observer => false
+    };
+    // the marker goes through a String so the padding applies to it
+    let marker = if show_type { card.card_type.to_string() } else { String::new() };
+    (flipped, marker)
+}
+
+/// Options for a room, can be changed with 'set' before the game starts
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CodenamesSettings {
+    // width of the word column in each board cell, longer words are truncated
+    pub word_width : usize,
+    // training mode enables hints for new players
+    pub training : bool,
+    // guesses have to be confirmed with !!yes before the card is flipped
+    pub confirm_guesses : bool,
+    // who hears the guessing team's chat during a turn
+    pub chat : ChatVisibility,
+    // how long a team has to act before its turn is passed, None for no limit
+    pub turn_time : Option<Duration>,
+    // number of rows and columns of the board
+    pub board_dim : usize,
+    // code of the word list the board is dealt from
+    pub language : String,
+    // cards of each type the board is dealt, None for the usual mix for its size
+    #[serde(default)]
+    pub card_mix : Option<CardMix>,
+    // clues fewer edits than this from an uncovered board word warn the spymaster, None to not check
+    #[serde(default = "default_clue_distance")]
+    pub clue_distance : Option<usize>,
+    // clue words can only be given once a game, a repeat is turned down
+    #[serde(default)]
+    pub lockout : bool,
+    // the board is drawn with column letters and row numbers, so cards can be guessed like !B3
+    #[serde(default)]
+    pub coords : bool
+}
+
+fn default_clue_distance() -> Option<usize> {
+    Some(DEFAULT_CLUE_DISTANCE)
+}
+
+/// Who chat from the guessing team's teammates reaches during a turn
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ChatVisibility {
+    Everyone,
+    // both spymasters are left out so they can't leak anything by reacting
+    HideFromSpymasters
+}
+
+impl fmt::Display for ChatVisibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChatVisibility::Everyone => write!(f, "all"),
+            ChatVisibility::HideFromSpymasters => write!(f, "guessers")
+        }
+    }
+}
+
+/// Numbers of each card type a room picked for its boards instead of the usual ones
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CardMix {
+    pub red_agents : usize,
+    pub blue_agents : usize,
+    pub bystanders : usize,
+    pub assassins : usize
+}
+
+impl CardMix {
+    /// Reads a mix written as red/blue/bystanders/assassins, like 9/8/6/2
+    fn parse(value : &str) -> Option<CardMix> {
+        match value.split('/').map(|n| n.parse::<usize>().ok()).collect::<Option<Vec<usize>>>()?[..] {
+            [red_agents, blue_agents, bystanders, assassins] =>
+                Some(CardMix { red_agents, blue_agents, bystanders, assassins }),
+            _ => None
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.red_agents + self.blue_agents + self.bystanders + self.assassins
+    }
+}
+
+impl fmt::Display for CardMix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}/{}", self.red_agents, self.blue_agents, self.bystanders, self.assassins)
+    }
+}
+
+impl Default for CodenamesSettings {
+    fn default() -> Self {
+        Self {
+            word_width: LONGEST_CODENAMES_WORD,
+            training: false,
+            confirm_guesses: false,
+            chat: ChatVisibility::Everyone,
+            turn_time: None,
+            board_dim: DEFAULT_BOARD_DIM,
+            language: DEFAULT_LANGUAGE.to_string(),
+            card_mix: None,
+            clue_distance: default_clue_distance(),
+            lockout: false,
+            coords: false
+        }
+    }
+}
+
+impl CodenamesSettings {
+    /// Changes a setting by name, returns a description of the
+    /// change or why it couldn't be made
+    fn apply(&mut self, option : &str, value : &str) -> Result<String, String> {
+        match option {
+            "width" => {
+                match value.parse::<usize>() {
+                    Ok(width) if (MIN_WORD_WIDTH..=MAX_WORD_WIDTH).contains(&width) => {
+                        self.word_width = width;
+                        Ok(format!("width to {}", width))
+                    },
+                    _ => Err(format!("width must be a number from {} to {}", MIN_WORD_WIDTH, MAX_WORD_WIDTH))
+                }
+            },
+            "training" => {
+                self.training = parse_on_off(option, value)?;
+                Ok(format!("training to {}", value))
+            },
+            "confirm" => {
+                self.confirm_guesses = parse_on_off(option, value)?;
+                Ok(format!("confirm to {}", value))
+            },
+            "chat" => {
+                self.chat = match value {
+                    "all" => ChatVisibility::Everyone,
+                    "guessers" => ChatVisibility::HideFromSpymasters,
+                    _ => return Err("chat must be all or guessers".to_string())
+                };
+                Ok(format!("chat to {}", value))
+            },
+            "timer" => {
+                self.turn_time = match value {
+                    "off" => None,
+                    _ => match value.parse::<u64>() {
+                        Ok(secs) if (MIN_TURN_SECS..=MAX_TURN_SECS).contains(&secs) => Some(Duration::from_secs(secs)),
+                        _ => return Err(format!("timer must be off or a number of seconds from {} to {}",
+                                                MIN_TURN_SECS, MAX_TURN_SECS))
+                    }
+                };
+                Ok(format!("timer to {}", value))
+            },
+            "size" => {
+                match value.parse::<usize>() {
+                    Ok(dim) if (MIN_BOARD_DIM..=MAX_BOARD_DIM).contains(&dim) => {
+                        if let Some(card_mix) = self.card_mix.filter(|mix| mix.total() != dim * dim) {
+                            return Err(format!("cards {} don't fill a {}x{} board, set cards default first",
+                                               card_mix, dim, dim));
+                        }
+                        self.board_dim = dim;
+                        Ok(format!("size to {}x{}", dim, dim))
+                    },
+                    _ => Err(format!("size must be a number from {} to {}", MIN_BOARD_DIM, MAX_BOARD_DIM))
+                }
+            },
+            "cards" => {
+                let cards = self.board_dim * self.board_dim;
+                self.card_mix = match CardMix::parse(value) {
+                    _ if value == "default" => None,
+                    Some(mix) if mix.total() == cards && mix.red_agents > 0 && mix.blue_agents > 0 => Some(mix),
+                    _ => return Err(format!("cards must be default or red/blue/bystanders/assassins adding up to {}, \
+                        with at least one agent for each team", cards))
+                };
+                Ok(format!("cards to {}", value))
+            },
+            "similar" => {
+                self.clue_distance = match value {
+                    "off" => None,
+                    _ => match value.parse::<usize>() {
+                        Ok(distance) if (1..=MAX_CLUE_DISTANCE).contains(&distance) => Some(distance),
+                        _ => return Err(format!("similar must be off or a number of edits from 1 to {}",
+                                                MAX_CLUE_DISTANCE))
+                    }
+                };
+                Ok(format!("similar to {}", value))
+            },
+            "lockout" => {
+                self.lockout = parse_on_off(option, value)?;
+                Ok(format!("lockout to {}", value))
+            },
+            "coords" => {
+                self.coords = parse_on_off(option, value)?;
+                Ok(format!("coords to {}", value))
+            },
+            "lang" => {
+                // checked against the loaded word lists when the board is dealt again
+                self.language = value.to_lowercase();
+                Ok(format!("lang to {}", self.language))
+            },
+            _ => Err(format!("{} is not a room setting", option))
+        }
+    }
+}
+
+impl fmt::Display for CodenamesSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "width {}, training {}, confirm {}, chat {}, timer {}, size {}x{}, lang {}, cards {}, similar {}, lockout {}, coords {}",
+               self.word_width, on_off(self.training), on_off(self.confirm_guesses), self.chat,
+               self.turn_time.map_or("off".to_string(), |t| format!("{}s", t.as_secs())),
+               self.board_dim, self.board_dim, self.language,
+               self.card_mix.map_or("default".to_string(), |mix| mix.to_string()),
+               self.clue_distance.map_or("off".to_string(), |distance| distance.to_string()),
+               on_off(self.lockout), on_off(self.coords))
+    }
+}
+
+fn parse_on_off(option : &str, value : &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("{} must be on or off", option))
+    }
+}
+
+fn on_off(value : bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
 
 pub struct CodenamesRoom {
     state : CodenamesState,
@@ -116,41 +628,134 @@ pub struct CodenamesRoom {
     guesses : i32,
     assassin_found_by : Option<CodenamesTeam>,
     clue: Option<CodenamesClue>,
-    board : [[CodenamesCard; BOARD_DIM]; BOARD_DIM]
+    // every clue given this game and the team it was for, oldest first
+    clues : Vec<(CodenamesTeam, CodenamesClue)>,
+    // whether the team whose turn it is is waiting for a clue or guessing
+    phase : TurnPhase,
+    // clues, guesses and turn changes in the order they happened
+    history : Vec<String>,
+    // user name, word and revealed card type of every guess that flipped a card
+    guess_log : Vec<(String, String, CodenamesCardType)>,
+    // guess waiting for confirmation and who made it, in confirm mode
+    pending_guess : Option<(SocketAddr, String)>,
+    // set when the game ended because neither team had a spymaster
+    drawn : bool,
+    // team, role and disconnect time of players who dropped mid game, by user name
+    reserved : HashMap<String, (CodenamesTeam, CodenamesRole, Instant)>,
+    // when the current team's turn is passed if they haven't acted, with the timer on
+    turn_deadline : Option<Instant>,
+    // votes to kick a player, by the player's address then the voter's with when they voted
+    kick_votes : HashMap<SocketAddr, HashMap<SocketAddr, Instant>>,
+    // votes to throw away the game in progress and deal a new board, by voter with when they voted
+    restart_votes : HashMap<SocketAddr, Instant>,
+    // the latest chat, oldest first, and whether each message was kept from the spymasters
+    chat_history : VecDeque<(String, bool)>,
+    // restored from a save and nobody has come back yet, seats are held
+    // and the turn timer is stopped until someone does
+    paused : bool,
+    settings : CodenamesSettings,
+    variant : RoomVariant,
+    board : Board,
+    // the server's word lists, new boards are dealt from the one for the room's language
+    word_lists : Arc<WordLists>
+}
+
+/// What's kept of a game in the save file, players are kept by user name
+/// since their connections don't survive a restart
+#[derive(Serialize, Deserialize)]
+pub struct SavedGame {
+    state : CodenamesState,
+    red_score : i32,
+    blue_score : i32,
+    guesses : i32,
+    assassin_found_by : Option<CodenamesTeam>,
+    clue : Option<CodenamesClue>,
+    #[serde(default)]
+    clues : Vec<(CodenamesTeam, CodenamesClue)>,
+    #[serde(default)]
+    phase : TurnPhase,
+    history : Vec<String>,
+    guess_log : Vec<(String, String, CodenamesCardType)>,
+    drawn : bool,
+    chat_history : VecDeque<(String, bool)>,
+    settings : CodenamesSettings,
+    variant : RoomVariant,
+    board : Board,
+    // user name, team and role of everyone playing, connected or not
+    seats : Vec<(String, CodenamesTeam, CodenamesRole)>
 }
 
-fn gen_board() -> [[CodenamesCard; 5]; 5] {
-    let word_list = String::from_utf8_lossy(
-        include_bytes!("./wordlist-eng.txt"))
-        .to_string();
-    // Get a complete list of all the words used for the game
-    let mut words : Vec<&str> = word_list.split('\n')
-        .map(|w| w.trim())
+/// Loads the words used for boards from the given file, or the embedded
+/// english list if there's no file, making sure there are enough for a board
+pub fn load_word_list(path : Option<&Path>) -> io::Result<Vec<String>> {
+    let word_list = match path {
+        Some(path) => fs::read(path)
+            .map_err(|e| io::Error::new(e.kind(),
+                                        format!("Couldn't read word list {}: {}", path.display(), e)))?,
+        None => include_bytes!("./wordlist-eng.txt").to_vec()
+    };
+    // boards rely on every word being unique ignoring case, so drop any repeats
+    let mut seen = HashSet::new();
+    let words : Vec<String> = String::from_utf8_lossy(&word_list)
+        // editors on some platforms start UTF-8 files with a byte order mark
+        .trim_start_matches('\u{feff}')
+        .split('\n')
+        .map(|w| w.trim().to_string())
+        .filter(|w| !w.is_empty() && seen.insert(w.to_lowercase()))
         .collect();
+    let unique_words = words.len();
+    if unique_words < DEFAULT_BOARD_DIM * DEFAULT_BOARD_DIM {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("Word list {} has {} unique words, at least {} are needed",
+                                          path.map_or("(embedded)".to_string(), |p| p.display().to_string()),
+                                          unique_words, DEFAULT_BOARD_DIM * DEFAULT_BOARD_DIM)));
+    }
+    Ok(words)
+}
+
+/// Generates a random board_dim x board_dim board from the word list with the
+/// variant's card mix, every word on the board is unique
+fn gen_board(word_list : &[String], variant : RoomVariant, board_dim : usize,
+             card_mix : Option<CardMix>) -> Result<Board, String> {
+    gen_board_with_rng(word_list, variant, board_dim, card_mix, &mut thread_rng())
+}
+
+/// Generates a board using the given random number generator,
+/// so the same seed always produces the same board
+fn gen_board_with_rng(word_list : &[String], variant : RoomVariant, board_dim : usize,
+                      card_mix : Option<CardMix>, rng : &mut impl Rng) -> Result<Board, String> {
+    // Get a complete list of all the distinct words used for the game,
+    // guesses ignore case so words that only differ in case count once
+    let mut seen = HashSet::new();
+    let mut words : Vec<&str> = word_list.iter()
+        .map(|w| w.as_str())
+        .filter(|w| seen.insert(w.to_lowercase()))
+        .collect();
+    let cards = board_dim * board_dim;
+    if words.len() < cards {
+        return Err(format!("Only {} distinct words are available, a board needs {}",
+                           words.len(), cards));
+    }
     // Get a list of all the card types used to pick from
-    // 8 blue agent, 9 red agent, 7 bystanders, 1 assassin
-    let mut card_types : Vec<&CodenamesCardType> =
-        [CodenamesCardType::BlueAgent].iter()
-            .cycle().take(8).chain(
-        [CodenamesCardType::RedAgent].iter()
-            .cycle().take(9)).chain(
-        [CodenamesCardType::Bystander].iter()
-            .cycle().take(7)).chain(
-        [CodenamesCardType::Assassin].iter())
-            .collect();
-    if card_types.len() != 25 {
+    // 8 blue agent, 9 red agent, 7 bystanders (some may be double agents), 1 assassin
+    // on a standard board, other sizes get the same proportions unless the room picked its own
+    let counts = variant.card_counts(cards, card_mix)?;
+    let mut card_types : Vec<&CodenamesCardType> = counts.iter()
+        .flat_map(|(card_type, count)| std::iter::repeat_n(card_type, *count))
+        .collect();
+    if card_types.len() != cards {
         panic!("Word length doesn't equal the card type length");
     }
-    [[(); 5]; 5].map(| x | x.map(|_| {
+    Ok((0..board_dim).map(|_| (0..board_dim).map(|_| {
         // TODO: should this be a function?
         let (i, &word) = words.iter()
             .enumerate()
-            .choose(&mut thread_rng())
+            .choose(rng)
             .unwrap();
         words.remove(i);
         let (i, &card_type) = card_types.iter()
             .enumerate()
-            .choose(&mut thread_rng())
+            .choose(rng)
             .unwrap();
         card_types.remove(i);
         CodenamesCard {
@@ -158,105 +763,459 @@ fn gen_board() -> [[CodenamesCard; 5]; 5] {
             card_type: *card_type,
             flipped: false
         }
-    }))
-}
-
-/// Initializes the board if necessary for the Codenames game
-/// returns the relevant Codenames room
-fn initialize_user_board<'a>(user_state : &mut User, game_rooms: &'a mut HashMap<i32, GameRoom>) -> Result<&'a mut CodenamesRoom, ()>{
-    // create room if not already there
-    // put the user and the room in the beginning states
-    user_state.player.get_or_insert(CodenamesPlayer {
-        team: CodenamesTeam::Floating,
-        role: CodenamesRole::Spectator,
-        chat_queue: VecDeque::new(),
-        state_prompted: None
-    });
-    match user_state.game_room_key {
-        Some(room) => {
-            match game_rooms.get_mut(&room) {
-                // TODO: lots of unwraps here
-                Some(room) => {
-                    if room.impl_room.is_none() {
-                        let mut players = HashSet::new();
-                        players.insert(user_state.socket_addr);
-                        room.impl_room = Some(CodenamesRoom {
-                            state: CodenamesState::WaitingToStart,
-                            players,
-                            blue_score: 0,
-                            red_score: 0,
-                            clue: None,
-                            guesses: 0,
-                            assassin_found_by: None,
-                            board: gen_board()
-                        });
-                    } else {
-                        // Insert key into the players list to
-                        // ensure that it's there
-                        room.impl_room
-                            .as_mut()
-                            .unwrap()
-                            .players
-                            .insert(user_state.socket_addr);
-                    }
-                    Ok(room.impl_room.as_mut().unwrap())
-                },
-                None => {
-                    // there should always be an existing room when
-                    // running this function
-                    user_state.state = ServerState::FatalError;
-                    Err(())
-                }
+    }).collect()).collect())
+}
+
+/// Loads the word lists rooms can pick from, english from the given file or the
+/// embedded list, and each '<code>.txt' file in the directory as the list for that
+/// language code, a file for english in the directory replaces the other one
+pub fn load_word_lists(english_path : Option<&Path>, dir : Option<&Path>) -> io::Result<WordLists> {
+    let mut word_lists = HashMap::new();
+    word_lists.insert(DEFAULT_LANGUAGE.to_string(), load_word_list(english_path)?);
+    let Some(dir) = dir else {
+        return Ok(word_lists);
+    };
+    let entries = fs::read_dir(dir)
+        .map_err(|e| io::Error::new(e.kind(),
+                                    format!("Couldn't read word list directory {}: {}", dir.display(), e)))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "txt") {
+            if let Some(code) = path.file_stem().and_then(|stem| stem.to_str()) {
+                word_lists.insert(code.to_lowercase(), load_word_list(Some(&path))?);
             }
-        },
+        }
+    }
+    Ok(word_lists)
+}
+
+/// Deals a new board if the size or language settings changed from the previous ones,
+/// the previous settings are put back if the language has no word list or there
+/// aren't enough words for the new board
+fn redeal_board(room : &mut CodenamesRoom, previous : CodenamesSettings,
+                word_lists : &WordLists) -> Result<(), String> {
+    if room.settings.board_dim == previous.board_dim && room.settings.language == previous.language
+        && room.settings.card_mix == previous.card_mix {
+        return Ok(());
+    }
+    let dealt = match word_lists.get(&room.settings.language) {
+        Some(words) => gen_board(words, room.variant, room.settings.board_dim, room.settings.card_mix),
         None => {
-            // there should always be an existing room when
-            // running this function
-            user_state.state = ServerState::FatalError;
-            Err(())
+            let mut languages : Vec<&str> = word_lists.keys().map(String::as_str).collect();
+            languages.sort();
+            Err(format!("lang must be one of {}", languages.join(", ")))
+        }
+    };
+    match dealt {
+        Ok(board) => {
+            room.board = board;
+            Ok(())
+        },
+        Err(e) => {
+            room.settings = previous;
+            Err(e)
         }
     }
 }
 
 impl CodenamesRoom {
+    fn new(players : HashSet<SocketAddr>, board : Board, variant : RoomVariant,
+           word_lists : Arc<WordLists>) -> CodenamesRoom {
+        CodenamesRoom {
+            state: CodenamesState::WaitingToStart,
+            players,
+            blue_score: 0,
+            red_score: 0,
+            clue: None,
+            clues: Vec::new(),
+            phase: TurnPhase::AwaitingClue,
+            guesses: 0,
+            assassin_found_by: None,
+            history: Vec::new(),
+            guess_log: Vec::new(),
+            pending_guess: None,
+            drawn: false,
+            reserved: HashMap::new(),
+            turn_deadline: None,
+            kick_votes: HashMap::new(),
+            restart_votes: HashMap::new(),
+            chat_history: VecDeque::new(),
+            paused: false,
+            settings: CodenamesSettings::default(),
+            variant,
+            board,
+            word_lists
+        }
+    }
+
+    /// Makes sure a user polled in the room is one of its players, catching them up if they just arrived
+    fn join(&mut self, user_state : &mut User) {
+        let player = user_state.player.get_or_insert_with(CodenamesPlayer::default);
+        if self.players.insert(user_state.socket_addr) {
+            // catch the new player up on the conversation so far
+            let hide = player.role == CodenamesRole::Spymaster;
+            for (chat_line, _) in self.chat_history.iter().filter(|(_, hidden)| !(hide && *hidden)) {
+                player.queue_chat(chat_line.to_string());
+            }
+        }
+    }
+
+    /// A new room waiting to start, with a board dealt from the default word list
+    pub fn deal(variant : RoomVariant, word_lists : &Arc<WordLists>) -> CodenamesRoom {
+        // the default list is checked for enough words when it's loaded
+        let board = gen_board(&word_lists[DEFAULT_LANGUAGE], variant, DEFAULT_BOARD_DIM, None)
+            .expect("The default word list must have enough words for a board");
+        CodenamesRoom::new(HashSet::new(), board, variant, Arc::clone(word_lists))
+    }
+
+    /// The game as it's written to the save file
+    pub fn snapshot(&self, user_state_map : &HashMap<SocketAddr, User>) -> SavedGame {
+        let mut seats : Vec<(String, CodenamesTeam, CodenamesRole)> = self.players.iter()
+            .filter_map(|addr| user_state_map.get(addr))
+            .map(|user| {
+                let player = user.player.as_ref().unwrap_or_default();
+                (user.user_name.to_string(), player.team, player.role)
+            })
+            .collect();
+        seats.extend(self.reserved.iter().map(|(user_name, (team, role, _))| (user_name.to_string(), *team, *role)));
+        SavedGame {
+            state: self.state,
+            red_score: self.red_score,
+            blue_score: self.blue_score,
+            guesses: self.guesses,
+            assassin_found_by: self.assassin_found_by,
+            clue: self.clue.clone(),
+            clues: self.clues.clone(),
+            phase: self.phase,
+            history: self.history.clone(),
+            guess_log: self.guess_log.clone(),
+            drawn: self.drawn,
+            chat_history: self.chat_history.clone(),
+            settings: self.settings.clone(),
+            variant: self.variant,
+            board: self.board.clone(),
+            seats
+        }
+    }
+
+    /// Rebuilds a game from the save file, nobody is connected so a game in progress holds
+    /// everyone's seat and waits for them to come back by user name
+    pub fn restore(saved : SavedGame, word_lists : &Arc<WordLists>) -> CodenamesRoom {
+        let mut room = CodenamesRoom::new(HashSet::new(), saved.board, saved.variant, Arc::clone(word_lists));
+        room.state = saved.state;
+        room.red_score = saved.red_score;
+        room.blue_score = saved.blue_score;
+        room.guesses = saved.guesses;
+        room.assassin_found_by = saved.assassin_found_by;
+        room.clue = saved.clue;
+        room.clues = saved.clues;
+        room.phase = saved.phase;
+        room.history = saved.history;
+        room.guess_log = saved.guess_log;
+        room.drawn = saved.drawn;
+        room.chat_history = saved.chat_history;
+        room.settings = saved.settings;
+        if room.in_progress() {
+            let now = Instant::now();
+            room.reserved = saved.seats.into_iter()
+                .map(|(user_name, team, role)| (user_name, (team, role, now)))
+                .collect();
+            room.paused = true;
+        }
+        room
+    }
+
+    /// Stops waiting for the players of a restored game, the seats still held get the
+    /// usual time to come back from now on and the turn timer starts again
+    pub fn resume(&mut self, now : Instant) {
+        if !self.paused {
+            return;
+        }
+        for (_, _, dropped) in self.reserved.values_mut() {
+            *dropped = now;
+        }
+        self.paused = false;
+        self.reset_turn_clock(now);
+    }
+
+    /// Keeps a chat message to replay to players that join later
+    fn record_chat(&mut self, chat_line : String, hidden_from_spymasters : bool) {
+        self.chat_history.push_back((chat_line, hidden_from_spymasters));
+        if self.chat_history.len() > CHAT_HISTORY_LEN {
+            self.chat_history.pop_front();
+        }
+    }
+
+    /// Whether nobody is in the room, seats held for dropped players don't count
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    /// Number of players in the room, seats held for dropped players don't count
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Where the game is at, for the lobby listing
+    pub fn status(&self) -> &'static str {
+        match self.state {
+            CodenamesState::WaitingToStart => "Waiting",
+            CodenamesState::RedTurn | CodenamesState::BlueTurn => "In Progress",
+            CodenamesState::GameEnd => "Ended"
+        }
+    }
+
+    pub fn has_ended(&self) -> bool {
+        self.state == CodenamesState::GameEnd
+    }
+
+    pub fn in_progress(&self) -> bool {
+        matches!(self.state, CodenamesState::RedTurn | CodenamesState::BlueTurn)
+    }
+
+    /// Restarts the turn timer, if it's on
+    fn reset_turn_clock(&mut self, now : Instant) {
+        self.turn_deadline = self.settings.turn_time.map(|t| now + t);
+    }
+
+    /// Whether the user can be added without going over the given number of players,
+    /// seats held for dropped players count as taken except the user's own
+    pub fn has_space_for(&self, user_name : &str, max_players : usize) -> bool {
+        let held = if self.in_progress() { self.reserved.len() } else { 0 };
+        self.has_reserved_seat(user_name) || self.players.len() + held < max_players
+    }
+
+    /// Whether a seat is being held for this user name
+    pub fn has_reserved_seat(&self, user_name : &str) -> bool {
+        self.in_progress() && self.reserved.contains_key(user_name)
+    }
+
+    /// The score a team needs to win, one point for each of its agents on the board
+    fn target_score(&self, team : CodenamesTeam) -> i32 {
+        let agent = if team == CodenamesTeam::Red {
+            CodenamesCardType::RedAgent
+        } else {
+            CodenamesCardType::BlueAgent
+        };
+        self.board.iter().flatten().filter(|card| card.card_type == agent).count() as i32
+    }
+
+    /// The team that won, if the assassin was found the other team wins,
+    /// otherwise it's the team that reached its target score
+    /// None if the game was a draw or nobody has won yet
+    fn winner(&self) -> Option<CodenamesTeam> {
+        if self.drawn {
+            return None;
+        }
+        match self.assassin_found_by {
+            Some(CodenamesTeam::Red) => Some(CodenamesTeam::Blue),
+            Some(_) => Some(CodenamesTeam::Red),
+            None if self.red_score >= self.target_score(CodenamesTeam::Red) => Some(CodenamesTeam::Red),
+            None if self.blue_score >= self.target_score(CodenamesTeam::Blue) => Some(CodenamesTeam::Blue),
+            None => None
+        }
+    }
+
+    /// Whether either team has found all of its agents
+    fn target_reached(&self) -> bool {
+        self.red_score >= self.target_score(CodenamesTeam::Red) ||
+            self.blue_score >= self.target_score(CodenamesTeam::Blue)
+    }
+
+    fn guess_history(&self) -> String {
+        if self.guess_log.is_empty() {
+            return "No cards have been guessed yet\r\n".to_string();
+        }
+        let mut out = "Guess history:\r\n".to_string();
+        for (user_name, word, card_type) in &self.guess_log {
+            out += &format!("{:>25}: {} ({})\r\n", user_name, word, card_type.name());
+        }
+        out
+    }
+
+    /// Who won, on its own so it stands out
+    fn result_headline(&self) -> String {
+        match self.winner() {
+            Some(winner) => format!("{} team wins!", winner),
+            None => "The game is a draw".to_string()
+        }
+    }
+
+    /// Why the game ended and the final score
+    fn result_reason(&self) -> String {
+        let reason = match (self.assassin_found_by, self.winner()) {
+            (Some(found_by), _) => format!("The {} team found the assassin. ", found_by),
+            (None, Some(winner)) => format!("The {} team found all of their agents. ", winner),
+            (None, None) => "Neither team has a spymaster. ".to_string()
+        };
+        reason + &format!("Final score {}-{} (R-B)", self.red_score, self.blue_score)
+    }
+
+    fn result_summary(&self) -> String {
+        format!("{} {}", self.result_headline(), self.result_reason())
+    }
+
+    /// The whole game in one block, for pasting elsewhere after the game ends
+    fn transcript(&self) -> String {
+        let mut out = format!("{:=^width$}\r\n", " Codenames Transcript ", width=line_width(self.settings.word_width, self.board.len()));
+        for event in &self.history {
+            out += &format!("{}\r\n", event);
+        }
+        out += &format!("{}\r\n", self.result_summary());
+        out += &self.get_board(CodenamesTeam::Floating, CodenamesRole::Spymaster);
+        out + &format!("{:=<width$}\r\n", "", width=line_width(self.settings.word_width, self.board.len()))
+    }
+
+    fn game_result(&self, user_state_map : &HashMap<SocketAddr, User>) -> GameResult {
+        let mut players : Vec<(String, CodenamesTeam, CodenamesRole)> = self.players.iter()
+            .filter_map(|addr| user_state_map.get(addr))
+            .map(|u| (u.user_name.to_string(),
+                      u.player.as_ref().unwrap_or_default().team,
+                      u.player.as_ref().unwrap_or_default().role))
+            .collect();
+        players.sort();
+        GameResult {
+            winner: self.winner().unwrap_or(CodenamesTeam::Floating),
+            drawn: self.drawn,
+            red_score: self.red_score,
+            blue_score: self.blue_score,
+            assassin_loss: self.assassin_found_by.is_some(),
+            players
+        }
+    }
+
     /// Returns a string representing a board's state for a given
     /// team and role type
-    fn get_board(&self, _team : CodenamesTeam, role : CodenamesRole) -> String {
+    pub(crate) fn get_board(&self, _team : CodenamesTeam, role : CodenamesRole) -> String {
+        self.render_board(role, self.settings.word_width)
+    }
+
+    /// Same as get_board, narrowed down for a terminal of the given width if it's too
+    /// narrow for the whole board, words are cut shorter to fit and when even that
+    /// doesn't do it every card goes on its own line
+    fn get_board_for_width(&self, team : CodenamesTeam, role : CodenamesRole,
+                           window_width : Option<u16>) -> String {
+        let board_dim = self.board.len();
+        let labels = self.row_label_len();
+        let columns = match window_width.map(usize::from) {
+            Some(columns) if columns < labels + line_width(self.settings.word_width, board_dim) => columns,
+            _ => return self.get_board(team, role)
+        };
+        // widest word column that keeps a line within the terminal
+        let word_width = (columns.saturating_sub(labels + 1) / board_dim).saturating_sub(1 + FLIPPED_LEN + MARKER_LEN);
+        if word_width >= MIN_WORD_WIDTH {
+            self.render_board(role, word_width)
+        } else {
+            self.render_card_list(role, columns)
+        }
+    }
+
+    /// Width of the row numbers in front of each line of the grid, none without coordinates
+    fn row_label_len(&self) -> usize {
+        if self.settings.coords { ROW_LABEL_LEN } else { 0 }
+    }
+
+    /// The board as a grid of cells with the word column the given width,
+    /// with coordinates shown the columns are lettered and the rows numbered
+    fn render_board(&self, role : CodenamesRole, word_width : usize) -> String {
         let board = &self.board;
-        let mut board_str = format!("{:-<width$}\r\n", "", width=LINE_WIDTH).to_string();
-        for row in board {
+        let line_width = line_width(word_width, board.len());
+        let labels = self.row_label_len();
+        let mut board_str = String::new();
+        if role == CodenamesRole::Spectator {
+            board_str += "Spectator view, every card's type is shown\r\n";
+        }
+        if self.settings.coords {
+            board_str += &format!("{:labels$}", "", labels=labels);
+            for column in 0..board.len() {
+                board_str += &format!(" {:^cell_width$}", column_letter(column),
+                                      cell_width=word_width + FLIPPED_LEN + MARKER_LEN);
+            }
+            board_str += " \r\n";
+        }
+        let divider = format!("{:labels$}{:-<width$}\r\n", "", "", labels=labels, width=line_width);
+        board_str += &divider;
+        for (row_index, row) in board.iter().enumerate() {
+            if self.settings.coords {
+                board_str += &format!("{:<labels$}", row_index + 1, labels=labels);
+            }
             for card in row {
-                let flipped = if card.flipped { "X".to_string() }  else { " ".to_string() };
-                // If you are a teammate and the card isn't flipped over then ONLY show the word
-                // otherwise you are a spymaster or the card is flipped over, show everything
-                if CodenamesRole::Teammate == role && !card.flipped {
-                    board_str += &format!("|{:>flipped_len$}{:^longest$}{:<marker_len$}",
-                                          flipped,
-                                          card.word,
-                                          "".to_string(),
-                                          flipped_len=FLIPPED_LEN,
-                                          longest=LONGEST_CODENAMES_WORD,
-                                          marker_len=MARKER_LEN);
+                let (flipped, marker) = card_marks(card, role);
+                // left aligned so there's always a gap between the X and the word
+                board_str += &format!("|{:<flipped_len$}{}{:<marker_len$}",
+                                      flipped,
+                                      center_in_columns(&card.word, word_width),
+                                      marker,
+                                      flipped_len=FLIPPED_LEN,
+                                      marker_len=MARKER_LEN);
+            }
+            board_str += "|\r\n";
+            board_str += &divider;
+        }
+        board_str
+    }
+
+    /// The board one card per line, for terminals too narrow for the grid,
+    /// the rows are still kept apart by a line as wide as the terminal
+    fn render_card_list(&self, role : CodenamesRole, columns : usize) -> String {
+        let mut board_str = String::new();
+        if role == CodenamesRole::Spectator {
+            board_str += "Spectator view, every card's type is shown\r\n";
+        }
+        board_str += &format!("{:-<width$}\r\n", "", width=columns);
+        for (row_index, row) in self.board.iter().enumerate() {
+            for (column, card) in row.iter().enumerate() {
+                let (flipped, marker) = card_marks(card, role);
+                let coord = if self.settings.coords {
+                    format!("{}{} ", column_letter(column), row_index + 1)
                 } else {
-                    board_str += &format!("|{:>flipped_len$}{:^longest$}{:<marker_len$} ",
-                                          flipped,
-                                          card.word,
-                                          card.card_type,
-                                          flipped_len=FLIPPED_LEN,
-                                          longest=LONGEST_CODENAMES_WORD,
-                                          marker_len=MARKER_LEN);
-                }
+                    String::new()
+                };
+                board_str += &format!("{:<flipped_len$}{:<marker_len$}{}{}\r\n",
+                                      flipped, marker, coord, card.word,
+                                      flipped_len=FLIPPED_LEN,
+                                      marker_len=MARKER_LEN);
             }
-            board_str += &format!("|\r\n{:-<width$}\r\n", "", width=LINE_WIDTH);
+            board_str += &format!("{:-<width$}\r\n", "", width=columns);
         }
         board_str
     }
 }
 
+/// The room's players grouped by team and role, one line per group, the four
+/// seats a game needs are always listed so it's clear which are still open
+fn get_team_rosters(room : &CodenamesRoom, user_state_map : &HashMap<SocketAddr, User>) -> String {
+    let mut groups : Vec<(String, Vec<&str>)> = [CodenamesTeam::Red, CodenamesTeam::Blue].iter()
+        .flat_map(|team| [format!("{} Spymaster", team), format!("{} Team", team)])
+        .chain(["Spectators".to_string(), "Unassigned".to_string()])
+        .map(|group| (group, Vec::new()))
+        .collect();
+    for user in room.players.iter().filter_map(|addr| user_state_map.get(addr)) {
+        let player = user.player.as_ref().unwrap_or_default();
+        let group = match (player.team, player.role) {
+            (CodenamesTeam::Red, CodenamesRole::Spymaster) => 0,
+            (CodenamesTeam::Red, CodenamesRole::Teammate) => 1,
+            (CodenamesTeam::Blue, CodenamesRole::Spymaster) => 2,
+            (CodenamesTeam::Blue, CodenamesRole::Teammate) => 3,
+            (_, CodenamesRole::Spectator | CodenamesRole::Observer) => 4,
+            _ => 5
+        };
+        groups[group].1.push(&user.user_name);
+    }
+    groups.iter_mut()
+        .filter(|(group, names)| !names.is_empty() || !matches!(group.as_str(), "Spectators" | "Unassigned"))
+        .map(|(group, names)| {
+            names.sort();
+            let names = if names.is_empty() { "(nobody)".to_string() } else { names.join(", ") };
+            format!("{}: {}\r\n", group, names)
+        })
+        .collect()
+}
+
 /// Shows the roles of all the room's players
 fn get_player_roles(room : &CodenamesRoom, user_state_map : &HashMap<SocketAddr, User>, cur_user_addr : SocketAddr) -> String {
     let list_str : String = room.players.iter().map(|room_player_addr|
-        user_state_map.get(&room_player_addr)
+        user_state_map.get(room_player_addr)
             .map_or("".to_string(), |u| format!("{:>3} {:>25} {:>10?}, {:>10?}\r\n",
                                                 if cur_user_addr == u.socket_addr { "YOU" } else { "" },
                                                 &u.user_name,
@@ -268,72 +1227,115 @@ fn get_player_roles(room : &CodenamesRoom, user_state_map : &HashMap<SocketAddr,
         &format!("{:-<49}\r\n", "")
 }
 
-fn codenames_turn_prompt(team : CodenamesTeam, player : &CodenamesPlayer, room : &CodenamesRoom) -> String {
-    let mut out = format!("{} Team's Turn:\r\n", team);
-    if (CodenamesRole::Spymaster, team) == (player.role, player.team) {
-        out += "Type in your clue in the format 'clue,number' where a clue is a single word\
-            and the number is the number of guesses your team has. Keep in mind you can't use\
-            the word you would like them to choose in the guess\r\n";
-    } else if  (CodenamesRole::Teammate, team) == (player.role, player.team) {
-        out += "Use chat to talk to everyone but ";
-        out += format!("the spymaster on the {} team. ", team).as_str();
-        out += "Guess by submitting your guess word with a '!' in front. \
-            End your turn with '!!' after making at least one guess.\r\n";
-    } else {
-        out += "Continue to talk to everyone, it's not your turn\r\n";
-    }
-    out += &format!("Score: {}-{} (R-B)\r\n", room.red_score, room.blue_score);
-    out += room.get_board(player.team, player.role).as_str();
-    return out;
-}
-
-/// Prompt generation function for a given user
-pub fn codenames_prompt(user_stream : &TcpStream, user_state_map : &mut HashMap<SocketAddr, User>,
-                        game_rooms : &mut HashMap<i32, GameRoom>) -> Option<String> {
-    let user_state = get_user_state(user_state_map, user_stream);
+fn codenames_turn_prompt(team : CodenamesTeam, player : &CodenamesPlayer, room : &CodenamesRoom,
+                         window_width : Option<u16>, prompts : &Prompts) -> String {
+    let mut out = prompts.get("turn", &[("team", &team)]);
+    let own_turn = player.team == team;
+    match (room.phase, player.role) {
+        (TurnPhase::AwaitingClue, CodenamesRole::Spymaster) if own_turn => {
+            out += &prompts.get("give_clue", &[]);
+        },
+        (TurnPhase::AwaitingClue, CodenamesRole::Teammate) if own_turn => {
+            out += &prompts.get("await_clue", &[]);
+        },
+        (TurnPhase::Guessing, CodenamesRole::Spymaster) if own_turn => {
+            out += &prompts.get("team_guessing", &[]);
+        },
+        (TurnPhase::Guessing, CodenamesRole::Teammate) if own_turn => {
+            out += &prompts.get("guess", &[("team", &team)]);
+            if room.settings.confirm_guesses {
+                out += &prompts.get("confirm_guesses", &[]);
+            }
+            if room.settings.training {
+                out += &prompts.get("training_risk", &[]);
+            }
+        },
+        (TurnPhase::AwaitingClue, _) => {
+            out += &prompts.get("other_team_clue", &[("team", &team)]);
+        },
+        (TurnPhase::Guessing, _) => {
+            out += &prompts.get("other_team_guessing", &[("team", &team)]);
+        }
+    }
+    if let (TurnPhase::Guessing, Some(clue)) = (room.phase, &room.clue) {
+        out += &prompts.get("clue", &[("clue", &clue.describe())]);
+    }
+    out += &prompts.get("score", &[("red", &room.red_score), ("blue", &room.blue_score)]);
+    out += &prompts.get("restart_hint", &[]);
+    // the first prompt of a turn is the one while the clue is awaited
+    if player.role == CodenamesRole::Spymaster && room.phase == TurnPhase::AwaitingClue {
+        out += &card_legend(player.team, room);
+    }
+    out += room.get_board_for_width(player.team, player.role, window_width).as_str();
+    out
+}
+
+/// What the type markers on a spymaster's board mean, from the point of view of their team
+fn card_legend(team : CodenamesTeam, room : &CodenamesRoom) -> String {
+    let (own, enemy) = match team {
+        CodenamesTeam::Blue => (CodenamesCardType::BlueAgent, CodenamesCardType::RedAgent),
+        _ => (CodenamesCardType::RedAgent, CodenamesCardType::BlueAgent)
+    };
+    let mut out = format!("Legend: {} your agent, {} enemy agent, {} assassin, ",
+                          own, enemy, CodenamesCardType::Assassin);
+    if room.board.iter().flatten().any(|card| card.card_type == CodenamesCardType::DoubleAgent) {
+        out += &format!("{} double agent, ", CodenamesCardType::DoubleAgent);
+    }
+    out + "blank bystander, X in front of a word means it's been flipped\r\n"
+}
+
+/// Prompt generation function for a given user
+fn codenames_prompt(user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+                    room : &mut CodenamesRoom, prompts : &Prompts) -> Option<String> {
+    let user_state = get_user_state(user_state_map, user_addr);
     let user_addr = user_state.socket_addr;
     // total output message (including all chat messages and prompt)
     let mut prompt : Vec<String> = Vec::new();
-    // process user's chat queue
+    // process user's chat queue, messages may or may not end in a line break
+    // so it's taken off here and every message gets exactly one below
     if let Some(ref mut player) = user_state.player {
         while let Some(msg) = player.chat_queue.pop_front() {
-            prompt.push(msg);
-        }
-    }
-    match initialize_user_board(user_state, game_rooms) {
-        Ok(room) => {
-            let player = user_state.player.as_mut().unwrap();
-            if player.state_prompted.is_none() ||
-                player.state_prompted.is_some_and(|state_prompted| state_prompted != room.state) {
-                player.state_prompted = Some(room.state);
-                match room.state {
-                    CodenamesState::WaitingToStart => { // TODO: refresh for all players if this prompt changes
-                        prompt.push("Available Options:\r\n".to_string() +
-                            "teammate/spymaster: Put yourself in one of these roles\r\n" +
-                            "red/blue: Put yourself into one of these teams\r\n" +
-                            "show: Show the current state of the room if there are any changes\r\n" +
-                            "start: Start the game if the correct roles are filled\r\n" +
-                            "Otherwise, any other input will be a chat message to the room\r\n" +
-                            &get_player_roles(&room, user_state_map, user_addr))
-                    },
-                    CodenamesState::BlueTurn => prompt.push(codenames_turn_prompt(CodenamesTeam::Blue, player, room)),
-                    CodenamesState::RedTurn => prompt.push(codenames_turn_prompt(CodenamesTeam::Red, player, room)),
-                    CodenamesState::GameEnd => { // TODO: not always triggering
-                        prompt.push("The game has ended, thanks for playing!\r\n".to_string());
-                        if let Some(found_by) = room.assassin_found_by {
-                            prompt.push(format!("The {} team found the assassin, so they lost!", found_by));
-                        } else {
-                            prompt.push(format!("The final score was {}-{} (R-B)\r\n", room.red_score, room.blue_score))
-                        }
-                    }
-                }
-            } else {
-                player.state_prompted = Some(room.state);
+            prompt.push(msg.trim_end_matches(['\r', '\n']).to_string());
+        }
+    }
+    let window_width = user_state.window_width;
+    room.join(user_state);
+    let player = user_state.player.as_mut().unwrap();
+    // the state's prompt goes out once, after that only the chat does
+    if player.state_prompted != Some(room.state) {
+        player.state_prompted = Some(room.state);
+        match room.state {
+            CodenamesState::WaitingToStart => {
+                prompt.push("Available Options:\r\n".to_string() +
+                    "teammate/spymaster/spectator: Put yourself in one of these roles\r\n" +
+                    "red/blue: Put yourself into one of these teams\r\n" +
+                    "show: Show the current state of the room if there are any changes\r\n" +
+                    "teams: Show who's on each team\r\n" +
+                    "stats: Show how your games this session went\r\n" +
+                    "start: Start the game if the correct roles are filled\r\n" +
+                    "balance: Put everyone without a team onto the smaller team\r\n" +
+                    "set <option> <value>: Change a room setting (width, training, confirm, chat, timer, size, lang, cards, similar, lockout, coords)\r\n" +
+                    "/me <action>: Emote to the room\r\n" +
+                    "/nick <name>: Change your username\r\n" +
+                    "/kick <username>: Vote to remove a player from the room\r\n" +
+                    "/report <username> <reason>: Report a player to the server's moderators\r\n" +
+                    "Commands can be shortened, like 'sm' for spymaster, 'tm' for teammate, 'r'/'b' for the teams or 's' for show\r\n" +
+                    "Otherwise, any other input will be a chat message to the room\r\n" +
+                    &format!("Room variant: {}\r\n", room.variant) +
+                    &format!("Room settings: {}\r\n", room.settings) +
+                    &get_player_roles(room, user_state_map, user_addr))
+            },
+            CodenamesState::BlueTurn => prompt.push(codenames_turn_prompt(CodenamesTeam::Blue, player, room, window_width, prompts)),
+            CodenamesState::RedTurn => prompt.push(codenames_turn_prompt(CodenamesTeam::Red, player, room, window_width, prompts)),
+            CodenamesState::GameEnd => {
+                prompt.push("The game has ended, thanks for playing!\r\n".to_string());
+                prompt.push("Type 'transcript' for a summary of the game to share, 'reveal' to see every card, \
+                    or 'newgame' to play again in this room\r\n".to_string());
+                prompt.push(room.guess_history());
             }
-        },
-        Err(_) => {} // TODO: should do something here
+        }
     }
-    if prompt.len() == 0 {
+    if prompt.is_empty() {
         return None;
     }
     Some(prompt.iter().map(|x| x.to_string() + "\r\n").collect())
@@ -341,42 +1343,88 @@ pub fn codenames_prompt(user_stream : &TcpStream, user_state_map : &mut HashMap<
 
 /// Sends a chat message from user to everyone else in the room
 /// players from the room are found using the user state map
+/// the room's chat setting can keep the guessing team's chat from the spymasters
 fn broadcast_chat(user_addr : SocketAddr, user_name : String,
-                  chat_line : String, room : &CodenamesRoom,
+                  chat_line : String, room : &mut CodenamesRoom,
                   user_state_map : &mut HashMap<SocketAddr, User>) {
+    let guessing_team = match room.state {
+        CodenamesState::RedTurn => Some(CodenamesTeam::Red),
+        CodenamesState::BlueTurn => Some(CodenamesTeam::Blue),
+        _ => None
+    };
+    if !chat_allowed(user_addr, user_state_map, Instant::now()) {
+        return;
+    }
+    let sender = user_state_map.get(&user_addr).and_then(|u| u.player.as_ref());
+    let hide_from_spymasters = room.settings.chat == ChatVisibility::HideFromSpymasters &&
+        sender.is_some_and(|p| p.role == CodenamesRole::Teammate && Some(p.team) == guessing_team);
+    let chat_line = format!("{}: {}", user_name, chat_line.trim());
     // send as a chat message to everyone else
     for room_user in user_state_map.values_mut() {
         if room.players.contains(&room_user.socket_addr) && room_user.socket_addr != user_addr {
             if let Some(ref mut room_player) = room_user.player {
-                room_player.chat_queue.push_back(
-                    format!("{}: {}", user_name, chat_line.trim().to_string()));
+                if hide_from_spymasters && room_player.role == CodenamesRole::Spymaster {
+                    continue;
+                }
+                room_player.queue_chat(chat_line.to_string());
+            }
+        }
+    }
+    room.record_chat(chat_line, hide_from_spymasters);
+}
+
+/// Whether the user can send another chat message now, if not they're told
+/// to slow down, once until a message of theirs gets through again
+fn chat_allowed(user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+                now : Instant) -> bool {
+    if let Some(player) = user_state_map.get_mut(&user_addr).and_then(|u| u.player.as_mut()) {
+        if !player.chat_limit.take(now) {
+            if !player.chat_limit.warned {
+                player.chat_limit.warned = true;
+                player.queue_chat("You're sending messages too fast\r\n".to_string());
             }
+            return false;
         }
     }
+    true
+}
+
+/// Sends a message to a single user
+fn send_chat(user_addr : SocketAddr, chat_line : String,
+             user_state_map : &mut HashMap<SocketAddr, User>) {
+    if let Some(player) = user_state_map.get_mut(&user_addr).and_then(|u| u.player.as_mut()) {
+        player.queue_chat(chat_line);
+    }
 }
 
-fn broadcast_chat_everyone(chat_line : String, room : &CodenamesRoom,
+/// Sends a system message or emote to everyone in the room, these are prefixed
+/// with "* " to set them apart from player chat
+fn broadcast_chat_everyone(chat_line : String, room : &mut CodenamesRoom,
                   user_state_map : &mut HashMap<SocketAddr, User>) {
+    let chat_line = format!("* {}", chat_line.trim());
     // send as a chat message to everyone else
     for room_user in user_state_map.values_mut() {
         if room.players.contains(&room_user.socket_addr) {
             if let Some(ref mut room_player) = room_user.player {
-                room_player.chat_queue.push_back(chat_line.trim().to_string());
+                room_player.queue_chat(chat_line.to_string());
             }
         }
     }
+    room.record_chat(chat_line, false);
 }
 
-fn verify_room(room : &CodenamesRoom, user_state_map : &mut HashMap<SocketAddr, User>) -> bool {
-    let mut counts = HashMap::new();
-    for addr in &room.players {
-        // TODO: should unwrap or is_some? it doesn't make sense if this option is None
-        let user = user_state_map.get(addr).unwrap();
-        if let Some(player) = &user.player {
-            let k = (player.team, player.role);
-            counts.entry(k).or_insert(0);
-            counts.insert(k, 1 + counts[&k]);
-        }
+/// Whether the room has at least a spymaster and a teammate on each team to start with,
+/// addresses of players that are already gone from the user state map are skipped
+fn verify_room(room : &CodenamesRoom, user_state_map : &HashMap<SocketAddr, User>) -> bool {
+    let mut counts : HashMap<(CodenamesTeam, CodenamesRole), usize> = HashMap::new();
+    let players = room.players.iter()
+        .filter_map(|addr| user_state_map.get(addr))
+        .filter_map(|user| user.player.as_ref())
+        // spectators, observers and players without a role don't count towards the roles needed to start
+        .filter(|p| !matches!(p.role, CodenamesRole::Spectator | CodenamesRole::Unassigned
+                                      | CodenamesRole::Observer));
+    for player in players {
+        *counts.entry((player.team, player.role)).or_default() += 1;
     }
     // should have at least one of the spymaster/teammate roles
     // in both red and blue
@@ -391,20 +1439,199 @@ fn verify_room(room : &CodenamesRoom, user_state_map : &mut HashMap<SocketAddr,
     true
 }
 
+/// Whether a player hasn't picked a team, observers never do so they don't count
+fn is_floating(player : &CodenamesPlayer) -> bool {
+    player.team == CodenamesTeam::Floating && player.role != CodenamesRole::Observer
+}
+
+/// Whether anyone in the room still hasn't picked a team
+fn has_floating_players(room : &CodenamesRoom, user_state_map : &HashMap<SocketAddr, User>) -> bool {
+    room.players.iter()
+        .filter_map(|addr| user_state_map.get(addr).and_then(|u| u.player.as_ref()))
+        .any(is_floating)
+}
+
+/// Puts every player that hasn't picked a team onto whichever team is smaller,
+/// those without a playing role become teammates so they count towards starting
+/// returns who went where, going through the players by name
+fn balance_teams(room : &CodenamesRoom,
+                 user_state_map : &mut HashMap<SocketAddr, User>) -> Vec<(String, CodenamesTeam)> {
+    let team_size = |team, user_state_map : &HashMap<SocketAddr, User>| room.players.iter()
+        .filter_map(|addr| user_state_map.get(addr).and_then(|u| u.player.as_ref()))
+        .filter(|player| player.team == team)
+        .count();
+    let mut floating : Vec<SocketAddr> = room.players.iter()
+        .filter(|addr| user_state_map.get(addr)
+            .and_then(|u| u.player.as_ref())
+            .is_some_and(is_floating))
+        .copied()
+        .collect();
+    floating.sort_by_key(|addr| user_state_map[addr].user_name.to_string());
+    let mut assignments = Vec::new();
+    for addr in floating {
+        let team = if team_size(CodenamesTeam::Blue, user_state_map) < team_size(CodenamesTeam::Red, user_state_map) {
+            CodenamesTeam::Blue
+        } else {
+            CodenamesTeam::Red
+        };
+        if let Some(user) = user_state_map.get_mut(&addr) {
+            let player = user.player.as_mut().unwrap();
+            player.team = team;
+            if matches!(player.role, CodenamesRole::Spectator | CodenamesRole::Unassigned) {
+                player.role = CodenamesRole::Teammate;
+            }
+            assignments.push((user.user_name.to_string(), team));
+        }
+    }
+    assignments
+}
+
+/// Whether the team still has a spymaster in the room to give clues,
+/// a spymaster that dropped counts until their seat is given up
+fn has_spymaster(team : CodenamesTeam, room : &CodenamesRoom,
+                 user_state_map : &HashMap<SocketAddr, User>) -> bool {
+    room.players.iter()
+        .filter_map(|addr| user_state_map.get(addr))
+        .filter_map(|user| user.player.as_ref())
+        .map(|player| (player.team, player.role))
+        .chain(room.reserved.values().map(|(team, role, _)| (*team, *role)))
+        .any(|seat| seat == (team, CodenamesRole::Spymaster))
+}
+
+/// Hands the turn over to the other team, ending the game if the scores are reached
+fn end_turn(team : CodenamesTeam, room : &mut CodenamesRoom) {
+    room.history.push(format!("{} turn ended", team));
+    room.pending_guess = None; // unconfirmed guesses time out with the turn
+    room.state = if team == CodenamesTeam::Blue {
+        CodenamesState::RedTurn
+    } else {
+        CodenamesState::BlueTurn
+    };
+    room.guesses = 0; // reset guesses for the new turn
+    room.clue = None; // and wait for the new spymaster's clue
+    room.phase = TurnPhase::AwaitingClue;
+    room.reset_turn_clock(Instant::now());
+    // if the end conditions are met, end the game
+    // TODO: adjust when either team can go first
+    if room.target_reached() {
+        room.state = CodenamesState::GameEnd;
+    }
+}
+
+/// Tells everyone in the room how the game ended and shows them the end of game prompt,
+/// the outcome goes in their chat so it's delivered even if the room is removed right after
+fn announce_game_end(room : &mut CodenamesRoom,
+                     user_state_map : &mut HashMap<SocketAddr, User>) {
+    record_game_end(room, user_state_map);
+    broadcast_chat_everyone(room.result_headline(), room, user_state_map);
+    broadcast_chat_everyone(room.result_reason(), room, user_state_map);
+    refresh_prompt(room, user_state_map);
+}
+
+/// Counts the game towards the session stats of everyone who played it,
+/// a draw counts as played without a win or a loss
+fn record_game_end(room : &CodenamesRoom, user_state_map : &mut HashMap<SocketAddr, User>) {
+    let winner = room.winner();
+    for addr in &room.players {
+        let Some(user) = user_state_map.get_mut(addr) else {
+            continue;
+        };
+        let Some(team) = user.player.as_ref()
+            .filter(|player| matches!(player.role, CodenamesRole::Spymaster | CodenamesRole::Teammate))
+            .map(|player| player.team)
+            .filter(|team| *team != CodenamesTeam::Floating) else {
+            continue;
+        };
+        user.stats.played += 1;
+        match winner {
+            Some(winner) if winner == team => user.stats.wins += 1,
+            Some(_) => user.stats.losses += 1,
+            None => {}
+        }
+    }
+}
+
+/// Has everyone in the room, spectators and observers included, sent the prompt again
+/// on their next poll, even if they haven't typed anything, so they see what changed
 fn refresh_prompt(room : &mut CodenamesRoom,
                   user_state_map : &mut HashMap<SocketAddr, User>) {
     for id in &room.players {
-        if let Some(user) = user_state_map.get_mut(&id) {
+        if let Some(user) = user_state_map.get_mut(id) {
             user.player.as_mut().unwrap().state_prompted = None;
         }
     }
 }
 
+/// One line summary of the scores, whose turn it is and the clue being played,
+/// only public information so anyone can ask for it
+fn status_line(team : CodenamesTeam, room : &CodenamesRoom) -> String {
+    let guesses = if room.guesses == 1 { "guess" } else { "guesses" };
+    let clue = match &room.clue {
+        Some(clue) => format!("clue: {} {}", clue.clue,
+                              clue.cards_to_match.map_or("unlimited".to_string(), |n| n.to_string())),
+        None => "no clue yet".to_string()
+    };
+    format!("Red {} / Blue {} - {}'s turn, {} {} made, {}\r\n",
+            room.red_score, room.blue_score, team, room.guesses, guesses, clue)
+}
+
+/// How many of each team's agents are still face down on the board, the given team's first,
+/// the same as the agents each team still needs unless double agents have made up some of it
+fn remaining_report(team : CodenamesTeam, room : &CodenamesRoom) -> String {
+    let describe = |team : CodenamesTeam| {
+        let agent = if team == CodenamesTeam::Red {
+            CodenamesCardType::RedAgent
+        } else {
+            CodenamesCardType::BlueAgent
+        };
+        let unflipped = room.board.iter().flatten()
+            .filter(|card| !card.flipped && card.card_type == agent)
+            .count() as i32;
+        let score = if team == CodenamesTeam::Red { room.red_score } else { room.blue_score };
+        let needed = (room.target_score(team) - score).max(0);
+        if needed == unflipped {
+            format!("{} {} left", team, unflipped)
+        } else {
+            format!("{} {} left ({} more to win)", team, unflipped, needed)
+        }
+    };
+    // players without a team just see red first
+    let (first, second) = if team == CodenamesTeam::Blue {
+        (CodenamesTeam::Blue, CodenamesTeam::Red)
+    } else {
+        (CodenamesTeam::Red, CodenamesTeam::Blue)
+    };
+    format!("Agents: {}, {}\r\n", describe(first), describe(second))
+}
+
+/// Counts of the unflipped cards that would hurt the given team if guessed,
+/// without revealing which cards they are
+fn risk_report(team : CodenamesTeam, room : &CodenamesRoom) -> String {
+    let enemy_agent = if team == CodenamesTeam::Red {
+        CodenamesCardType::BlueAgent
+    } else {
+        CodenamesCardType::RedAgent
+    };
+    let unflipped : Vec<&CodenamesCard> = room.board.iter()
+        .flatten()
+        .filter(|card| !card.flipped)
+        .collect();
+    let count = |card_type| unflipped.iter().filter(|card| card.card_type == card_type).count();
+    // rooms can pick a mix with more than one assassin
+    let assassins = count(CodenamesCardType::Assassin);
+    format!("{} unflipped cards: {} enemy agents, {} bystanders, {} {}\r\n",
+            unflipped.len(), count(enemy_agent), count(CodenamesCardType::Bystander),
+            assassins, if assassins == 1 { "assassin" } else { "assassins" })
+}
+
 /// Finds the card with the given card name in the codenames room, returns a mutable reference
+/// names are matched ignoring case and surrounding whitespace, words are unique
+/// per board regardless of case (see `gen_board`), so there is at most one match
 fn find_card<'a>(card_name : &str, room : & 'a mut CodenamesRoom) -> Option<& 'a mut CodenamesCard> {
-    for row in room.board.as_mut() {
+    let card_name = card_name.trim().to_lowercase();
+    for row in room.board.iter_mut() {
         for card in row {
-            if card.word == card_name {
+            if card.word.trim().to_lowercase() == card_name {
                 // found the card, output what it is
                 return Some(card);
             }
@@ -413,96 +1640,305 @@ fn find_card<'a>(card_name : &str, room : & 'a mut CodenamesRoom) -> Option<& 'a
     None
 }
 
+/// Reads a coordinate like B3, a column letter then a row number, into the row and
+/// column of the board it's on, None if it isn't one or is off the board
+fn parse_coord(coord : &str, board_dim : usize) -> Option<(usize, usize)> {
+    let coord = coord.trim();
+    let mut chars = coord.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let row = chars.as_str().parse::<usize>().ok()?;
+    let column = usize::from(u8::try_from(letter).ok()?.checked_sub(b'A')?);
+    (letter.is_ascii_uppercase() && (1..=board_dim).contains(&row) && column < board_dim)
+        .then_some((row - 1, column))
+}
+
+/// Finds the card at the given coordinate in the codenames room, returns a mutable reference
+fn find_card_by_coord<'a>(coord : &str, room : & 'a mut CodenamesRoom) -> Option<& 'a mut CodenamesCard> {
+    let (row, column) = parse_coord(coord, room.board.len())?;
+    room.board.get_mut(row)?.get_mut(column)
+}
+
+/// An uncovered board word the clue looks like a form of, covered ones are fair game
+fn related_board_word<'a>(clue : &str, room : &'a CodenamesRoom, max_distance : usize) -> Option<&'a str> {
+    room.board.iter().flatten()
+        .find(|card| !card.flipped && clue_resembles(clue, &card.word, max_distance))
+        .map(|card| card.word.as_str())
+}
+
+/// Guesses the team has left on the current clue, a clue for n cards allows n + 1,
+/// None when there's no limit, before a clue or for a 0 or unlimited clue
+fn guesses_remaining(room : &CodenamesRoom) -> Option<i32> {
+    room.clue.as_ref()
+        .and_then(CodenamesClue::max_guesses)
+        .map(|max_guesses| (max_guesses - room.guesses).max(0))
+}
+
+/// Flips the guessed card and scores it for the guessing team
+/// returns whether the guess ends the team's turn
+fn make_guess(team : CodenamesTeam,
+              guess : &str,
+              user_name : &str,
+              room : &mut CodenamesRoom,
+              user_state_map : &mut HashMap<SocketAddr, User>) -> bool {
+    let mut switch_turn = false;
+    broadcast_chat_everyone(format!("{} Guessed {}\r\n", user_name, guess),
+                            room, user_state_map);
+    // a guess that's both a coordinate and a board word is the word,
+    // unless the board is showing its coordinates
+    let by_coord = parse_coord(guess, room.board.len()).is_some()
+        && (room.settings.coords || find_card(guess, room).is_none());
+    let card = if by_coord { find_card_by_coord(guess, room) } else { find_card(guess, room) };
+    // check the guess, act on flipped card
+    match card {
+        Some(card) if !card.flipped => {
+            // flip over the card so everyone can see it
+            card.flipped = true;
+            let card_type = card.card_type;
+            let word = card.word.to_string();
+            // only a guess that turns over a card uses one up
+            room.guesses += 1;
+            room.reset_turn_clock(Instant::now());
+            room.history.push(format!("{} guessed {}: {}", user_name, word, card_type.name()));
+            room.guess_log.push((user_name.to_string(), word, card_type));
+            // red agents increment the red score
+            // blue agents increment the blue score
+            // bystanders switch the turn
+            // double agents score for the guessing team in the double agent variant
+            // assassins end the game and cause the current team to lose
+            match card_type {
+                CodenamesCardType::RedAgent => {
+                    room.red_score += 1;
+                    if team == CodenamesTeam::Blue {
+                        switch_turn = true;
+                    }
+                },
+                CodenamesCardType::BlueAgent => {
+                    room.blue_score += 1;
+                    if team == CodenamesTeam::Red {
+                        switch_turn = true;
+                    }
+                },
+                CodenamesCardType::Bystander => switch_turn = true,
+                CodenamesCardType::DoubleAgent => match room.variant {
+                    RoomVariant::DoubleAgent => {
+                        if team == CodenamesTeam::Red {
+                            room.red_score += 1;
+                        } else {
+                            room.blue_score += 1;
+                        }
+                    },
+                    // never dealt in a standard game, treat it like a bystander
+                    RoomVariant::Standard => switch_turn = true
+                },
+                CodenamesCardType::Assassin => {
+                    // end the game, this team lost
+                    room.assassin_found_by = Some(team);
+                    room.state = CodenamesState::GameEnd;
+                    return false
+                }
+            }
+            // the game is over as soon as a team's last agent is found,
+            // double agents can push a team past its own agent count
+            if room.target_reached() {
+                room.state = CodenamesState::GameEnd;
+                return false;
+            }
+            // the extra guess over the clue's number has been used up
+            if guesses_remaining(room) == Some(0) {
+                switch_turn = true;
+            }
+            // rebroadcast the board to everyone to take these updates into account
+            refresh_prompt(room, user_state_map);
+        },
+        Some(card) => {
+            let word = card.word.to_string();
+            broadcast_chat_everyone(format!("{} has already been flipped\r\n", word), room, user_state_map);
+        },
+        None => broadcast_chat_everyone(format!("{} is not a valid card name to guess\r\n", guess),
+                                        room, user_state_map)
+    }
+    switch_turn
+}
+
 fn turn_logic(team : CodenamesTeam,
               line : &Option<String>,
               user_state_map : &mut HashMap<SocketAddr, User>,
               room : &mut CodenamesRoom,
               user_addr : SocketAddr,
               user_name : String) {
+    // spymasters can leave mid game, a team without one can't be given clues
+    if !has_spymaster(team, room, user_state_map) {
+        let other_team = if team == CodenamesTeam::Red { CodenamesTeam::Blue } else { CodenamesTeam::Red };
+        if has_spymaster(other_team, room, user_state_map) {
+            broadcast_chat_everyone(format!("{} has no spymaster, skipping turn\r\n", team),
+                                    room, user_state_map);
+            end_turn(team, room);
+        } else {
+            room.history.push("Neither team has a spymaster, the game is a draw".to_string());
+            room.drawn = true;
+            room.state = CodenamesState::GameEnd;
+        }
+        if room.has_ended() {
+            announce_game_end(room, user_state_map);
+        } else {
+            refresh_prompt(room, user_state_map);
+        }
+        return;
+    }
     let mut switch_turn = false;
     if let Some(line) = line {
+        if line.trim() == "risk" {
+            let report = if room.settings.training {
+                risk_report(team, room)
+            } else {
+                "risk is only available in training mode\r\n".to_string()
+            };
+            send_chat(user_addr, report, user_state_map);
+            return;
+        }
+        if line.trim() == "transcript" {
+            send_chat(user_addr, "The transcript is available once the game ends\r\n".to_string(),
+                      user_state_map);
+            return;
+        }
+        if matches!(line.trim(), "status" | "score") {
+            send_chat(user_addr, status_line(team, room), user_state_map);
+            return;
+        }
+        if line.trim() == "board" {
+            // the same view the turn prompt gives this player, fitted to their window
+            let board = user_state_map.get(&user_addr)
+                .and_then(|user| user.player.as_ref().map(|player| (player, user.window_width)))
+                .map(|(player, window_width)| room.get_board_for_width(player.team, player.role, window_width));
+            if let Some(board) = board {
+                send_chat(user_addr, board, user_state_map);
+            }
+            return;
+        }
+        if matches!(line.trim(), "clue" | "/clue") {
+            send_chat(user_addr, clue_recap(room), user_state_map);
+            return;
+        }
+        if line.trim() == "remaining" {
+            let viewer_team = user_state_map.get(&user_addr)
+                .and_then(|user| user.player.as_ref())
+                .map_or(CodenamesTeam::Floating, |player| player.team);
+            send_chat(user_addr, remaining_report(viewer_team, room), user_state_map);
+            return;
+        }
         let user = user_state_map.get(&user_addr).unwrap();
         let player = user.player.as_ref().unwrap();
         if team == player.team && player.role == CodenamesRole::Teammate {
             // Teammate actions for the team
-            if line.starts_with("!!") {
-                // End guesses, must have guessed at least once
-                if room.guesses > 0 {
+            if room.settings.confirm_guesses && line.trim() == "!!yes" {
+                // Confirm this teammate's pending guess
+                match room.pending_guess.take() {
+                    Some((guesser, guess)) if guesser == user_addr => {
+                        switch_turn = make_guess(team, &guess, &user_name, room, user_state_map);
+                    },
+                    pending => {
+                        room.pending_guess = pending;
+                        send_chat(user_addr, "You have no guess to confirm\r\n".to_string(), user_state_map);
+                    }
+                }
+            } else if line.starts_with('!') && room.phase == TurnPhase::AwaitingClue {
+                send_chat(user_addr, "Wait for your spymaster's clue\r\n".to_string(), user_state_map);
+            } else if line.starts_with("!!") {
+                // End guesses, a numbered clue must be guessed at least once
+                if room.guesses > 0 || !room.clue.as_ref().is_some_and(CodenamesClue::requires_guess) {
                     switch_turn = true;
                 } else {
-                    // TODO: notify can't end
+                    send_chat(user_addr, "Make at least one guess before ending the turn\r\n".to_string(),
+                              user_state_map);
                 }
-            } else if line.starts_with("!") {
-                // Guess
-                room.guesses += 1;
-                let guess = &line[1..].trim();
-                broadcast_chat_everyone(format!("{} Guessed {}\r\n", user.user_name, guess),
-                                        room, user_state_map);
-                // check the guess, act on flipped card
-                if let Some(card) = find_card(guess, room) {
-                    // flip over the card so everyone can see it
-                    card.flipped = true;
-                    // red agents increment the red score
-                    // blue agents increment the blue score
-                    // bystanders switch the turn
-                    // assassins end the game and cause the current team to lose
-                    match card.card_type {
-                        CodenamesCardType::RedAgent => {
-                            room.red_score += 1;
-                            if team == CodenamesTeam::Blue {
-                                switch_turn = true;
-                            }
-                        },
-                        CodenamesCardType::BlueAgent => {
-                            room.blue_score += 1;
-                            if team == CodenamesTeam::Red {
-                                switch_turn = true;
-                            }
-                        },
-                        CodenamesCardType::Bystander => switch_turn = true,
-                        CodenamesCardType::Assassin => {
-                            // end the game, this team lost
-                            room.assassin_found_by = Some(team);
-                            room.state = CodenamesState::GameEnd;
-                            return
-                        }
-                    }
-                    // switch turn if +1 guess than the spymaster
-                    if let Some(clue) = &room.clue {
-                        if room.guesses > clue.cards_to_match {
-                            switch_turn = true;
-                        }
-                    }
-                    // rebroadcast the board to everyone to take these updates into account
-                    refresh_prompt(room, user_state_map);
+            } else if let Some(guess) = line.strip_prefix('!') {
+                let guess = guess.trim();
+                if room.settings.confirm_guesses {
+                    // hold the guess until the teammate confirms it
+                    room.pending_guess = Some((user_addr, guess.to_string()));
+                    send_chat(user_addr, format!("Guess {}? Reply !!yes to confirm\r\n", guess),
+                              user_state_map);
                 } else {
-                    broadcast_chat_everyone(
-                        format!("{} is not a valid card name to guess\r\n", guess),
-                        room, user_state_map);
+                    switch_turn = make_guess(team, guess, &user_name, room, user_state_map);
                 }
+            } else {
+                // talking it over with the team
+                broadcast_chat(user_addr, user_name, line.to_string(), room, user_state_map);
             }
         } else if team == player.team && player.role == CodenamesRole::Spymaster {
             // Spymaster actions
             // spymaster should only say the guess word comma the number
             match line.split(',').collect::<Vec<&str>>()[..] {
+                // no clue this turn, the other team goes next
+                _ if line.trim() == "pass" && room.phase == TurnPhase::AwaitingClue => {
+                    room.history.push(format!("{} spymaster passed", team));
+                    broadcast_chat_everyone(format!("{}'s spymaster passed.\r\n", team), room, user_state_map);
+                    switch_turn = true;
+                },
+                _ if line.trim() == "pass" => {
+                    send_chat(user_addr, "Your team is already guessing on your clue, it's too late to pass\r\n"
+                                  .to_string(), user_state_map);
+                },
+                // only one clue per turn, it's cleared when the turn ends
+                _ if room.phase == TurnPhase::Guessing => {
+                    send_chat(user_addr, "You've already given a clue this turn\r\n".to_string(),
+                              user_state_map);
+                },
+                [word, _] if word.trim().is_empty() => {
+                    send_chat(user_addr, "The clue is missing its word, give your clue as 'clue,number'\r\n"
+                                  .to_string(), user_state_map);
+                },
+                [word, _] if room.settings.lockout && clue_given_before(word, room) => {
+                    send_chat(user_addr, format!("{} has already been a clue this game, pick another\r\n",
+                                                 word.trim()), user_state_map);
+                },
                 [word, number] => {
-                    if let Ok(guess_number) = number.trim().parse::<i32>() {
-                        room.clue = Some(CodenamesClue {
+                    let word = word.trim();
+                    if let Some(guess_number) = parse_clue_number(number) {
+                        let clue = CodenamesClue {
                             cards_to_match: guess_number,
                             clue: word.to_string()
-                        });
+                        };
+                        room.clues.push((team, clue.clone()));
+                        room.clue = Some(clue);
+                        room.phase = TurnPhase::Guessing;
+                        room.reset_turn_clock(Instant::now());
+                        let guess_number = guess_number.map_or("unlimited".to_string(), |n| n.to_string());
+                        room.history.push(format!("{} spymaster clue: {}, {}", team, word, guess_number));
                         // notify everyone of the guess
                         broadcast_chat_everyone(format!("Spymaster Clue: {}, {}\r\n",
-                                                        word.to_string(), guess_number),
+                                                        word, guess_number),
                                                 room, user_state_map);
+                        // the turn prompt changes for everyone now the team is guessing
+                        refresh_prompt(room, user_state_map);
+                        // the clue still stands, it's up to the room whether to let it go
+                        if let Some(board_word) = room.settings.clue_distance
+                            .and_then(|distance| related_board_word(word, room, distance)) {
+                            send_chat(user_addr, format!("Careful, {} looks a lot like {} on the board, \
+                                                          the other players might not accept it\r\n", word, board_word),
+                                      user_state_map);
+                        }
                     } else {
-                        // TODO: notify user
+                        send_chat(user_addr, format!("{} isn't a number of cards, use 0 or more or 'unlimited'\r\n",
+                                                     number.trim()), user_state_map);
                     }
                 },
+                [word] => {
+                    send_chat(user_addr, format!("{} is missing the number of cards, give your clue as 'clue,number'\r\n",
+                                                 word.trim()), user_state_map);
+                },
+                // a comma in the clue makes it more than one word
                 _ => {
-                    // TODO: notify user
+                    send_chat(user_addr, "Clues have to be a single word, give your clue as 'clue,number'\r\n"
+                                  .to_string(), user_state_map);
                 }
             }
+        } else if player.role == CodenamesRole::Spectator && line.starts_with('!') {
+            send_chat(user_addr, "Spectators cannot guess\r\n".to_string(), user_state_map);
+        } else if player.role == CodenamesRole::Unassigned && line.starts_with('!') {
+            send_chat(user_addr, "Players without a role cannot guess\r\n".to_string(), user_state_map);
+        } else if player.role == CodenamesRole::Observer && line.starts_with('!') {
+            send_chat(user_addr, "Observers cannot guess\r\n".to_string(), user_state_map);
         } else  {
             // Spectator/non participant actions
             // can talk to everyone
@@ -513,113 +1949,2105 @@ fn turn_logic(team : CodenamesTeam,
         }
 
         if switch_turn {
-            room.state = if team == CodenamesTeam::Blue {
-                CodenamesState::RedTurn
-            } else {
-                CodenamesState::BlueTurn
-            };
-            room.guesses = 0; // reset guesses for the new turn
-            // if the end conditions are met, end the game
-            // TODO: adjust when either team can go first
-            if room.red_score == 9 || room.blue_score == 8 {
-                room.state = CodenamesState::GameEnd;
-            }
+            end_turn(team, room);
+        }
+        // whatever ended the game, everyone has to hear about it
+        if room.has_ended() {
+            announce_game_end(room, user_state_map);
         }
     }
 }
 
 /// Processes the input from a user
-pub fn codenames_logic(user_stream : &TcpStream, user_state_map : &mut HashMap<SocketAddr, User>,
-                       game_rooms : &mut HashMap<i32, GameRoom>, line : &Option<String>) {
-    let user_state = get_user_state(user_state_map, user_stream);
+/// returns the game's result if this input ended the game
+fn codenames_logic(user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+                   room : &mut CodenamesRoom, word_lists : &WordLists,
+                   line : &Option<String>) -> Option<GameResult> {
+    let user_state = get_user_state(user_state_map, user_addr);
     let user_addr = user_state.socket_addr;
     let user_name = user_state.user_name.to_string();
-    // Based on the state of the room, either go through the pre-game
-    // initialization or the game logic itself
-    match initialize_user_board(user_state, game_rooms) {
-        Ok(room) => {
-            // TODO: is it possible for this unwrap to panic?
-            let player = user_state.player.as_mut().unwrap();
-            match room.state {
-                CodenamesState::WaitingToStart => {
-                    if let Some(line) = line {
-                        match line.trim() {
-                            "start" => {
-                                // verify conditions are correct, then start the game
-                                // tell the room which player started the game
-                                // need at least 2 players on each team,
-                                // one spymaster and one teammate
-                                if verify_room(room, user_state_map) {
-                                    broadcast_chat_everyone(user_name.to_string() +
-                                                                " Started the Game!\r\n",
-                                                            room, user_state_map);
-                                    room.state = CodenamesState::RedTurn;
-                                } else {
-                                    broadcast_chat_everyone(
-                                        "Cannot start the game yet, need at least a \
-                                        spymaster and a teammate on each team\r\n".to_string(),
-                                            room, user_state_map);
+    let window_width = user_state.window_width;
+    room.join(user_state);
+    // TODO: is it possible for this unwrap to panic?
+    let player = user_state.player.as_mut().unwrap();
+    // commands can be shortened before the game, once it starts a word on its own is a guess
+    let expanded;
+    let line = match line.as_deref().map(str::trim).filter(|_| room.state == CodenamesState::WaitingToStart) {
+        Some(word) => match resolve_command(word, &WAITING_COMMANDS) {
+            Ok(Some(command)) => {
+                expanded = Some(command.to_string());
+                &expanded
+            },
+            Ok(None) => line,
+            Err(candidates) => {
+                player.queue_chat(format!("'{}' could be {}, type more of it\r\n", word, candidates.join(", ")));
+                return None;
+            }
+        },
+        None => line
+    };
+    if line.as_deref().map(str::trim) == Some("log") {
+        player.queue_chat(room.guess_history());
+        return None;
+    }
+    if line.as_deref().map(str::trim) == Some("teams") {
+        let rosters = get_team_rosters(room, user_state_map);
+        send_chat(user_addr, rosters, user_state_map);
+        return None;
+    }
+    if line.as_deref().map(str::trim) == Some("who") {
+        // roles and teams are public, so anyone can see them at any time
+        let roles = get_player_roles(room, user_state_map, user_addr);
+        send_chat(user_addr, roles, user_state_map);
+        return None;
+    }
+    if let Some(action) = line.as_deref().and_then(|l| l.trim().strip_prefix("/me ")) {
+        if !room.has_ended() {
+            if chat_allowed(user_addr, user_state_map, Instant::now()) {
+                broadcast_chat_everyone(format!("{} {}", user_name, action.trim()), room, user_state_map);
+            }
+            return None;
+        }
+    }
+    if let Some(target) = line.as_deref().and_then(|l| l.trim().strip_prefix("/kick ")) {
+        if !room.has_ended() {
+            vote_to_kick(user_addr, target.trim(), room, user_state_map, Instant::now());
+            return None;
+        }
+    }
+    if line.as_deref().map(str::trim) == Some("/restart") {
+        if room.in_progress() {
+            vote_to_restart(user_addr, room, user_state_map, word_lists, Instant::now());
+        } else {
+            player.queue_chat("There's no game in progress to restart\r\n".to_string());
+        }
+        return None;
+    }
+    match room.state {
+        CodenamesState::WaitingToStart => {
+            if let Some(line) = line {
+                match line.trim() {
+                    "start" => {
+                        // verify conditions are correct, then start the game
+                        // tell the room which player started the game
+                        // need at least 2 players on each team,
+                        // one spymaster and one teammate
+                        if verify_room(room, user_state_map) {
+                            broadcast_chat_everyone(user_name.to_string() +
+                                                        " Started the Game!\r\n",
+                                                    room, user_state_map);
+                            room.state = CodenamesState::RedTurn;
+                            room.reset_turn_clock(Instant::now());
+                        } else {
+                            broadcast_chat_everyone(
+                                "Cannot start the game yet, need at least a \
+                                spymaster and a teammate on each team\r\n".to_string(),
+                                    room, user_state_map);
+                            if has_floating_players(room, user_state_map) {
+                                send_chat(user_addr, "Type 'balance' to put the players without \
+                                    a team onto the smaller team\r\n".to_string(), user_state_map);
+                            }
+                        }
+                    },
+                    "balance" => {
+                        let assignments = balance_teams(room, user_state_map);
+                        if assignments.is_empty() {
+                            send_chat(user_addr, "Everyone is already on a team\r\n".to_string(), user_state_map);
+                        } else {
+                            let placed : Vec<String> = assignments.iter()
+                                .map(|(name, team)| format!("{} to {}", name, team))
+                                .collect();
+                            broadcast_chat_everyone(format!("{} balanced the teams: {}\r\n",
+                                                            user_name, placed.join(", ")),
+                                                    room, user_state_map);
+                            refresh_prompt(room, user_state_map);
+                        }
+                    },
+                    "teammate" | "spymaster" | "spectator" | "red" | "blue"
+                        if player.role == CodenamesRole::Observer => {
+                        player.queue_chat("You're only watching this room, join it from the lobby \
+                            to play\r\n".to_string());
+                    },
+                    choice @ ("teammate" | "spymaster" | "spectator" | "red" | "blue") => {
+                        match choice {
+                            "teammate" => player.role = CodenamesRole::Teammate,
+                            "spymaster" => player.role = CodenamesRole::Spymaster,
+                            "spectator" => player.role = CodenamesRole::Spectator,
+                            "red" => player.team = CodenamesTeam::Red,
+                            _ => player.team = CodenamesTeam::Blue
+                        }
+                        // everyone's roles table shows the change
+                        refresh_prompt(room, user_state_map);
+                    },
+                    "show" => {
+                        player.state_prompted = None;
+                    }
+                    cmd if cmd.starts_with("set ") => {
+                        match cmd.split_whitespace().collect::<Vec<&str>>()[..] {
+                            [_, option, value] => {
+                                let previous = room.settings.clone();
+                                match room.settings.apply(option, value)
+                                    .and_then(|change| redeal_board(room, previous, word_lists).map(|_| change)) {
+                                    Ok(change) => {
+                                        broadcast_chat_everyone(format!("{} set {}\r\n", user_name, change),
+                                                                room, user_state_map);
+                                        refresh_prompt(room, user_state_map);
+                                    },
+                                    Err(reason) => player.queue_chat(reason + "\r\n")
                                 }
                             },
-                            "teammate" => {
-                                player.role = CodenamesRole::Teammate;
-                                player.state_prompted = None;
-                            },
-                            "spymaster" => {
-                                player.role = CodenamesRole::Spymaster;
-                                player.state_prompted = None;
-                            },
-                            "red" => {
-                                player.team = CodenamesTeam::Red;
-                                player.state_prompted = None;
-                            },
-                            "blue" => {
-                                player.team = CodenamesTeam::Blue;
-                                player.state_prompted = None;
-                            }
-                            "show" => {
-                                player.state_prompted = None;
-                            }
-                            _ => {
-                                broadcast_chat(user_addr, user_name,
-                                               line.to_string(),
-                                               room, user_state_map);
-                            }
+                            _ => player.queue_chat("Usage: set <option> <value>\r\n".to_string())
                         }
                     }
-                },
-                CodenamesState::BlueTurn => turn_logic(CodenamesTeam::Blue, line, user_state_map,
-                                                       room, user_addr, user_name),
-                CodenamesState::RedTurn => turn_logic(CodenamesTeam::Red, line, user_state_map,
-                                                      room, user_addr, user_name),
-                CodenamesState::GameEnd => {
-                    // delete the room when the game ends
-                    if let Some(room_key) = user_state.game_room_key {
-                        game_rooms.remove(&room_key);
+                    _ => {
+                        broadcast_chat(user_addr, user_name,
+                                       line.to_string(),
+                                       room, user_state_map);
                     }
                 }
             }
         },
-        Err(_) => {}
+        CodenamesState::BlueTurn | CodenamesState::RedTurn => {
+            // a start typed at about the same time as the one that got in first,
+            // before anyone has made a move in the game it started
+            if room.history.is_empty() && line.as_deref().map(str::trim) == Some("start") {
+                player.queue_chat("The game is already starting\r\n".to_string());
+                return None;
+            }
+            let team = if room.state == CodenamesState::BlueTurn {
+                CodenamesTeam::Blue
+            } else {
+                CodenamesTeam::Red
+            };
+            turn_logic(team, line, user_state_map, room, user_addr, user_name);
+            // only the input that ends the game produces a result
+            if room.state == CodenamesState::GameEnd {
+                return Some(room.game_result(user_state_map));
+            }
+        },
+        CodenamesState::GameEnd => {
+            match line.as_deref().map(str::trim) {
+                Some("transcript") => player.queue_chat(room.transcript()),
+                Some("reveal") => {
+                    // the spymaster view has every card's type, with the guessed ones marked
+                    player.queue_chat("Every card's type, X marks the ones that were guessed\r\n".to_string() +
+                                      &room.get_board_for_width(player.team, CodenamesRole::Spymaster,
+                                                                window_width));
+                },
+                Some("newgame") => {
+                    match deal_new_game(room, word_lists) {
+                        Ok(()) => {
+                            broadcast_chat_everyone(format!("{} started a new game", user_name),
+                                                    room, user_state_map);
+                            refresh_prompt(room, user_state_map);
+                        },
+                        Err(e) => player.queue_chat(e + "\r\n")
+                    }
+                },
+                // players can keep talking about the game, the room goes once they've all left
+                Some(chat_line) => broadcast_chat(user_addr, user_name, chat_line.to_string(),
+                                                  room, user_state_map),
+                None => {}
+            }
+        }
+    }
+    None
+}
+
+/// Replaces the room's game with a new one on a freshly dealt board, waiting to start,
+/// the players keep their teams and roles and the room its settings and chat
+fn deal_new_game(room : &mut CodenamesRoom, word_lists : &WordLists) -> Result<(), String> {
+    let words = word_lists.get(&room.settings.language)
+        .unwrap_or(&word_lists[DEFAULT_LANGUAGE]);
+    let board = gen_board(words, room.variant, room.settings.board_dim, room.settings.card_mix)?;
+    // everything about the old game goes
+    let mut new_room = CodenamesRoom::new(room.players.clone(), board, room.variant, Arc::clone(&room.word_lists));
+    new_room.settings = room.settings.clone();
+    new_room.chat_history = std::mem::take(&mut room.chat_history);
+    *room = new_room;
+    Ok(())
+}
+
+/// Whether either team has been given the word as a clue this game, ignoring case
+fn clue_given_before(word : &str, room : &CodenamesRoom) -> bool {
+    let word = word.trim().to_lowercase();
+    room.clues.iter().any(|(_, clue)| clue.clue.trim().to_lowercase() == word)
+}
+
+/// The clue the team is guessing on with the guesses it has left, followed by
+/// every clue given this game in order
+fn clue_recap(room : &CodenamesRoom) -> String {
+    let mut out = match (room.phase, &room.clue) {
+        (TurnPhase::Guessing, Some(clue)) => {
+            let left = guesses_remaining(room).map_or("unlimited".to_string(), |left| left.to_string());
+            format!("Current clue: {}, guesses left: {}\r\n", clue.describe(), left)
+        },
+        _ => "No clue has been given this turn yet\r\n".to_string()
+    };
+    if !room.clues.is_empty() {
+        out += "Clues so far:\r\n";
+        for (team, clue) in &room.clues {
+            out += &format!("  {}: {}\r\n", team, clue.describe());
+        }
     }
+    out
 }
 
-pub fn codenames_disconnect(addr : SocketAddr,
-                            game_rooms : &mut HashMap<i32, GameRoom>,
-                            user_state_map : &mut HashMap<SocketAddr, User>) {
-    // remove from lobbies if in any, notify any users affected that this user has left
-    // TODO: slow
-    for room in game_rooms.values_mut() {
-        if let Some(room) = &mut room.impl_room {
-            if room.players.contains(&addr) {
-                // TODO: unwrap could be wierd?
-                // TODO: if last person, delete room too
-                broadcast_chat_everyone(
-                    format!("{} has left the game!",
-                            user_state_map.get(&addr).unwrap().user_name),
-                    room, user_state_map);
-                room.players.remove(&addr);
+/// Counts a vote from the voter to restart the game in progress, once most
+/// of the room has voted a new board is dealt and red starts again
+fn vote_to_restart(voter : SocketAddr, room : &mut CodenamesRoom, user_state_map : &mut HashMap<SocketAddr, User>,
+                   word_lists : &WordLists, now : Instant) {
+    room.restart_votes.retain(|_, voted| now.duration_since(*voted) < KICK_VOTE_WINDOW);
+    if room.restart_votes.contains_key(&voter) {
+        send_chat(voter, "You've already voted to restart the game\r\n".to_string(), user_state_map);
+        return;
+    }
+    room.restart_votes.insert(voter, now);
+    let (count, needed) = (room.restart_votes.len(), room.players.len() / 2 + 1);
+    let voter_name = user_state_map.get(&voter).map_or("", |u| &u.user_name).to_string();
+    broadcast_chat_everyone(format!("{} voted to restart the game ({}/{})", voter_name, count, needed),
+                            room, user_state_map);
+    if count >= needed {
+        // seats held for dropped players carry over to the new game
+        let reserved = std::mem::take(&mut room.reserved);
+        match deal_new_game(room, word_lists) {
+            Ok(()) => {
+                room.reserved = reserved;
+                room.state = CodenamesState::RedTurn;
+                room.reset_turn_clock(now);
+                broadcast_chat_everyone("The room voted to restart, a new board has been dealt".to_string(),
+                                        room, user_state_map);
+                refresh_prompt(room, user_state_map);
+            },
+            Err(e) => {
+                room.reserved = reserved;
+                room.restart_votes.clear();
+                broadcast_chat_everyone(format!("The game couldn't be restarted: {}", e), room, user_state_map);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Counts a vote from the voter to kick the named player out of the room,
+/// once most of the players in the room want them gone they're sent back to the lobby
+fn vote_to_kick(voter : SocketAddr, target_name : &str, room : &mut CodenamesRoom,
+                user_state_map : &mut HashMap<SocketAddr, User>, now : Instant) {
+    let target = room.players.iter()
+        .find(|addr| user_state_map.get(addr).is_some_and(|u| u.user_name == target_name))
+        .copied();
+    let target = match target {
+        Some(target) if target == voter => {
+            send_chat(voter, "You can't vote to kick yourself\r\n".to_string(), user_state_map);
+            return;
+        },
+        Some(target) => target,
+        None => {
+            send_chat(voter, format!("Nobody named {} is in this room\r\n", target_name), user_state_map);
+            return;
+        }
+    };
+    let votes = room.kick_votes.entry(target).or_default();
+    votes.retain(|_, voted| now.duration_since(*voted) < KICK_VOTE_WINDOW);
+    if votes.contains_key(&voter) {
+        send_chat(voter, format!("You've already voted to kick {}\r\n", target_name), user_state_map);
+        return;
+    }
+    votes.insert(voter, now);
+    let (count, needed) = (votes.len(), room.players.len() / 2 + 1);
+    let voter_name = user_state_map.get(&voter).map_or("", |u| &u.user_name).to_string();
+    broadcast_chat_everyone(format!("{} voted to kick {} ({}/{})", voter_name, target_name, count, needed),
+                            room, user_state_map);
+    if count >= needed {
+        leave_room(target, room);
+        broadcast_chat_everyone(format!("{} was voted out of the room", target_name), room, user_state_map);
+        if let Some(user) = user_state_map.get_mut(&target) {
+            user.state = ServerState::Kicked;
+            user.game_room_key = None;
+            user.player = None;
+        }
+    }
+}
+
+/// Takes the player out of the room along with anything that only applies while they're in it
+fn leave_room(addr : SocketAddr, room : &mut CodenamesRoom) {
+    room.players.remove(&addr);
+    // votes against them no longer matter and votes they cast stop counting
+    room.kick_votes.remove(&addr);
+    for votes in room.kick_votes.values_mut() {
+        votes.remove(&addr);
+    }
+    room.restart_votes.remove(&addr);
+    if room.pending_guess.as_ref().is_some_and(|(guesser, _)| *guesser == addr) {
+        room.pending_guess = None;
+    }
+}
+
+impl Game for CodenamesRoom {
+    fn prompt(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+              prompts : &Prompts) -> Option<String> {
+        codenames_prompt(user_addr, user_state_map, self, prompts)
+    }
+
+    fn logic(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+             line : &Option<String>) -> Option<GameResult> {
+        let word_lists = Arc::clone(&self.word_lists);
+        codenames_logic(user_addr, user_state_map, self, &word_lists, line)
+    }
+
+    fn disconnect(&mut self, addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>) {
+        // TODO: unwrap could be wierd?
+        let user = user_state_map.get(&addr).unwrap();
+        let user_name = user.user_name.to_string();
+        leave_room(addr, self);
+        let player = user.player.as_ref().unwrap_or_default();
+        // observers have no seat to hold, they can come back and watch whenever
+        if self.in_progress() && player.role != CodenamesRole::Observer {
+            // hold the seat in case they reconnect
+            self.reserved.insert(user_name.to_string(), (player.team, player.role, Instant::now()));
+            broadcast_chat_everyone(
+                format!("{} has disconnected, their seat is held for {} seconds",
+                        user_name, RECONNECT_GRACE.as_secs()),
+                self, user_state_map);
+        } else {
+            broadcast_chat_everyone(format!("{} has left the game!", user_name),
+                                    self, user_state_map);
+        }
+    }
+
+    /// The roles table shows the new name too
+    fn rename(&mut self, old_name : &str, new_name : &str, user_state_map : &mut HashMap<SocketAddr, User>) {
+        broadcast_chat_everyone(format!("{} is now known as {}", old_name, new_name), self, user_state_map);
+        refresh_prompt(self, user_state_map);
+    }
+
+    fn players(&self) -> &HashSet<SocketAddr> {
+        &self.players
+    }
+
+    fn status(&self) -> &'static str {
+        CodenamesRoom::status(self)
+    }
+
+    fn has_ended(&self) -> bool {
+        CodenamesRoom::has_ended(self)
+    }
+
+    fn holds_seats(&self) -> bool {
+        self.in_progress() && !self.reserved.is_empty()
+    }
+
+    fn has_space_for(&self, user_name : &str, max_players : usize) -> bool {
+        CodenamesRoom::has_space_for(self, user_name, max_players)
+    }
+
+    fn listing_tag(&self) -> Option<String> {
+        (self.variant != RoomVariant::Standard).then(|| self.variant.to_string())
+    }
+
+    fn has_reserved_seat(&self, user_name : &str) -> bool {
+        CodenamesRoom::has_reserved_seat(self, user_name)
+    }
+
+    fn joins_as_observer(&self, user_name : &str) -> bool {
+        self.in_progress() && !self.has_reserved_seat(user_name)
+    }
+
+    /// Someone with a seat held gets it back, someone only coming to watch
+    /// leaves any seat they have alone
+    fn admit(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>) {
+        let user_state = get_user_state(user_state_map, user_addr);
+        if user_state.player.as_ref().is_some_and(CodenamesPlayer::is_observer) {
+            return;
+        }
+        let user_name = user_state.user_name.to_string();
+        let seat = take_reserved_seat(&user_name, self, user_state_map);
+        if seat.is_some() {
+            get_user_state(user_state_map, user_addr).player = seat;
+        }
+    }
+
+    fn resume(&mut self, now : Instant) {
+        CodenamesRoom::resume(self, now)
+    }
+
+    fn release_expired_seats(&mut self, user_state_map : &mut HashMap<SocketAddr, User>, now : Instant) -> bool {
+        release_expired_seats(self, user_state_map, now)
+    }
+
+    fn expire_turn_timer(&mut self, user_state_map : &mut HashMap<SocketAddr, User>, now : Instant) -> bool {
+        expire_turn_timer(self, user_state_map, now)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Gives a reconnecting user back the team and role they had when they dropped
+fn take_reserved_seat(user_name : &str, room : &mut CodenamesRoom,
+                      user_state_map : &mut HashMap<SocketAddr, User>) -> Option<CodenamesPlayer> {
+    if !room.has_reserved_seat(user_name) {
+        return None;
+    }
+    let (team, role, _) = room.reserved.remove(user_name)?;
+    broadcast_chat_everyone(format!("{} has rejoined the game!", user_name), room, user_state_map);
+    room.resume(Instant::now());
+    refresh_prompt(room, user_state_map);
+    Some(CodenamesPlayer::new(team, role))
+}
+
+/// Passes the turn of the team that hasn't acted before its turn timer ran out
+/// returns whether it passed one
+fn expire_turn_timer(room : &mut CodenamesRoom, user_state_map : &mut HashMap<SocketAddr, User>,
+                     now : Instant) -> bool {
+    let team = match room.state {
+        CodenamesState::RedTurn => CodenamesTeam::Red,
+        CodenamesState::BlueTurn => CodenamesTeam::Blue,
+        _ => return false
+    };
+    if room.turn_deadline.is_none_or(|deadline| now < deadline) {
+        return false;
+    }
+    broadcast_chat_everyone(format!("{} ran out of time", team), room, user_state_map);
+    end_turn(team, room);
+    room.reset_turn_clock(now);
+    if room.has_ended() {
+        announce_game_end(room, user_state_map);
+    } else {
+        refresh_prompt(room, user_state_map);
+    }
+    true
+}
+
+/// Gives up the seats of players who haven't reconnected within the grace period
+/// returns whether any were given up
+fn release_expired_seats(room : &mut CodenamesRoom, user_state_map : &mut HashMap<SocketAddr, User>,
+                         now : Instant) -> bool {
+    if room.paused {
+        return false;
+    }
+    let expired : Vec<String> = room.reserved.iter()
+        .filter(|(_, (_, _, dropped))| now.duration_since(*dropped) >= RECONNECT_GRACE)
+        .map(|(user_name, _)| user_name.to_string())
+        .collect();
+    let released = !expired.is_empty();
+    for user_name in expired {
+        room.reserved.remove(&user_name);
+        broadcast_chat_everyone(format!("{} has left the game!", user_name),
+                                room, user_state_map);
+    }
+    released
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::game::{GameServerState, SessionStats};
+    use crate::game::tests::{create_room, login, new_addr, send, start_game};
+    use super::*;
+
+    #[test]
+    fn double_agent_board_mix() {
+        let words = load_word_list(None).unwrap();
+        let board = gen_board_with_rng(&words, RoomVariant::DoubleAgent, DEFAULT_BOARD_DIM, None, &mut StdRng::seed_from_u64(7)).unwrap();
+        let cards : Vec<&CodenamesCard> = board.iter().flatten().collect();
+        for (card_type, count) in [(CodenamesCardType::RedAgent, 9), (CodenamesCardType::BlueAgent, 8),
+                                   (CodenamesCardType::Bystander, 5), (CodenamesCardType::DoubleAgent, 2),
+                                   (CodenamesCardType::Assassin, 1)] {
+            assert_eq!(cards.iter().filter(|c| c.card_type == card_type).count(), count);
+        }
+    }
+
+    #[test]
+    fn double_agents_score_for_the_guessing_team() {
+        let mut game_server_state = GameServerState::new();
+        game_server_state.create_room_with_variant("Double Agents", RoomVariant::DoubleAgent);
+        let players = start_game(&mut game_server_state, &[]);
+        let double_agent = find_word(room(&game_server_state), CodenamesCardType::DoubleAgent);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", double_agent));
+        assert_eq!((room(&game_server_state).red_score, room(&game_server_state).blue_score), (1, 0));
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+
+        send(&mut game_server_state, players[1], "!!");
+        let double_agent = find_word(room(&game_server_state), CodenamesCardType::DoubleAgent);
+        send(&mut game_server_state, players[2], "fruit,2");
+        send(&mut game_server_state, players[3], &format!("!{}", double_agent));
+        assert_eq!((room(&game_server_state).red_score, room(&game_server_state).blue_score), (1, 1));
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+    }
+
+    #[test]
+    fn game_end_prompts_everyone_once() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        for addr in &players {
+            let _ = game_server_state.prompt_for(*addr);
+        }
+        // a stale value left over from before mustn't hide the end of the game
+        game_server_state.user_state.get_mut(&players[3]).unwrap()
+            .player.as_mut().unwrap().state_prompted = Some(CodenamesState::GameEnd);
+        let assassin = find_word(room(&game_server_state), CodenamesCardType::Assassin);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", assassin));
+        for addr in &players {
+            assert!(game_server_state.prompt_for(*addr).unwrap().contains("The game has ended"));
+            assert!(game_server_state.prompt_for(*addr).is_none_or(|p| !p.contains("The game has ended")));
+        }
+    }
+
+    #[test]
+    fn game_result_reaches_everyone() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let assassin = find_word(room(&game_server_state), CodenamesCardType::Assassin);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", assassin));
+        for addr in &players {
+            let chat = chat_queue(&game_server_state, *addr);
+            assert_eq!(chat[chat.len() - 2..], ["* Blue team wins!".to_string(),
+                       "* The Red team found the assassin. Final score 0-0 (R-B)".to_string()]);
+        }
+        // input after the game is chat, the room stays until everyone has left
+        send(&mut game_server_state, players[2], "bye");
+        assert!(game_server_state.game_rooms.contains_key(&1));
+        let prompt = game_server_state.prompt_for(players[3]).unwrap();
+        assert!(prompt.contains("The Red team found the assassin") && prompt.contains("carol: bye"));
+        for addr in &players {
+            assert!(game_server_state.game_rooms.contains_key(&1));
+            game_server_state.remove_client(*addr);
+        }
+        assert!(!game_server_state.game_rooms.contains_key(&1));
+    }
+
+    #[test]
+    fn finished_games_count_towards_session_stats() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let erin = join_room(&mut game_server_state, "erin");
+        let assassin = find_word(room(&game_server_state), CodenamesCardType::Assassin);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", assassin));
+
+        let stats = |game_server_state : &GameServerState, addr| game_server_state.user_state[&addr].stats;
+        assert_eq!(stats(&game_server_state, players[1]), SessionStats { played: 1, wins: 0, losses: 1 });
+        assert_eq!(stats(&game_server_state, players[2]), SessionStats { played: 1, wins: 1, losses: 0 });
+        // watching a game isn't playing it
+        assert_eq!(stats(&game_server_state, erin), SessionStats::default());
+
+        send(&mut game_server_state, players[2], "stats");
+        assert!(game_server_state.user_state[&players[2]].outbound
+            .ends_with(b"Games this session: 1 played, 1 won, 0 lost\r\n"));
+        assert!(!room(&game_server_state).chat_history.iter().any(|(line, _)| line.contains("stats")));
+    }
+
+    #[test]
+    fn reveal_and_new_game_after_the_end() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set size 4"]);
+        let assassin = find_word(room(&game_server_state), CodenamesCardType::Assassin);
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", assassin));
+
+        send(&mut game_server_state, players[3], "reveal");
+        let revealed = last_chat(&game_server_state, players[3]);
+        assert!(revealed.starts_with("Every card's type"));
+        assert!(revealed.contains(&format!("X {:^14}A", assassin)));
+        assert!(revealed.contains(&format!("  {:^14}R", red_word)));
+
+        send(&mut game_server_state, players[1], "newgame");
+        let new_room = room(&game_server_state);
+        assert_eq!(new_room.state, CodenamesState::WaitingToStart);
+        assert_eq!(new_room.settings.board_dim, 4);
+        assert_eq!(new_room.board.len(), 4);
+        assert!(new_room.board.iter().flatten().all(|card| !card.flipped));
+        assert_eq!(last_chat(&game_server_state, players[2]), "* bob started a new game");
+        // everyone kept their seats and can start straight away
+        send(&mut game_server_state, players[0], "start");
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+    }
+
+    #[test]
+    fn card_mix_scales_with_board_size() {
+        let words = load_word_list(None).unwrap();
+        for (dim, red, blue, bystanders) in [(4, 6, 5, 4), (5, 9, 8, 7), (6, 13, 12, 10)] {
+            let board = gen_board(&words, RoomVariant::Standard, dim, None).unwrap();
+            assert_eq!((board.len(), board[0].len()), (dim, dim));
+            let cards : Vec<&CodenamesCard> = board.iter().flatten().collect();
+            for (card_type, count) in [(CodenamesCardType::RedAgent, red), (CodenamesCardType::BlueAgent, blue),
+                                       (CodenamesCardType::Bystander, bystanders), (CodenamesCardType::Assassin, 1)] {
+                assert_eq!(cards.iter().filter(|c| c.card_type == card_type).count(), count);
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_pick_their_own_card_mix() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set cards 9/8/7/2", "set cards 9/8/6/2",
+                                                           "set size 6", "set training on"]);
+        assert_eq!(room(&game_server_state).settings.board_dim, DEFAULT_BOARD_DIM);
+        let cards : Vec<&CodenamesCard> = room(&game_server_state).board.iter().flatten().collect();
+        for (card_type, count) in [(CodenamesCardType::RedAgent, 9), (CodenamesCardType::BlueAgent, 8),
+                                   (CodenamesCardType::Bystander, 6), (CodenamesCardType::Assassin, 2)] {
+            assert_eq!(cards.iter().filter(|c| c.card_type == card_type).count(), count);
+        }
+        send(&mut game_server_state, players[1], "risk");
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   "25 unflipped cards: 8 enemy agents, 6 bystanders, 2 assassins\r\n");
+
+        let mix = CardMix::parse("12/12/1/0").unwrap();
+        assert!(RoomVariant::DoubleAgent.card_counts(25, Some(mix)).is_err());
+        assert_eq!(RoomVariant::Standard.card_counts(25, Some(mix)).unwrap()[4], (CodenamesCardType::Assassin, 0));
+        let mut settings = CodenamesSettings::default();
+        for value in ["9/8/8", "0/17/7/1", "9/8/seven/1"] {
+            assert!(settings.apply("cards", value).is_err());
+        }
+    }
+
+    #[test]
+    fn board_size_setting() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set size 7", "set size 6"]);
+        assert_eq!(last_chat(&game_server_state, players[0]), "* alice Started the Game!");
+        let room = room(&game_server_state);
+        assert_eq!(room.settings.board_dim, 6);
+        assert_eq!(room.board.len(), 6);
+        assert_eq!((room.target_score(CodenamesTeam::Red), room.target_score(CodenamesTeam::Blue)), (13, 12));
+        assert_aligned(&room.get_board(CodenamesTeam::Red, CodenamesRole::Spymaster),
+                       line_width(LONGEST_CODENAMES_WORD, 6));
+    }
+
+    #[test]
+    fn guesses_ignore_case_and_spaces() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let red_words : Vec<String> = room(&game_server_state).board.iter().flatten()
+            .filter(|card| card.card_type == CodenamesCardType::RedAgent)
+            .map(|card| card.word.to_string())
+            .collect();
+        send(&mut game_server_state, players[0], "fruit,unlimited");
+        send(&mut game_server_state, players[1], &format!("!{}", red_words[0].to_uppercase()));
+        send(&mut game_server_state, players[1], &format!("!  {}  \r\n", red_words[1].to_lowercase()));
+        assert!(is_flipped(&game_server_state, &red_words[0]));
+        assert!(is_flipped(&game_server_state, &red_words[1]));
+        assert_eq!(room(&game_server_state).red_score, 2);
+        // the board keeps the word list's casing
+        let board = room(&game_server_state).get_board(CodenamesTeam::Red, CodenamesRole::Teammate);
+        assert!(board.contains(red_words[0].as_str()));
+    }
+
+    #[test]
+    fn words_differing_in_case_are_dealt_once() {
+        let words : Vec<String> = (0..24).map(|i| format!("word{}", i))
+            .chain((0..24).map(|i| format!("WORD{}", i)))
+            .collect();
+        assert_eq!(gen_board(&words, RoomVariant::Standard, DEFAULT_BOARD_DIM, None).err().unwrap(),
+                   "Only 24 distinct words are available, a board needs 25");
+    }
+
+    #[test]
+    fn status_shows_scores_turn_and_clue() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[3], "status");
+        assert_eq!(last_chat(&game_server_state, players[3]),
+                   "Red 0 / Blue 0 - Red's turn, 0 guesses made, no clue yet\r\n");
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[0], "ocean,2");
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], "score");
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   "Red 1 / Blue 0 - Red's turn, 1 guess made, clue: ocean 2\r\n");
+        // scores only, nothing about the cards
+        assert!(!last_chat(&game_server_state, players[1]).contains(&find_word(room(&game_server_state),
+                                                                               CodenamesCardType::Assassin)));
+    }
+
+    #[test]
+    fn remaining_agents_per_team() {
+        let mut game_server_state = GameServerState::new();
+        game_server_state.create_room_with_variant("Double Agents", RoomVariant::DoubleAgent);
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[1], "remaining");
+        assert_eq!(last_chat(&game_server_state, players[1]), "Agents: Red 9 left, Blue 8 left\r\n");
+
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[3], "remaining");
+        assert_eq!(last_chat(&game_server_state, players[3]), "Agents: Blue 8 left, Red 8 left\r\n");
+        // double agents count towards the score without being one of the team's agents
+        let double_agent = find_word(room(&game_server_state), CodenamesCardType::DoubleAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", double_agent));
+        send(&mut game_server_state, players[1], "remaining");
+        assert_eq!(last_chat(&game_server_state, players[1]), "Agents: Red 8 left (7 more to win), Blue 8 left\r\n");
+    }
+
+    #[test]
+    fn spymasters_can_pass_instead_of_a_clue() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "pass");
+        assert_eq!(last_chat(&game_server_state, players[3]), "* Red's spymaster passed.");
+        let room_now = room(&game_server_state);
+        assert_eq!((room_now.state, room_now.phase, room_now.guesses), (CodenamesState::BlueTurn, TurnPhase::AwaitingClue, 0));
+        assert!(room_now.clue.is_none());
+
+        // once the team is guessing it's too late
+        let blue_word = find_word(room(&game_server_state), CodenamesCardType::BlueAgent);
+        send(&mut game_server_state, players[2], "fruit,2");
+        send(&mut game_server_state, players[3], &format!("!{}", blue_word));
+        send(&mut game_server_state, players[2], "pass");
+        assert_eq!(last_chat(&game_server_state, players[2]),
+                   "Your team is already guessing on your clue, it's too late to pass\r\n");
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+        // the next turn starts clean after a pass too
+        send(&mut game_server_state, players[3], "!!");
+        send(&mut game_server_state, players[0], "pass");
+        let room_now = room(&game_server_state);
+        assert_eq!((room_now.state, room_now.guesses), (CodenamesState::BlueTurn, 0));
+        assert!(room_now.clue.is_none());
+    }
+
+    #[test]
+    fn board_is_sent_again_on_request() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,2");
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        let guesses = room(&game_server_state).guesses;
+        send(&mut game_server_state, players[0], "board");
+        send(&mut game_server_state, players[1], "board");
+        let room = room(&game_server_state);
+        assert_eq!(last_chat(&game_server_state, players[0]), room.get_board(CodenamesTeam::Red, CodenamesRole::Spymaster));
+        assert_eq!(last_chat(&game_server_state, players[1]), room.get_board(CodenamesTeam::Red, CodenamesRole::Teammate));
+        // nothing about the turn changes
+        assert_eq!((room.state, room.phase, room.guesses), (CodenamesState::RedTurn, TurnPhase::Guessing, guesses));
+        assert!(room.clue.is_some());
+    }
+
+    #[test]
+    fn clues_are_recapped_on_request() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[1], "clue");
+        assert_eq!(last_chat(&game_server_state, players[1]), "No clue has been given this turn yet\r\n");
+        send(&mut game_server_state, players[0], "fruit,2");
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], "/clue");
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   "Current clue: fruit, 2, guesses left: 2\r\nClues so far:\r\n  Red: fruit, 2\r\n");
+        send(&mut game_server_state, players[1], "!!");
+        send(&mut game_server_state, players[2], "ocean,0");
+        // clues are public, the other team and spymasters can ask too
+        for addr in [players[0], players[3]] {
+            send(&mut game_server_state, addr, "clue");
+            assert_eq!(last_chat(&game_server_state, addr),
+                       "Current clue: ocean, 0, guesses left: unlimited\r\nClues so far:\r\n  \
+                        Red: fruit, 2\r\n  Blue: ocean, 0\r\n");
+        }
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+    }
+
+    #[test]
+    fn chat_lines_end_in_one_line_break() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        for addr in &players {
+            let _ = game_server_state.prompt_for(*addr);
+        }
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[0], "tree,1");
+        // the clue goes out as a broadcast, the refusal to its sender
+        assert!(game_server_state.prompt_for(players[2]).unwrap()
+            .starts_with("* Spymaster Clue: fruit, 2\r\nRed Team's Turn:\r\n"));
+        assert!(game_server_state.prompt_for(players[0]).unwrap()
+            .starts_with("* Spymaster Clue: fruit, 2\r\nYou've already given a clue this turn\r\nRed Team's Turn:\r\n"));
+    }
+
+    #[test]
+    fn one_clue_per_turn() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        assert_eq!(last_chat(&game_server_state, players[1]), "Wait for your spymaster's clue\r\n");
+        assert!(!is_flipped(&game_server_state, &red_word));
+
+        send(&mut game_server_state, players[0], "fruit,1");
+        send(&mut game_server_state, players[0], "tree,3");
+        assert_eq!(last_chat(&game_server_state, players[0]), "You've already given a clue this turn\r\n");
+        assert_eq!(room(&game_server_state).clue.as_ref().unwrap().clue, "fruit");
+
+        // the next team's spymaster gets their own clue
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], "!!");
+        send(&mut game_server_state, players[2], "tree,3");
+        assert_eq!(room(&game_server_state).clue.as_ref().unwrap().clue, "tree");
+    }
+
+    #[test]
+    fn clues_like_board_words_are_told_apart() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert!(clue_resembles("OCEANS", "Ocean", DEFAULT_CLUE_DISTANCE));
+        assert!(clue_resembles("firework", "FIRE", DEFAULT_CLUE_DISTANCE));
+        assert!(clue_resembles("backfire", "FIRE", DEFAULT_CLUE_DISTANCE));
+        assert!(clue_resembles("swam", "SWIM", DEFAULT_CLUE_DISTANCE));
+        assert!(!clue_resembles("sea", "OCEAN", DEFAULT_CLUE_DISTANCE));
+        // too short to count as a root
+        assert!(!clue_resembles("at", "BATTERY", DEFAULT_CLUE_DISTANCE));
+        assert!(!clue_resembles("bread", "BEARD", DEFAULT_CLUE_DISTANCE));
+        assert!(clue_resembles("bread", "BEARD", 3));
+    }
+
+    #[test]
+    fn clues_like_board_words_warn_the_spymaster() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let room_mut = game_server_state.game_rooms.get_mut(&1).unwrap().codenames_mut().unwrap();
+        room_mut.board[0][0].word = "OCEAN".to_string();
+        send(&mut game_server_state, players[0], "oceans,2");
+        assert_eq!(last_chat(&game_server_state, players[0]),
+                   "Careful, oceans looks a lot like OCEAN on the board, the other players might not accept it\r\n");
+        // the clue is given all the same, and only the spymaster hears about it
+        assert_eq!(room(&game_server_state).phase, TurnPhase::Guessing);
+        assert_eq!(last_chat(&game_server_state, players[1]), "* Spymaster Clue: oceans, 2");
+
+        // rooms can turn the check off
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set similar 9", "set similar off"]);
+        let room_mut = game_server_state.game_rooms.get_mut(&1).unwrap().codenames_mut().unwrap();
+        room_mut.board[0][0].word = "OCEAN".to_string();
+        assert_eq!(room_mut.settings.clue_distance, None);
+        send(&mut game_server_state, players[0], "oceans,2");
+        assert_eq!(last_chat(&game_server_state, players[0]), "* Spymaster Clue: oceans, 2");
+    }
+
+    #[test]
+    fn cards_can_be_guessed_by_coordinate() {
+        for coords in [true, false] {
+            let mut game_server_state = GameServerState::new();
+            let players = start_game(&mut game_server_state, &[if coords { "set coords on" } else { "set coords off" }]);
+            let room_mut = game_server_state.game_rooms.get_mut(&1).unwrap().codenames_mut().unwrap();
+            for (row, column) in [(0, 0), (0, 2), (2, 1), (4, 4)] {
+                room_mut.board[row][column].card_type = CodenamesCardType::RedAgent;
+            }
+            // a word that could be taken for the first card's coordinate
+            room_mut.board[4][4].word = "A1".to_string();
+            send(&mut game_server_state, players[0], "fruit,4");
+            send(&mut game_server_state, players[1], "!b3");
+            assert!(room(&game_server_state).board[2][1].flipped);
+            // the coordinate only wins when the board shows coordinates
+            send(&mut game_server_state, players[1], "!A1");
+            assert_eq!(room(&game_server_state).board[0][0].flipped, coords);
+            assert_eq!(room(&game_server_state).board[4][4].flipped, !coords);
+            send(&mut game_server_state, players[1], "!F1");
+            assert_eq!(last_chat(&game_server_state, players[1]), "* F1 is not a valid card name to guess");
+            let word = room(&game_server_state).board[0][2].word.to_string();
+            send(&mut game_server_state, players[1], &format!("!{}", word));
+            assert!(room(&game_server_state).board[0][2].flipped);
+        }
+    }
+
+    #[test]
+    fn malformed_clues_are_explained() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        for (clue, reply) in [("New,York,2", "Clues have to be a single word, give your clue as 'clue,number'\r\n"),
+                              ("fruit", "fruit is missing the number of cards, give your clue as 'clue,number'\r\n"),
+                              ("fruit,lots", "lots isn't a number of cards, use 0 or more or 'unlimited'\r\n"),
+                              ("fruit,-1", "-1 isn't a number of cards, use 0 or more or 'unlimited'\r\n"),
+                              (" ,2", "The clue is missing its word, give your clue as 'clue,number'\r\n")] {
+            send(&mut game_server_state, players[0], clue);
+            assert_eq!(last_chat(&game_server_state, players[0]), reply);
+            assert_eq!(room(&game_server_state).phase, TurnPhase::AwaitingClue);
+            assert!(room(&game_server_state).clues.is_empty());
+        }
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[0], "New,York,2");
+        assert_eq!(last_chat(&game_server_state, players[0]), "You've already given a clue this turn\r\n");
+    }
+
+    #[test]
+    fn clue_words_are_trimmed() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], ",2");
+        assert_eq!(last_chat(&game_server_state, players[0]),
+                   "The clue is missing its word, give your clue as 'clue,number'\r\n");
+        assert_eq!(room(&game_server_state).phase, TurnPhase::AwaitingClue);
+        send(&mut game_server_state, players[0], "fruit , 2");
+        assert_eq!(last_chat(&game_server_state, players[1]), "* Spymaster Clue: fruit, 2");
+        let room_now = room(&game_server_state);
+        assert_eq!(room_now.clue.as_ref().unwrap().clue, "fruit");
+        assert_eq!(room_now.history[0], "Red spymaster clue: fruit, 2");
+    }
+
+    #[test]
+    fn lockout_turns_down_repeated_clues() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set lockout on"]);
+        send(&mut game_server_state, players[0], "fruit,1");
+        let bystander = find_word(room(&game_server_state), CodenamesCardType::Bystander);
+        send(&mut game_server_state, players[1], &format!("!{}", bystander));
+        // the other team can't use it either, in any case
+        send(&mut game_server_state, players[2], "FRUIT,2");
+        assert_eq!(last_chat(&game_server_state, players[2]),
+                   "FRUIT has already been a clue this game, pick another\r\n");
+        assert_eq!(room(&game_server_state).phase, TurnPhase::AwaitingClue);
+        send(&mut game_server_state, players[2], "ocean,2");
+        assert_eq!(room(&game_server_state).phase, TurnPhase::Guessing);
+
+        // off by default
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        assert!(!room(&game_server_state).settings.lockout);
+        send(&mut game_server_state, players[0], "fruit,1");
+        let bystander = find_word(room(&game_server_state), CodenamesCardType::Bystander);
+        send(&mut game_server_state, players[1], &format!("!{}", bystander));
+        send(&mut game_server_state, players[2], "fruit,2");
+        assert_eq!(room(&game_server_state).phase, TurnPhase::Guessing);
+    }
+
+    #[test]
+    fn vote_to_kick() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "/kick alice");
+        assert_eq!(last_chat(&game_server_state, players[0]), "You can't vote to kick yourself\r\n");
+        send(&mut game_server_state, players[0], "/kick erin");
+        assert_eq!(last_chat(&game_server_state, players[0]), "Nobody named erin is in this room\r\n");
+
+        send(&mut game_server_state, players[0], "/kick dave");
+        send(&mut game_server_state, players[0], "/kick dave");
+        assert_eq!(last_chat(&game_server_state, players[0]), "You've already voted to kick dave\r\n");
+        send(&mut game_server_state, players[1], "/kick dave");
+        assert_eq!(last_chat(&game_server_state, players[2]), "* bob voted to kick dave (2/3)");
+        assert_eq!(game_server_state.user_state[&players[3]].state, ServerState::InRoom);
+
+        send(&mut game_server_state, players[2], "/kick dave");
+        assert_eq!(last_chat(&game_server_state, players[0]), "* dave was voted out of the room");
+        assert!(!room(&game_server_state).players.contains(&players[3]));
+        // kicked players aren't held a seat like players that dropped
+        assert!(!room(&game_server_state).has_reserved_seat("dave"));
+        assert_eq!(game_server_state.prompt_for(players[3]).unwrap(), "You were voted out of the room\r\n");
+        let _ = game_server_state.handle_input(players[3], None);
+        assert_eq!(game_server_state.user_state[&players[3]].state, ServerState::LobbySelection);
+    }
+
+    #[test]
+    fn stale_kick_votes_expire() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let stale = Instant::now().checked_sub(KICK_VOTE_WINDOW).unwrap();
+        let room_mut = game_server_state.game_rooms.get_mut(&1).unwrap().codenames_mut().unwrap();
+        room_mut.kick_votes.entry(players[3]).or_default().extend([(players[0], stale), (players[1], stale)]);
+        send(&mut game_server_state, players[2], "/kick dave");
+        assert_eq!(last_chat(&game_server_state, players[2]), "* carol voted to kick dave (1/3)");
+        assert_eq!(game_server_state.user_state[&players[3]].state, ServerState::InRoom);
+    }
+
+    #[test]
+    fn majority_vote_restarts_the_game() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], "!!");
+        let old_board : Vec<String> = room(&game_server_state).board.iter().flatten().map(|c| c.word.clone()).collect();
+
+        // a stale vote doesn't count towards the three needed
+        let stale = Instant::now().checked_sub(KICK_VOTE_WINDOW).unwrap();
+        let room_mut = game_server_state.game_rooms.get_mut(&1).unwrap().codenames_mut().unwrap();
+        room_mut.restart_votes.insert(players[3], stale);
+        send(&mut game_server_state, players[0], "/restart");
+        send(&mut game_server_state, players[0], "/restart");
+        assert_eq!(last_chat(&game_server_state, players[0]), "You've already voted to restart the game\r\n");
+        send(&mut game_server_state, players[1], "/restart");
+        assert_eq!(last_chat(&game_server_state, players[3]), "* bob voted to restart the game (2/3)");
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+        send(&mut game_server_state, players[2], "/restart");
+        assert_eq!(last_chat(&game_server_state, players[3]), "* The room voted to restart, a new board has been dealt");
+
+        let room = room(&game_server_state);
+        assert_eq!(room.state, CodenamesState::RedTurn);
+        assert_eq!((room.red_score, room.blue_score, room.guesses), (0, 0, 0));
+        assert!(room.clue.is_none() && room.guess_log.is_empty() && room.restart_votes.is_empty());
+        assert!(room.board.iter().flatten().all(|c| !c.flipped));
+        assert_ne!(room.board.iter().flatten().map(|c| c.word.clone()).collect::<Vec<String>>(), old_board);
+        // teams and roles are kept
+        let player = game_server_state.user_state[&players[2]].player.as_ref().unwrap();
+        assert_eq!((player.team, player.role), (CodenamesTeam::Blue, CodenamesRole::Spymaster));
+        assert!(game_server_state.prompt_for(players[1]).unwrap().contains("Red Team's Turn"));
+    }
+
+    fn chat_queue(game_server_state : &GameServerState, addr : SocketAddr) -> Vec<String> {
+        game_server_state.user_state[&addr].player.as_ref().unwrap().chat_queue.iter().cloned().collect()
+    }
+
+    #[test]
+    fn spymasters_get_a_legend_each_turn() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let prompts : Vec<String> = players.iter().map(|addr| game_server_state.prompt_for(*addr).unwrap()).collect();
+        assert!(prompts[0].contains("Legend: R your agent, B enemy agent, A assassin, blank bystander"));
+        assert!(prompts[2].contains("Legend: B your agent, R enemy agent"));
+        // teammates don't see the types it explains
+        assert!(!prompts[1].contains("Legend") && !prompts[3].contains("Legend"));
+        // the prompts while the team guesses leave it out
+        send(&mut game_server_state, players[0], "fruit,2");
+        assert!(!game_server_state.prompt_for(players[0]).unwrap().contains("Legend"));
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], "!!");
+        // and it's back for the next turn's spymaster
+        assert!(game_server_state.prompt_for(players[2]).unwrap().contains("Legend: B your agent"));
+    }
+
+    #[test]
+    fn a_clue_allows_one_extra_guess() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        assert_eq!(guesses_remaining(room(&game_server_state)), None);
+        send(&mut game_server_state, players[0], "fruit,2");
+        assert_eq!(guesses_remaining(room(&game_server_state)), Some(3));
+        for left in [2, 1] {
+            let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+            send(&mut game_server_state, players[1], &format!("!{}", red_word));
+            assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+            assert_eq!(guesses_remaining(room(&game_server_state)), Some(left));
+        }
+        // the third right guess is the extra one, the turn ends with it
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        let room_now = room(&game_server_state);
+        assert_eq!((room_now.state, room_now.red_score), (CodenamesState::BlueTurn, 3));
+        assert_eq!(guesses_remaining(room_now), None);
+    }
+
+    #[test]
+    fn guesses_that_miss_the_board_are_not_counted() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,1");
+        send(&mut game_server_state, players[1], "!notaword");
+        assert_eq!(last_chat(&game_server_state, players[1]), "* notaword is not a valid card name to guess");
+        assert_eq!(guesses_remaining(room(&game_server_state)), Some(2));
+        // the typo didn't eat into the budget, a right guess leaves the extra one
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+        assert_eq!(guesses_remaining(room(&game_server_state)), Some(1));
+    }
+
+    #[test]
+    fn flipped_cards_cannot_be_guessed_again() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,2");
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        assert_eq!(last_chat(&game_server_state, players[1]), format!("* {} has already been flipped", red_word));
+        let room_now = room(&game_server_state);
+        assert_eq!((room_now.red_score, room_now.guess_log.len()), (1, 1));
+        assert_eq!(guesses_remaining(room_now), Some(2));
+    }
+
+    #[test]
+    fn wrong_guesses_end_the_turn_with_guesses_left() {
+        for card_type in [CodenamesCardType::Bystander, CodenamesCardType::BlueAgent] {
+            let mut game_server_state = GameServerState::new();
+            let players = start_game(&mut game_server_state, &[]);
+            send(&mut game_server_state, players[0], "fruit,3");
+            let wrong_word = find_word(room(&game_server_state), card_type);
+            send(&mut game_server_state, players[1], &format!("!{}", wrong_word));
+            assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+        }
+        // unlimited clues have no budget, a wrong guess still ends them
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,unlimited");
+        assert_eq!(guesses_remaining(room(&game_server_state)), None);
+        let bystander = find_word(room(&game_server_state), CodenamesCardType::Bystander);
+        send(&mut game_server_state, players[1], &format!("!{}", bystander));
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+    }
+
+    #[test]
+    fn only_the_first_start_counts() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        // carol's start arrives on the poll after alice's
+        send(&mut game_server_state, players[2], "start");
+        assert_eq!(last_chat(&game_server_state, players[2]), "The game is already starting\r\n");
+        let started = chat_queue(&game_server_state, players[1]).iter()
+            .filter(|chat_line| chat_line.ends_with("Started the Game!"))
+            .count();
+        assert_eq!(started, 1);
+        // it's not taken as chat either
+        assert!(!chat_queue(&game_server_state, players[1]).iter().any(|chat_line| chat_line.contains("carol")));
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+
+        // once the game is underway it's just something said to the room
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[3], "start");
+        assert_ne!(last_chat(&game_server_state, players[3]), "The game is already starting\r\n");
+        assert!(last_chat(&game_server_state, players[2]).ends_with("start"));
+    }
+
+    #[test]
+    fn chat_flooding_is_dropped() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        for i in 0..CHAT_BURST + 3 {
+            send(&mut game_server_state, players[3], &format!("hello {}", i));
+        }
+        let received = chat_queue(&game_server_state, players[2]).iter()
+            .filter(|chat_line| chat_line.starts_with("dave: hello"))
+            .count();
+        assert_eq!(received, CHAT_BURST as usize);
+        let notices = chat_queue(&game_server_state, players[3]).iter()
+            .filter(|chat_line| *chat_line == "You're sending messages too fast\r\n")
+            .count();
+        assert_eq!(notices, 1);
+    }
+
+    #[test]
+    fn chat_limit_refills_over_time() {
+        let start = Instant::now();
+        let mut limit = ChatLimit::new();
+        for _ in 0..CHAT_BURST {
+            assert!(limit.take(start));
+        }
+        assert!(!limit.take(start));
+        assert!(limit.take(start + CHAT_REFILL));
+        assert!(!limit.take(start + CHAT_REFILL));
+        // waiting a long time only gives back a full burst
+        let later = start + CHAT_REFILL * 100;
+        for _ in 0..CHAT_BURST {
+            assert!(limit.take(later));
+        }
+        assert!(!limit.take(later));
+    }
+
+    #[test]
+    fn waiting_room_commands_can_be_shortened() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        game_server_state.handle_input(alice, None).unwrap();
+        send(&mut game_server_state, alice, "b");
+        send(&mut game_server_state, alice, "sm");
+        let player = game_server_state.user_state[&alice].player.as_ref().unwrap();
+        assert_eq!((player.team, player.role), (CodenamesTeam::Blue, CodenamesRole::Spymaster));
+        send(&mut game_server_state, alice, "tm");
+        send(&mut game_server_state, alice, "r");
+        let player = game_server_state.user_state[&alice].player.as_ref().unwrap();
+        assert_eq!((player.team, player.role), (CodenamesTeam::Red, CodenamesRole::Teammate));
+        send(&mut game_server_state, alice, "spy");
+        assert_eq!(game_server_state.user_state[&alice].player.as_ref().unwrap().role, CodenamesRole::Spymaster);
+
+        send(&mut game_server_state, alice, "te");
+        assert_eq!(last_chat(&game_server_state, alice), "'te' could be teammate, teams, type more of it\r\n");
+        assert!(room(&game_server_state).chat_history.is_empty());
+        // longer chat isn't mistaken for a command
+        send(&mut game_server_state, alice, "teams are ready");
+        assert_eq!(room(&game_server_state).chat_history.back().unwrap().0, "alice: teams are ready");
+    }
+
+    #[test]
+    fn chat_queue_drops_oldest() {
+        let mut player = CodenamesPlayer::default();
+        for i in 0..MAX_CHAT_QUEUE + 3 {
+            player.queue_chat(format!("message {}", i));
+        }
+        assert_eq!(player.chat_queue.len(), MAX_CHAT_QUEUE);
+        assert_eq!(player.chat_queue.front().unwrap(), "message 3");
+    }
+
+    #[test]
+    fn chat_history_replayed_on_join() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set chat guessers"]);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], "maybe apple?");
+        game_server_state.remove_client(players[2]);
+
+        // a spymaster coming back doesn't get the guessers' chat they weren't meant to see
+        let carol = new_addr();
+        login(&mut game_server_state, carol, "carol");
+        send(&mut game_server_state, carol, "y");
+        let _ = game_server_state.prompt_for(carol);
+        let replayed = chat_queue(&game_server_state, carol);
+        assert!(replayed.contains(&"* Spymaster Clue: fruit, 2".to_string()));
+        assert!(!replayed.contains(&"bob: maybe apple?".to_string()));
+
+        let erin = watch_game(&mut game_server_state, "erin");
+        let replayed = chat_queue(&game_server_state, erin);
+        assert!(replayed.contains(&"* alice Started the Game!".to_string()));
+        assert!(replayed.contains(&"bob: maybe apple?".to_string()));
+    }
+
+    #[test]
+    fn chat_history_is_bounded() {
+        let mut room = themed_room(&["ONE"]);
+        for i in 0..CHAT_HISTORY_LEN + 5 {
+            room.record_chat(format!("message {}", i), false);
+        }
+        assert_eq!(room.chat_history.len(), CHAT_HISTORY_LEN);
+        assert_eq!(room.chat_history.front().unwrap().0, "message 5");
+    }
+
+    #[test]
+    fn finding_the_last_agent_wins_straight_away() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let red_words : Vec<String> = room(&game_server_state).board.iter().flatten()
+            .filter(|card| card.card_type == CodenamesCardType::RedAgent)
+            .map(|card| card.word.to_string())
+            .collect();
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let hook_results = results.clone();
+        game_server_state.on_game_end = Some(Box::new(move |result| hook_results.lock().unwrap().push(result)));
+        send(&mut game_server_state, players[0], "fruit,unlimited");
+        for red_word in &red_words {
+            send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        }
+        assert_eq!(room(&game_server_state).state, CodenamesState::GameEnd);
+        let chat = chat_queue(&game_server_state, players[2]);
+        assert_eq!(chat[chat.len() - 2..], ["* Red team wins!".to_string(),
+                   "* The Red team found all of their agents. Final score 9-0 (R-B)".to_string()]);
+        // the text and the result handed to the hook agree
+        assert_eq!(results.lock().unwrap()[0].winner, CodenamesTeam::Red);
+    }
+
+    fn room(game_server_state : &GameServerState) -> &CodenamesRoom {
+        game_server_state.game_rooms[&1].codenames().unwrap()
+    }
+
+    fn find_word(room : &CodenamesRoom, card_type : CodenamesCardType) -> String {
+        room.board.iter().flatten()
+            .find(|card| card.card_type == card_type && !card.flipped)
+            .map(|card| card.word.to_string())
+            .unwrap()
+    }
+
+    fn themed_room(words : &[&str]) -> CodenamesRoom {
+        let mut board = gen_board(&load_word_list(None).unwrap(), RoomVariant::Standard, DEFAULT_BOARD_DIM, None).unwrap();
+        for (card, word) in board.iter_mut().flatten().zip(words.iter().cycle()) {
+            card.word = word.to_string();
+        }
+        CodenamesRoom::new(HashSet::new(), board, RoomVariant::Standard, Arc::new(load_word_lists(None, None).unwrap()))
+    }
+
+    fn assert_aligned(board : &str, width : usize) {
+        use unicode_width::UnicodeWidthStr;
+        for line in board.split("\r\n").filter(|l| !l.is_empty()) {
+            assert_eq!(line.width(), width, "misaligned line {:?}", line);
+        }
+    }
+
+    #[test]
+    fn wide_characters_keep_the_board_aligned() {
+        let words = ["東京", "北京大学", "😀😃", "ÉCOLE", "ＡＢＣ", "超電導量子コンピュータ研究所"];
+        let mut room = themed_room(&words);
+        room.board[0][0].card_type = CodenamesCardType::RedAgent;
+        for (role, cell) in [(CodenamesRole::Teammate, "|       東京       |"),
+                             (CodenamesRole::Spymaster, "|       東京     R |")] {
+            let board = room.get_board(CodenamesTeam::Red, role);
+            assert_aligned(&board, line_width(LONGEST_CODENAMES_WORD, DEFAULT_BOARD_DIM));
+            // 5 columns of padding either side of a 2 character, 4 column CJK word
+            assert!(board.lines().nth(1).unwrap().starts_with(cell));
+            // cut at the last whole character that fits in 14 columns
+            assert!(board.contains("超電導量子コン") && !board.contains("超電導量子コンピ"));
+        }
+        assert_eq!(center_in_columns("😀😃", 7), " 😀😃  ");
+    }
+
+    #[test]
+    fn boards_fit_narrow_terminals() {
+        let words = ["CONSTELLATIONS", "SUPERCONDUCTOR", "ROME"];
+        let room = themed_room(&words);
+        let full = room.get_board(CodenamesTeam::Red, CodenamesRole::Teammate);
+        assert_eq!(room.get_board_for_width(CodenamesTeam::Red, CodenamesRole::Teammate, None), full);
+        assert_eq!(room.get_board_for_width(CodenamesTeam::Red, CodenamesRole::Teammate, Some(120)), full);
+
+        // an 80 column terminal gets the grid with words cut to 10 columns
+        let board = room.get_board_for_width(CodenamesTeam::Red, CodenamesRole::Teammate, Some(80));
+        assert_aligned(&board, line_width(10, DEFAULT_BOARD_DIM));
+        assert!(board.contains("CONSTELLAT") && !board.contains("CONSTELLATI"));
+
+        // too narrow for the grid, every card gets a line of its own
+        let board = room.get_board_for_width(CodenamesTeam::Red, CodenamesRole::Spymaster, Some(30));
+        assert!(board.split("\r\n").all(|line| line.len() <= 30));
+        assert!(board.contains(&format!("  {:<2}SUPERCONDUCTOR\r\n", room.board[0][1].card_type.to_string())));
+    }
+
+    #[test]
+    fn wide_cells_fit_long_words() {
+        let words = ["CONSTELLATIONS", "SUPERCONDUCTOR", "PHOTOSYNTHESIS!!", "THERMODYNAMICS!!!"];
+        let mut room = themed_room(&words);
+        assert!(room.settings.apply("width", "20").is_ok());
+        for role in [CodenamesRole::Teammate, CodenamesRole::Spymaster] {
+            let board = room.get_board(CodenamesTeam::Red, role);
+            assert_aligned(&board, line_width(20, DEFAULT_BOARD_DIM));
+            for word in words {
+                assert!(board.contains(word));
+            }
+        }
+    }
+
+    #[test]
+    fn board_layout() {
+        let mut room = themed_room(&["ONE", "TWO", "THREE", "FOUR", "FIVE"]);
+        assert!(room.settings.apply("width", "6").is_ok());
+        for (i, card) in room.board.iter_mut().flatten().enumerate() {
+            card.card_type = match i {
+                0..=4 => CodenamesCardType::RedAgent,
+                5..=9 => CodenamesCardType::BlueAgent,
+                24 => CodenamesCardType::Assassin,
+                _ => CodenamesCardType::Bystander
+            };
+        }
+        room.board[0][0].flipped = true;
+        room.board[1][1].flipped = true;
+        room.board[2][2].flipped = true;
+        let line = format!("{:-<56}\r\n", "");
+        let unflipped = "|   ONE    |   TWO    |  THREE   |   FOUR   |   FIVE   |\r\n";
+        assert_eq!(room.get_board(CodenamesTeam::Red, CodenamesRole::Teammate), [
+            &line, "|X  ONE  R |   TWO    |  THREE   |   FOUR   |   FIVE   |\r\n",
+            &line, "|   ONE    |X  TWO  B |  THREE   |   FOUR   |   FIVE   |\r\n",
+            &line, "|   ONE    |   TWO    |X THREE   |   FOUR   |   FIVE   |\r\n",
+            &line, unflipped,
+            &line, unflipped,
+            &line
+        ].concat());
+        assert_eq!(room.get_board(CodenamesTeam::Red, CodenamesRole::Spymaster), [
+            &line, "|X  ONE  R |   TWO  R |  THREE R |   FOUR R |   FIVE R |\r\n",
+            &line, "|   ONE  B |X  TWO  B |  THREE B |   FOUR B |   FIVE B |\r\n",
+            &line, "|   ONE    |   TWO    |X THREE   |   FOUR   |   FIVE   |\r\n",
+            &line, unflipped,
+            &line, "|   ONE    |   TWO    |  THREE   |   FOUR   |   FIVE A |\r\n",
+            &line
+        ].concat());
+    }
+
+    #[test]
+    fn board_layout_with_coordinates() {
+        let mut room = themed_room(&["ONE", "TWO", "THREE", "FOUR", "FIVE"]);
+        assert!(room.settings.apply("width", "6").is_ok());
+        assert!(room.settings.apply("coords", "on").is_ok());
+        room.board[1][1].flipped = true;
+        room.board[1][1].card_type = CodenamesCardType::BlueAgent;
+        let board = room.get_board(CodenamesTeam::Red, CodenamesRole::Teammate);
+        assert_aligned(&board, ROW_LABEL_LEN + line_width(6, DEFAULT_BOARD_DIM));
+        let lines : Vec<&str> = board.split("\r\n").collect();
+        assert_eq!(lines[0], "       A          B          C          D          E      ");
+        assert_eq!(lines[1], format!("  {:-<56}", ""));
+        assert_eq!(lines[4], "2 |   ONE    |X  TWO  B |  THREE   |   FOUR   |   FIVE   |");
+
+        // an 80 column terminal still fits the labels
+        assert!(room.settings.apply("width", "14").is_ok());
+        assert_aligned(&room.get_board_for_width(CodenamesTeam::Red, CodenamesRole::Teammate, Some(80)), 78);
+        let board = room.get_board_for_width(CodenamesTeam::Red, CodenamesRole::Teammate, Some(30));
+        assert!(board.contains("X B B2 TWO\r\n") && board.contains("    E5 FIVE\r\n"));
+    }
+
+    #[test]
+    fn narrow_cells_truncate_long_words() {
+        let mut room = themed_room(&["THERMODYNAMICS!!!"]);
+        assert!(room.settings.apply("width", "10").is_ok());
+        assert!(room.settings.apply("width", "100").is_err());
+        let board = room.get_board(CodenamesTeam::Red, CodenamesRole::Spymaster);
+        assert_aligned(&board, line_width(10, DEFAULT_BOARD_DIM));
+        assert!(board.contains("THERMODYNA") && !board.contains("THERMODYNAM"));
+    }
+
+    fn write_word_list(name : &str, contents : &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("codenames-{}-{}.txt", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn word_list_from_file() {
+        let words : Vec<String> = (0..30).map(|i| format!("WORD{}", i)).collect();
+        let path = write_word_list("valid", &(words.join("\r\n") + "\r\n\r\n"));
+        assert_eq!(load_word_list(Some(&path)).unwrap(), words);
+        let board = gen_board(&words, RoomVariant::Standard, DEFAULT_BOARD_DIM, None).unwrap();
+        assert!(board.iter().flatten().all(|card| words.contains(&card.word)));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn word_list_errors() {
+        let missing = std::env::temp_dir().join("codenames-missing-word-list.txt");
+        assert_eq!(load_word_list(Some(&missing)).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let path = write_word_list("short", &"SAME\n".repeat(30));
+        let err = load_word_list(Some(&path)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("has 1 unique words, at least 25 are needed"));
+        fs::remove_file(path).unwrap();
+    }
+
+    const FRENCH_WORDS : [&str; 27] = ["CAFÉ", "ÉCOLE", "FORÊT", "CHÂTEAU", "GARÇON", "NOËL", "HÔPITAL",
+        "PÂTE", "FENÊTRE", "ÎLE", "BÊTE", "CRÈME", "ÉTOILE", "FRÈRE", "GÂTEAU", "HÔTEL", "MÈRE", "PÈRE",
+        "RÊVE", "SŒUR", "TÊTE", "ÉLÈVE", "ÉPÉE", "ÉGLISE", "PLAGE", "NUIT", "LUNE"];
+
+    #[test]
+    fn word_lists_from_directory() {
+        let dir = std::env::temp_dir().join(format!("codenames-lists-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("FR.txt"), "\u{feff}".to_string() + &FRENCH_WORDS.join("\n")).unwrap();
+        fs::write(dir.join("notes.md"), "not a word list").unwrap();
+        let word_lists = load_word_lists(None, Some(&dir)).unwrap();
+        let mut languages : Vec<&String> = word_lists.keys().collect();
+        languages.sort();
+        assert_eq!(languages, ["en", "fr"]);
+        assert_eq!(word_lists["fr"], FRENCH_WORDS);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(load_word_lists(None, Some(&dir)).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn room_language_setting() {
+        let mut game_server_state = GameServerState::new();
+        Arc::make_mut(&mut game_server_state.word_lists).insert("fr".to_string(), FRENCH_WORDS.map(str::to_string).to_vec());
+        let players = start_game(&mut game_server_state, &["set lang de", "set lang FR"]);
+        assert!(chat_queue(&game_server_state, players[0]).contains(&"lang must be one of en, fr\r\n".to_string()));
+        let room = room(&game_server_state);
+        assert_eq!(room.settings.language, "fr");
+        assert!(room.board.iter().flatten().all(|card| FRENCH_WORDS.contains(&card.word.as_str())));
+        for role in [CodenamesRole::Teammate, CodenamesRole::Spymaster] {
+            assert_aligned(&room.get_board(CodenamesTeam::Red, role), line_width(LONGEST_CODENAMES_WORD, DEFAULT_BOARD_DIM));
+        }
+    }
+
+    fn last_chat(game_server_state : &GameServerState, addr : SocketAddr) -> String {
+        game_server_state.user_state[&addr]
+            .player.as_ref().unwrap()
+            .chat_queue.back().unwrap().to_string()
+    }
+
+    #[test]
+    fn risk_in_training_mode() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set training on"]);
+        send(&mut game_server_state, players[1], "risk");
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   "25 unflipped cards: 8 enemy agents, 7 bystanders, 1 assassin\r\n");
+
+        let bystander = find_word(room(&game_server_state), CodenamesCardType::Bystander);
+        send(&mut game_server_state, players[0], "clue,1");
+        send(&mut game_server_state, players[1], &format!("!{}", bystander));
+        // now it's blue's turn, so red agents are the enemy
+        send(&mut game_server_state, players[3], "risk");
+        assert_eq!(last_chat(&game_server_state, players[3]),
+                   "24 unflipped cards: 9 enemy agents, 6 bystanders, 1 assassin\r\n");
+    }
+
+    #[test]
+    fn risk_refused_outside_training_mode() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[1], "risk");
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   "risk is only available in training mode\r\n");
+    }
+
+    #[test]
+    fn word_list_duplicates_removed() {
+        let path = write_word_list("duplicates", &(0..50).map(|i| format!("WORD{}\n", i % 25)).collect::<String>());
+        let words = load_word_list(Some(&path)).unwrap();
+        assert_eq!(words.len(), 25);
+        let board = gen_board(&words, RoomVariant::Standard, DEFAULT_BOARD_DIM, None).unwrap();
+        let board_words : HashSet<&String> = board.iter().flatten().map(|card| &card.word).collect();
+        assert_eq!(board_words.len(), 25);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn seeded_board_is_reproducible() {
+        let words = load_word_list(None).unwrap();
+        let board = gen_board_with_rng(&words, RoomVariant::Standard, DEFAULT_BOARD_DIM, None, &mut StdRng::seed_from_u64(7)).unwrap();
+        let cards : Vec<&CodenamesCard> = board.iter().flatten().collect();
+        for (card_type, count) in [(CodenamesCardType::RedAgent, 9), (CodenamesCardType::BlueAgent, 8),
+                                   (CodenamesCardType::Bystander, 7), (CodenamesCardType::Assassin, 1)] {
+            assert_eq!(cards.iter().filter(|c| c.card_type == card_type).count(), count);
+        }
+        assert_eq!(cards.iter().map(|c| c.word.as_str()).collect::<HashSet<&str>>().len(), 25);
+
+        let again = gen_board_with_rng(&words, RoomVariant::Standard, DEFAULT_BOARD_DIM, None, &mut StdRng::seed_from_u64(7)).unwrap();
+        assert!(board.iter().flatten().zip(again.iter().flatten())
+            .all(|(a, b)| a.word == b.word && a.card_type == b.card_type));
+    }
+
+    #[test]
+    fn gen_board_needs_enough_distinct_words() {
+        let words : Vec<String> = (0..48).map(|i| format!("WORD{}", i % 24)).collect();
+        assert_eq!(gen_board(&words, RoomVariant::Standard, DEFAULT_BOARD_DIM, None).err().unwrap(),
+                   "Only 24 distinct words are available, a board needs 25");
+    }
+
+    #[test]
+    fn transcript_after_game_end() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        let bystander = find_word(room(&game_server_state), CodenamesCardType::Bystander);
+        let assassin = find_word(room(&game_server_state), CodenamesCardType::Assassin);
+        send(&mut game_server_state, players[1], "transcript");
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   "The transcript is available once the game ends\r\n");
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], &format!("!{}", bystander));
+        send(&mut game_server_state, players[2], "ocean,1");
+        send(&mut game_server_state, players[3], &format!("!{}", assassin));
+        assert!(room(&game_server_state).has_ended());
+
+        // polling without input doesn't clean up the room
+        let _ = game_server_state.handle_input(players[3], None);
+        send(&mut game_server_state, players[3], "transcript");
+        let transcript = last_chat(&game_server_state, players[3]);
+        let expected_events = [
+            "Red spymaster clue: fruit, 2".to_string(),
+            format!("bob guessed {}: Red agent", red_word),
+            format!("bob guessed {}: Bystander", bystander),
+            "Red turn ended".to_string(),
+            "Blue spymaster clue: ocean, 1".to_string(),
+            format!("dave guessed {}: Assassin", assassin),
+            "Red team wins! The Blue team found the assassin. Final score 1-0 (R-B)".to_string()
+        ];
+        let lines : Vec<&str> = transcript.split("\r\n").collect();
+        assert_eq!(lines[1..8], expected_events);
+        assert!(transcript.contains(&room(&game_server_state).get_board(CodenamesTeam::Floating,
+                                                                        CodenamesRole::Spymaster)));
+    }
+
+    fn is_flipped(game_server_state : &GameServerState, word : &str) -> bool {
+        room(game_server_state).board.iter().flatten().any(|card| card.word == word && card.flipped)
+    }
+
+    #[test]
+    fn confirm_mode_guesses() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set confirm on"]);
+        let red_words : Vec<String> = room(&game_server_state).board.iter().flatten()
+            .filter(|card| card.card_type == CodenamesCardType::RedAgent)
+            .map(|card| card.word.to_string())
+            .collect();
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", red_words[0]));
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   format!("Guess {}? Reply !!yes to confirm\r\n", red_words[0]));
+        assert!(!is_flipped(&game_server_state, &red_words[0]));
+        send(&mut game_server_state, players[1], "!!yes");
+        assert!(is_flipped(&game_server_state, &red_words[0]));
+
+        // an unconfirmed guess times out when the turn ends
+        send(&mut game_server_state, players[1], &format!("!{}", red_words[1]));
+        send(&mut game_server_state, players[1], "!!");
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+        assert!(room(&game_server_state).pending_guess.is_none());
+        assert!(!is_flipped(&game_server_state, &red_words[1]));
+    }
+
+    #[test]
+    fn guesses_flip_immediately_by_default() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        assert!(is_flipped(&game_server_state, &red_word));
+    }
+
+    #[test]
+    fn guess_log_across_turns() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[2], "log");
+        assert_eq!(last_chat(&game_server_state, players[2]), "No cards have been guessed yet\r\n");
+
+        let bystander = find_word(room(&game_server_state), CodenamesCardType::Bystander);
+        let blue_word = find_word(room(&game_server_state), CodenamesCardType::BlueAgent);
+        send(&mut game_server_state, players[0], "fruit,1");
+        send(&mut game_server_state, players[1], &format!("!{}", bystander));
+        send(&mut game_server_state, players[2], "ocean,1");
+        send(&mut game_server_state, players[3], "!not a card");
+        send(&mut game_server_state, players[3], &format!("!{}", blue_word));
+        assert_eq!(room(&game_server_state).guess_log, vec![
+            ("bob".to_string(), bystander.to_string(), CodenamesCardType::Bystander),
+            ("dave".to_string(), blue_word.to_string(), CodenamesCardType::BlueAgent)
+        ]);
+        send(&mut game_server_state, players[0], "log");
+        assert_eq!(last_chat(&game_server_state, players[0]),
+                   format!("Guess history:\r\n{:>25}: {} (Bystander)\r\n{:>25}: {} (Blue agent)\r\n",
+                           "bob", bystander, "dave", blue_word));
+    }
+
+    fn join_room(game_server_state : &mut GameServerState, user_name : &str) -> SocketAddr {
+        let addr = new_addr();
+        login(game_server_state, addr, user_name);
+        send(game_server_state, addr, "1");
+        let _ = game_server_state.handle_input(addr, None);
+        addr
+    }
+
+    /// Joins room 1 while its game is underway, taking the offer to watch it
+    fn watch_game(game_server_state : &mut GameServerState, user_name : &str) -> SocketAddr {
+        let addr = new_addr();
+        login(game_server_state, addr, user_name);
+        send(game_server_state, addr, "1");
+        send(game_server_state, addr, "y");
+        let _ = game_server_state.handle_input(addr, None);
+        addr
+    }
+
+    #[test]
+    fn role_changes_shown_to_everyone() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.prompt_for(alice);
+        let bob = join_room(&mut game_server_state, "bob");
+        let _ = game_server_state.prompt_for(bob);
+        assert!(game_server_state.prompt_for(bob).is_none());
+
+        send(&mut game_server_state, alice, "spymaster");
+        send(&mut game_server_state, alice, "red");
+        let prompt = game_server_state.prompt_for(bob).unwrap();
+        assert!(prompt.contains(&format!("{:>25} {:>10?}, {:>10?}", "alice", CodenamesRole::Spymaster,
+                                         CodenamesTeam::Red)));
+    }
+
+    #[test]
+    fn spectators_see_everything_but_cannot_guess() {
+        let mut game_server_state = GameServerState::new();
+        let erin = new_addr();
+        login(&mut game_server_state, erin, "erin");
+        create_room(&mut game_server_state, erin);
+        send(&mut game_server_state, erin, "spectator");
+        let mut players = Vec::new();
+        for (user_name, team, role) in [("alice", "red", "spymaster"), ("bob", "red", "teammate"),
+                                        ("carol", "blue", "spymaster"), ("dave", "blue", "teammate")] {
+            let addr = join_room(&mut game_server_state, user_name);
+            send(&mut game_server_state, addr, team);
+            send(&mut game_server_state, addr, role);
+            players.push(addr);
+        }
+        // the spectator doesn't get in the way of starting
+        send(&mut game_server_state, erin, "start");
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, erin, &format!("!{}", red_word));
+        assert_eq!(last_chat(&game_server_state, erin), "Spectators cannot guess\r\n");
+        assert!(!is_flipped(&game_server_state, &red_word));
+
+        let board = room(&game_server_state).get_board(CodenamesTeam::Floating, CodenamesRole::Spectator);
+        assert!(board.starts_with("Spectator view"));
+        assert!(board.contains(&format!("{:^14}A", find_word(room(&game_server_state), CodenamesCardType::Assassin))));
+    }
+
+    #[test]
+    fn turns_go_from_clue_to_guessing() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        assert_eq!(room(&game_server_state).phase, TurnPhase::AwaitingClue);
+        assert!(game_server_state.prompt_for(players[1]).unwrap().contains("Waiting for your spymaster's clue"));
+        // nothing to guess or pass on until the clue is in
+        for line in ["!apple", "!!"] {
+            send(&mut game_server_state, players[1], line);
+            assert_eq!(last_chat(&game_server_state, players[1]), "Wait for your spymaster's clue\r\n");
+        }
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+
+        send(&mut game_server_state, players[0], "fruit,2");
+        assert_eq!(room(&game_server_state).phase, TurnPhase::Guessing);
+        let prompt = game_server_state.prompt_for(players[1]).unwrap();
+        assert!(prompt.contains("Guess by submitting") && prompt.contains("Clue: fruit, 2"));
+        assert!(game_server_state.prompt_for(players[0]).unwrap().contains("Your team is guessing"));
+        assert!(game_server_state.prompt_for(players[2]).unwrap().contains("The Red team is guessing"));
+        send(&mut game_server_state, players[0], "apple,1");
+        assert_eq!(last_chat(&game_server_state, players[0]), "You've already given a clue this turn\r\n");
+
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        assert_eq!(room(&game_server_state).phase, TurnPhase::Guessing);
+        send(&mut game_server_state, players[1], "!!");
+        assert_eq!((room(&game_server_state).state, room(&game_server_state).phase),
+                   (CodenamesState::BlueTurn, TurnPhase::AwaitingClue));
+        assert!(game_server_state.prompt_for(players[2]).unwrap().contains("Type in your clue"));
+    }
+
+    /// A room with a player seated in each of the given spots, and their user states
+    fn seated_room(seats : &[(CodenamesTeam, CodenamesRole)]) -> (CodenamesRoom, HashMap<SocketAddr, User>) {
+        let mut user_state_map = HashMap::new();
+        let mut room = themed_room(&["WORD"]);
+        for (team, role) in seats {
+            let addr = new_addr();
+            get_user_state(&mut user_state_map, addr).player = Some(CodenamesPlayer::new(*team, *role));
+            room.players.insert(addr);
+        }
+        (room, user_state_map)
+    }
+
+    #[test]
+    fn verify_room_needs_every_role() {
+        let minimum = [(CodenamesTeam::Red, CodenamesRole::Spymaster), (CodenamesTeam::Red, CodenamesRole::Teammate),
+                       (CodenamesTeam::Blue, CodenamesRole::Spymaster), (CodenamesTeam::Blue, CodenamesRole::Teammate)];
+        let (room, user_state_map) = seated_room(&minimum);
+        assert!(verify_room(&room, &user_state_map));
+        let (room, user_state_map) = seated_room(&minimum[..3]);
+        assert!(!verify_room(&room, &user_state_map));
+        // spectators don't fill in for a missing teammate
+        let (room, user_state_map) = seated_room(&[minimum[0], minimum[1], minimum[2],
+                                                   (CodenamesTeam::Blue, CodenamesRole::Spectator)]);
+        assert!(!verify_room(&room, &user_state_map));
+    }
+
+    #[test]
+    fn verify_room_skips_stale_addresses() {
+        let (mut room, user_state_map) = seated_room(&[
+            (CodenamesTeam::Red, CodenamesRole::Spymaster), (CodenamesTeam::Red, CodenamesRole::Teammate),
+            (CodenamesTeam::Blue, CodenamesRole::Spymaster), (CodenamesTeam::Blue, CodenamesRole::Teammate)]);
+        // left the user state map while still listed in the room
+        room.players.insert(new_addr());
+        assert!(verify_room(&room, &user_state_map));
+    }
+
+    #[test]
+    fn balance_puts_floating_players_on_teams() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        send(&mut game_server_state, alice, "red");
+        send(&mut game_server_state, alice, "spymaster");
+        let carol = join_room(&mut game_server_state, "carol");
+        send(&mut game_server_state, carol, "blue");
+        send(&mut game_server_state, carol, "spymaster");
+        let bob = join_room(&mut game_server_state, "bob");
+        let dave = join_room(&mut game_server_state, "dave");
+        send(&mut game_server_state, dave, "spectator");
+        let erin = join_room(&mut game_server_state, "erin");
+
+        send(&mut game_server_state, alice, "start");
+        assert_eq!(room(&game_server_state).state, CodenamesState::WaitingToStart);
+        assert!(last_chat(&game_server_state, alice).starts_with("Type 'balance'"));
+
+        send(&mut game_server_state, bob, "balance");
+        assert!(chat_queue(&game_server_state, erin)
+            .contains(&"* bob balanced the teams: bob to Red, dave to Blue, erin to Red".to_string()));
+        for (addr, team) in [(bob, CodenamesTeam::Red), (dave, CodenamesTeam::Blue), (erin, CodenamesTeam::Red)] {
+            let player = game_server_state.user_state[&addr].player.as_ref().unwrap();
+            assert_eq!((player.team, player.role), (team, CodenamesRole::Teammate));
+        }
+        send(&mut game_server_state, bob, "balance");
+        assert_eq!(last_chat(&game_server_state, bob), "Everyone is already on a team\r\n");
+        send(&mut game_server_state, alice, "start");
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+    }
+
+    #[test]
+    fn observers_watch_from_the_lobby() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let erin = new_addr();
+        login(&mut game_server_state, erin, "erin");
+        send(&mut game_server_state, erin, "spectate 1");
+        let _ = game_server_state.handle_input(erin, None);
+        assert_eq!(game_server_state.user_state[&erin].state, ServerState::InRoom);
+        send(&mut game_server_state, erin, "red");
+        assert!(last_chat(&game_server_state, erin).starts_with("You're only watching this room"));
+
+        send(&mut game_server_state, alice, "red");
+        send(&mut game_server_state, alice, "spymaster");
+        let mut players = vec![alice];
+        for (user_name, team, role) in [("bob", "red", "teammate"), ("carol", "blue", "spymaster"),
+                                        ("dave", "blue", "teammate")] {
+            let addr = join_room(&mut game_server_state, user_name);
+            send(&mut game_server_state, addr, team);
+            send(&mut game_server_state, addr, role);
+            players.push(addr);
+        }
+        // the observer isn't someone to balance or wait for
+        send(&mut game_server_state, alice, "balance");
+        assert_eq!(last_chat(&game_server_state, alice), "Everyone is already on a team\r\n");
+        send(&mut game_server_state, erin, "start");
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, alice, "fruit,2");
+        send(&mut game_server_state, erin, &format!("!{}", red_word));
+        assert_eq!(last_chat(&game_server_state, erin), "Observers cannot guess\r\n");
+        assert!(!is_flipped(&game_server_state, &red_word));
+        let prompt = game_server_state.prompt_for(erin).unwrap();
+        assert!(prompt.contains(&room(&game_server_state).get_board(CodenamesTeam::Floating, CodenamesRole::Teammate)));
+
+        // and there's no seat to hold when they go
+        game_server_state.remove_client(erin);
+        assert!(!room(&game_server_state).has_reserved_seat("erin"));
+    }
+
+    #[test]
+    fn players_without_a_role_see_the_teammate_view() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let erin = watch_game(&mut game_server_state, "erin");
+        let _ = game_server_state.prompt_for(erin);
+        // joining mid game makes an observer, this is someone who was in
+        // the room before the start without picking a role
+        let erin_player = game_server_state.user_state.get_mut(&erin).unwrap().player.as_mut().unwrap();
+        assert_eq!(erin_player.role, CodenamesRole::Observer);
+        erin_player.role = CodenamesRole::Unassigned;
+
+        let assassin = find_word(room(&game_server_state), CodenamesCardType::Assassin);
+        let board = room(&game_server_state).get_board(CodenamesTeam::Floating, CodenamesRole::Unassigned);
+        assert!(!board.starts_with("Spectator view"));
+        assert!(!board.contains(&format!("{:^14}A", assassin)));
+        assert_eq!(board, room(&game_server_state).get_board(CodenamesTeam::Floating, CodenamesRole::Teammate));
+
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, erin, &format!("!{}", red_word));
+        assert_eq!(last_chat(&game_server_state, erin), "Players without a role cannot guess\r\n");
+        assert!(!is_flipped(&game_server_state, &red_word));
+    }
+
+    #[test]
+    fn end_turn_needs_a_guess_for_numbered_clues() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], "!!");
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+        assert_eq!(last_chat(&game_server_state, players[1]),
+                   "Make at least one guess before ending the turn\r\n");
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        send(&mut game_server_state, players[1], "!!");
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+
+        // unlimited clues can be passed on straight away
+        send(&mut game_server_state, players[2], "ocean,unlimited");
+        send(&mut game_server_state, players[3], "!!");
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+    }
+
+    #[test]
+    fn zero_clue_allows_unlimited_guesses() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,0");
+        for _ in 0..4 {
+            let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+            send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        }
+        assert_eq!(room(&game_server_state).red_score, 4);
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+        assert_eq!(room(&game_server_state).history[0], "Red spymaster clue: fruit, 0");
+    }
+
+    #[test]
+    fn teams_groups_players_by_seat() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        for command in ["red", "spymaster"] {
+            send(&mut game_server_state, alice, command);
+        }
+        for (user_name, commands) in [("bob", &["red", "teammate"][..]), ("carol", &["red", "teammate"]),
+                                      ("dave", &["spectator"]), ("erin", &[])] {
+            let addr = new_addr();
+            login(&mut game_server_state, addr, user_name);
+            send(&mut game_server_state, addr, "1");
+            // seated in the room on their first poll there
+            game_server_state.handle_input(addr, None).unwrap();
+            for command in commands {
+                send(&mut game_server_state, addr, command);
+            }
+        }
+        send(&mut game_server_state, alice, "teams");
+        assert_eq!(last_chat(&game_server_state, alice),
+                   "Red Spymaster: alice\r\nRed Team: bob, carol\r\nBlue Spymaster: (nobody)\r\n\
+                    Blue Team: (nobody)\r\nSpectators: dave\r\nUnassigned: erin\r\n");
+        // it works mid game as well, without anyone left over
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[3], "teams");
+        assert_eq!(last_chat(&game_server_state, players[3]),
+                   "Red Spymaster: alice\r\nRed Team: bob\r\nBlue Spymaster: carol\r\nBlue Team: dave\r\n");
+    }
+
+    #[test]
+    fn chat_alone_doesnt_redraw_the_board() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        assert!(game_server_state.prompt_for(players[2]).unwrap().contains("Red Team's Turn"));
+        send(&mut game_server_state, players[3], "good luck");
+        assert_eq!(game_server_state.prompt_for(players[2]).unwrap(), "dave: good luck\r\n");
+        // nothing new, nothing to send
+        assert_eq!(game_server_state.prompt_for(players[2]), None);
+    }
+
+    #[test]
+    fn who_lists_players_mid_game() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let _ = game_server_state.prompt_for(players[3]);
+        send(&mut game_server_state, players[3], "who");
+        let roles = last_chat(&game_server_state, players[3]);
+        assert!(roles.contains(&format!("{:>3} {:>25} {:>10?}, {:>10?}\r\n", "YOU", "dave",
+                                        CodenamesRole::Teammate, CodenamesTeam::Blue)));
+        assert!(roles.contains(&format!("{:>3} {:>25} {:>10?}, {:>10?}\r\n", "", "alice",
+                                        CodenamesRole::Spymaster, CodenamesTeam::Red)));
+        // the board prompt isn't sent again
+        let player = game_server_state.user_state[&players[3]].player.as_ref().unwrap();
+        assert_eq!(player.state_prompted, Some(CodenamesState::RedTurn));
+    }
+
+    #[test]
+    fn guessers_chat_hidden_from_spymasters() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set chat guessers"]);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], "maybe apple?");
+        assert_eq!(last_chat(&game_server_state, players[3]), "bob: maybe apple?");
+        for spymaster in [players[0], players[2]] {
+            assert_ne!(last_chat(&game_server_state, spymaster), "bob: maybe apple?");
+        }
+    }
+
+    #[test]
+    fn guessers_chat_reaches_everyone_by_default() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,2");
+        send(&mut game_server_state, players[1], "maybe apple?");
+        for addr in [players[0], players[2], players[3]] {
+            assert_eq!(last_chat(&game_server_state, addr), "bob: maybe apple?");
+        }
+    }
+
+    #[test]
+    fn emotes_and_system_messages() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        assert_eq!(last_chat(&game_server_state, players[1]), "* alice Started the Game!");
+        send(&mut game_server_state, players[2], "/me waves");
+        for addr in &players {
+            assert_eq!(last_chat(&game_server_state, *addr), "* carol waves");
+        }
+        send(&mut game_server_state, players[2], "hi");
+        assert_eq!(last_chat(&game_server_state, players[0]), "carol: hi");
+    }
+
+    #[test]
+    fn turn_timer_passes_idle_turns() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &["set timer 30"]);
+        game_server_state.tick(Instant::now() + Duration::from_secs(20));
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+        game_server_state.tick(Instant::now() + Duration::from_secs(31));
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+        assert_eq!(last_chat(&game_server_state, players[0]), "* Red ran out of time");
+
+        // acting restarts the clock
+        let start = Instant::now();
+        send(&mut game_server_state, players[2], "ocean,1");
+        assert!(room(&game_server_state).turn_deadline.unwrap() >= start + Duration::from_secs(30));
+    }
+
+    #[test]
+    fn turn_timer_off_by_default() {
+        let mut game_server_state = GameServerState::new();
+        let _players = start_game(&mut game_server_state, &[]);
+        game_server_state.tick(Instant::now() + Duration::from_secs(3600));
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+    }
+
+    #[test]
+    fn turn_skipped_without_spymaster() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        game_server_state.remove_client(players[0]);
+        // the seat is held for a while before the turn is given up
+        let _ = game_server_state.handle_input(players[1], None);
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+        game_server_state.release_expired_seats(Instant::now() + RECONNECT_GRACE);
+        let _ = game_server_state.handle_input(players[1], None);
+        assert_eq!(room(&game_server_state).state, CodenamesState::BlueTurn);
+        assert_eq!(last_chat(&game_server_state, players[1]), "* Red has no spymaster, skipping turn");
+    }
+
+    #[test]
+    fn draw_without_any_spymaster() {
+        let mut game_server_state = GameServerState::new();
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let hook_results = results.clone();
+        game_server_state.on_game_end = Some(Box::new(move |result| hook_results.lock().unwrap().push(result)));
+        let players = start_game(&mut game_server_state, &[]);
+        game_server_state.remove_client(players[0]);
+        game_server_state.remove_client(players[2]);
+        game_server_state.release_expired_seats(Instant::now() + RECONNECT_GRACE);
+        let _ = game_server_state.handle_input(players[1], None);
+        assert_eq!(room(&game_server_state).state, CodenamesState::GameEnd);
+        assert!(room(&game_server_state).result_summary().starts_with("The game is a draw"));
+        let result = &results.lock().unwrap()[0];
+        assert_eq!((result.winner, result.drawn), (CodenamesTeam::Floating, true));
+    }
+
+    #[test]
+    fn game_end_hook_receives_result() {
+        let mut game_server_state = GameServerState::new();
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let hook_results = results.clone();
+        game_server_state.on_game_end = Some(Box::new(move |result| hook_results.lock().unwrap().push(result)));
+        let players = start_game(&mut game_server_state, &[]);
+        assert_eq!(room(&game_server_state).state, CodenamesState::RedTurn);
+
+        let red_word = find_word(room(&game_server_state), CodenamesCardType::RedAgent);
+        let assassin = find_word(room(&game_server_state), CodenamesCardType::Assassin);
+        send(&mut game_server_state, players[0], "clue,2");
+        send(&mut game_server_state, players[1], &format!("!{}", red_word));
+        assert!(results.lock().unwrap().is_empty());
+        send(&mut game_server_state, players[1], &format!("!{}", assassin));
+
+        let results = results.lock().unwrap();
+        assert_eq!(*results, vec![GameResult {
+            winner: CodenamesTeam::Blue,
+            drawn: false,
+            red_score: 1,
+            blue_score: 0,
+            assassin_loss: true,
+            players: vec![
+                ("alice".to_string(), CodenamesTeam::Red, CodenamesRole::Spymaster),
+                ("bob".to_string(), CodenamesTeam::Red, CodenamesRole::Teammate),
+                ("carol".to_string(), CodenamesTeam::Blue, CodenamesRole::Spymaster),
+                ("dave".to_string(), CodenamesTeam::Blue, CodenamesRole::Teammate)
+            ]
+        }]);
+    }
+}
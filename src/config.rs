@@ -0,0 +1,184 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::event_log::Level;
+
+/// Which IP versions the server listens for clients on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IpMode {
+    Ipv4, // IPv4 loopback only
+    Ipv6, // IPv6 loopback only
+    DualStack // every interface, IPv4 clients come in on the IPv6 socket
+}
+
+impl IpMode {
+    fn parse(value : &str) -> Option<IpMode> {
+        match value {
+            "ipv4" => Some(IpMode::Ipv4),
+            "ipv6" => Some(IpMode::Ipv6),
+            "dual" => Some(IpMode::DualStack),
+            _ => None
+        }
+    }
+}
+
+/// Server wide options, read from the command line at startup
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    // word list to draw board words from, the embedded english list is used if not set
+    pub word_list_path : Option<PathBuf>,
+    // directory of '<language code>.txt' word lists rooms can pick with 'set lang'
+    pub word_lists_dir : Option<PathBuf>,
+    // most players a single room can hold
+    pub max_room_players : usize,
+    // password for logging in with '/admin', admin commands are off if not set
+    pub admin_password : Option<String>,
+    // IP versions the listener accepts
+    pub ip_mode : IpMode,
+    // file rooms are saved to and restored from on startup, nothing is saved if not set
+    pub save_path : Option<PathBuf>,
+    // file '/report' appends players' reports to, reports are turned down if not set
+    pub report_path : Option<PathBuf>,
+    // JSON file of prompt texts replacing the built in ones, for translations and the like
+    pub prompts_path : Option<PathBuf>,
+    // message of the day shown to new clients instead of the default banner,
+    // {players}, {rooms} and {uptime} in it are filled in
+    pub motd : Option<String>,
+    // size of the buffer each read from a client's socket goes into, bigger buffers take
+    // fewer system calls for a long line or a paste, smaller ones use less memory per read
+    pub read_buf_size : usize,
+    // input that isn't valid UTF-8 has the bad bytes replaced instead of being
+    // thrown away, so clients sending Latin-1 and the like can still play
+    pub lossy_utf8 : bool,
+    // most clients connected at once, more are told the server is full and let go
+    pub max_connections : usize,
+    // longest the event loop waits for a client's socket before checking timers and
+    // the shutdown flag, clients are answered as soon as they send whatever this is
+    pub idle_sleep : Duration,
+    // how often connections are checked for a client that went away without closing them,
+    // with TCP keepalives and a telnet no-op, None to not check
+    pub keepalive : Option<Duration>,
+    // least serious events written to the log, info shows all the traffic
+    pub log_level : Level
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            word_list_path: None,
+            word_lists_dir: None,
+            max_room_players: 20,
+            admin_password: None,
+            ip_mode: IpMode::Ipv4,
+            save_path: None,
+            report_path: None,
+            prompts_path: None,
+            motd: None,
+            read_buf_size: 1024,
+            lossy_utf8: false,
+            max_connections: 256,
+            idle_sleep: Duration::from_millis(15),
+            keepalive: Some(Duration::from_secs(60)),
+            log_level: Level::Info
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parses the command line arguments (without the program name)
+    pub fn from_args(args : impl IntoIterator<Item = String>) -> Result<ServerConfig> {
+        let mut config = ServerConfig::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--words" => {
+                    config.word_list_path = Some(PathBuf::from(expect_value(&arg, args.next())?));
+                },
+                "--word-lists" => {
+                    config.word_lists_dir = Some(PathBuf::from(expect_value(&arg, args.next())?));
+                },
+                "--save-file" => {
+                    config.save_path = Some(PathBuf::from(expect_value(&arg, args.next())?));
+                },
+                "--report-file" => {
+                    config.report_path = Some(PathBuf::from(expect_value(&arg, args.next())?));
+                },
+                "--prompts" => {
+                    config.prompts_path = Some(PathBuf::from(expect_value(&arg, args.next())?));
+                },
+                "--max-players" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.max_room_players = value.parse::<usize>().ok()
+                        .filter(|max| *max > 0)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                                                  format!("{} must be a positive number, got {}", arg, value)))?;
+                },
+                "--read-buffer" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.read_buf_size = value.parse::<usize>().ok()
+                        .filter(|size| *size > 0)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                                                  format!("{} must be a positive number of bytes, got {}", arg, value)))?;
+                },
+                "--lossy-utf8" => {
+                    config.lossy_utf8 = true;
+                },
+                "--max-connections" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.max_connections = value.parse::<usize>().ok()
+                        .filter(|max| *max > 0)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                                                  format!("{} must be a positive number, got {}", arg, value)))?;
+                },
+                "--idle-sleep" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.idle_sleep = value.parse::<u64>().ok()
+                        .map(Duration::from_millis)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                                                  format!("{} must be a number of milliseconds, got {}", arg, value)))?;
+                },
+                "--keepalive" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.keepalive = match value.as_str() {
+                        "off" => None,
+                        _ => Some(value.parse::<u64>().ok()
+                            .filter(|secs| *secs > 0)
+                            .map(Duration::from_secs)
+                            .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                                                      format!("{} must be off or a positive number of seconds, got {}",
+                                                              arg, value)))?)
+                    };
+                },
+                "--ip-mode" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.ip_mode = IpMode::parse(&value)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                                                  format!("{} must be ipv4, ipv6 or dual, got {}", arg, value)))?;
+                },
+                "--log-level" => {
+                    let value = expect_value(&arg, args.next())?;
+                    config.log_level = Level::parse(&value)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                                                  format!("{} must be info, warn or error, got {}", arg, value)))?;
+                },
+                "--motd" => {
+                    config.motd = Some(expect_value(&arg, args.next())?);
+                },
+                "--motd-file" => {
+                    let path = expect_value(&arg, args.next())?;
+                    config.motd = Some(std::fs::read_to_string(&path)
+                        .map_err(|e| Error::new(e.kind(), format!("Couldn't read MOTD file {}: {}", path, e)))?);
+                },
+                "--admin-password" => {
+                    config.admin_password = Some(expect_value(&arg, args.next())?);
+                },
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("Unknown argument {}", arg)))
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn expect_value(arg : &str, value : Option<String>) -> Result<String> {
+    value.ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Missing value for {}", arg)))
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// least serious level that's written out, set from the config at startup
+static MIN_LEVEL : AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// How serious a logged event is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info, // normal traffic, connects and disconnects
+    Warn, // recoverable errors
+    Error // errors that drop a connection or stop the server
+}
+
+impl Level {
+    /// The level for the name given on the command line
+    pub(crate) fn parse(name : &str) -> Option<Level> {
+        match name {
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Info => write!(f, "INFO"),
+            Level::Warn => write!(f, "WARN"),
+            Level::Error => write!(f, "ERROR")
+        }
+    }
+}
+
+/// Drops every event less serious than the given level from then on
+pub fn set_log_level(level : Level) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Writes out a single event, prefixed with its level so the output can be filtered,
+/// unless it's less serious than the level set from the config
+pub fn log_event(level : Level, message : &str) {
+    if (level as u8) < MIN_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    println!("[{}] {}", level, message);
+}
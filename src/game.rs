@@ -1,7 +1,23 @@
-use std::{net::{TcpStream, SocketAddr}, collections::HashMap};
+use std::{net::SocketAddr, collections::{HashMap, HashSet, VecDeque}};
+use std::any::Any;
 use std::cmp::max;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::codenames::{codenames_logic, CodenamesRoom, CodenamesPlayer, codenames_prompt, codenames_disconnect};
+use crate::codenames::{CodenamesTeam, CodenamesRole, CodenamesRoom, CodenamesPlayer,
+                       GameResult, load_word_lists, WordLists, RoomVariant, ChatLimit};
+use crate::chat_room::ChatRoom;
+use crate::admin::{admin_logic, is_admin_command};
+use crate::config::ServerConfig;
+use crate::event_log::{log_event, set_log_level, Level};
+use crate::persist::{load_rooms, save_rooms};
+use crate::prompts::Prompts;
+use crate::report::report_logic;
 
 // State of the user in the server
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -11,142 +27,1000 @@ pub enum ServerState {
     LobbySelection, // Selecting lobby
     InvalidInput, // Any time invalid input is inserted
     InRoom, // In game room
+    RecentRooms, // Listing recently played rooms
+    UsernameTaken, // Username is already used by someone in the selected room
+    RoomFull, // The selected room has no space left
+    RejoinOffer, // A seat is being held for this username in a game they dropped from
+    JoinInProgress, // The picked room's game is underway, asked whether to watch it instead
+    RoomPasswordSetup, // Choosing a password for a newly created room
+    RoomPasswordEntry, // Entering the password of a protected room
+    Kicked, // Voted out of the room by the other players
+    AdminLogin, // Entering the admin password
     FatalError
 }
 
+// Maximum number of rooms remembered per username
+const MAX_RECENT_ROOMS : usize = 5;
+const MAX_USERNAME_LEN : usize = 25;
+// how long rooms restored from a save wait for anyone to come back before they're closed
+pub(crate) const RECLAIM_WINDOW : Duration = Duration::from_secs(10 * 60);
+// fewest letters a lobby command can be shortened to, anything shorter is chat
+const MIN_LOBBY_PREFIX_LEN : usize = 3;
+// short forms for the commands typed most, alias to the command it stands for
+const COMMAND_ALIASES : [(&str, &str); 5] = [
+    ("sm", "spymaster"),
+    ("tm", "teammate"),
+    ("r", "red"),
+    ("b", "blue"),
+    ("s", "show")
+];
+
+/// A game played in a room, the room hands it the input and prompts of everyone in it
+pub trait Game : Any + Send {
+    /// Output for the given player since their last prompt, None if there's nothing new
+    /// a user polled for the first time in the room becomes one of its players
+    fn prompt(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+              prompts : &Prompts) -> Option<String>;
+
+    /// Processes the input from a player, returns the game's result if this input ended the game
+    /// a user sending input for the first time in the room becomes one of its players
+    fn logic(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>,
+             line : &Option<String>) -> Option<GameResult>;
+
+    /// Takes a player that disconnected out of the game, telling everyone else
+    fn disconnect(&mut self, user_addr : SocketAddr, user_state_map : &mut HashMap<SocketAddr, User>);
+
+    /// Tells the room a player changed their name
+    fn rename(&mut self, old_name : &str, new_name : &str, user_state_map : &mut HashMap<SocketAddr, User>);
+
+    /// Addresses of everyone in the room
+    fn players(&self) -> &HashSet<SocketAddr>;
+
+    /// Where the game is at, for the lobby listing
+    fn status(&self) -> &'static str;
+
+    /// Whether the game is over
+    fn has_ended(&self) -> bool;
+
+    /// Whether seats are being kept for players that dropped, which keeps the room open with nobody in it
+    fn holds_seats(&self) -> bool {
+        false
+    }
+
+    /// Whether the user can be added without going over the given number of players
+    fn has_space_for(&self, _user_name : &str, max_players : usize) -> bool {
+        self.players().len() < max_players
+    }
+
+    /// Shown after the room's name in the lobby listing, like how the game is played
+    fn listing_tag(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether a user coming in now could only watch, like while a game is underway,
+    /// they're asked if that's what they want before going in
+    fn joins_as_observer(&self, _user_name : &str) -> bool {
+        false
+    }
+
+    /// Whether a seat is being held for this username
+    fn has_reserved_seat(&self, _user_name : &str) -> bool {
+        false
+    }
+
+    /// Sets up a user coming into the room, before they're first polled in it
+    fn admit(&mut self, _user_addr : SocketAddr, _user_state_map : &mut HashMap<SocketAddr, User>) {}
+
+    /// Stops waiting for the players of a game restored from a save
+    fn resume(&mut self, _now : Instant) {}
+
+    /// Gives up the seats held for too long, returns whether any were given up
+    fn release_expired_seats(&mut self, _user_state_map : &mut HashMap<SocketAddr, User>, _now : Instant) -> bool {
+        false
+    }
+
+    /// Moves the game on if a turn ran out of time, returns whether one did
+    fn expire_turn_timer(&mut self, _user_state_map : &mut HashMap<SocketAddr, User>, _now : Instant) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Which game a room plays, picked when the room is created
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum GameKind {
+    #[default]
+    Codenames,
+    // no game, just a place to talk
+    Chat
+}
+
+impl GameKind {
+    /// Sets up a new game of this kind, the variant only matters to Codenames
+    pub(crate) fn new_game(self, variant : RoomVariant, word_lists : &Arc<WordLists>) -> Box<dyn Game> {
+        match self {
+            GameKind::Codenames => Box::new(CodenamesRoom::deal(variant, word_lists)),
+            GameKind::Chat => Box::<ChatRoom>::default()
+        }
+    }
+}
+
 pub struct GameRoom {
     pub name : String,
-    pub impl_room : Option<CodenamesRoom>
+    pub game : Box<dyn Game>,
+    pub kind : GameKind,
+    // rooms with a password can only be joined by entering it
+    pub password : Option<RoomPassword>
+}
+
+impl GameRoom {
+    /// A room with a new game of the given kind set up in it
+    pub fn new(name : String, kind : GameKind, variant : RoomVariant, word_lists : &Arc<WordLists>) -> GameRoom {
+        GameRoom { name, game: kind.new_game(variant, word_lists), kind, password: None }
+    }
+
+    /// The room's Codenames game, if it's playing one
+    pub fn codenames(&self) -> Option<&CodenamesRoom> {
+        self.game.as_any().downcast_ref()
+    }
+
+    pub fn codenames_mut(&mut self) -> Option<&mut CodenamesRoom> {
+        self.game.as_any_mut().downcast_mut()
+    }
+}
+
+/// A salted hash of a room's password, the password itself isn't kept
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RoomPassword {
+    salt : [u8; 16],
+    hash : [u8; 32]
+}
+
+impl RoomPassword {
+    pub fn new(password : &str) -> RoomPassword {
+        let salt = thread_rng().gen::<[u8; 16]>();
+        RoomPassword { salt, hash: hash_password(&salt, password) }
+    }
+
+    pub fn matches(&self, password : &str) -> bool {
+        hash_password(&self.salt, password) == self.hash
+    }
+}
+
+fn hash_password(salt : &[u8], password : &str) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(salt)
+        .chain_update(password.as_bytes())
+        .finalize()
+        .into()
+}
+
+/// How the user's client wants lines to end, the game writes \r\n which telnet expects
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    CrLf,
+    // raw socket clients like netcat
+    Lf
+}
+
+/// How a user's games have gone since they connected
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SessionStats {
+    pub played : u32,
+    pub wins : u32,
+    pub losses : u32
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Games this session: {} played, {} won, {} lost", self.played, self.wins, self.losses)
+    }
 }
 
 #[derive(Clone)]
 pub struct User {
     pub prev_prompt : String,
+    // output queued for the socket that hasn't been sent yet
+    pub outbound : Vec<u8>,
+    // start of a line that's still being read from the socket
+    pub inbound : Vec<u8>,
+    // the rest of an overlong line is being thrown away as it arrives, up to its newline
+    pub discarding : bool,
     pub state : ServerState,
     prev_state : ServerState,
     pub user_name : String,
     pub socket_addr : SocketAddr,
     pub game_room_key : Option<i32>,
-    pub player : Option<CodenamesPlayer>
+    // room the user is setting or entering a password for
+    pub(crate) pending_room_key : Option<i32>,
+    pub player : Option<CodenamesPlayer>,
+    // logged in with the admin password, can use the admin commands
+    pub is_admin : bool,
+    // terminal width the client reported over telnet, if it did
+    pub window_width : Option<u16>,
+    // has been told their input isn't UTF-8, so they aren't told on every line
+    pub encoding_warned : bool,
+    // how fast the user can chat in the lobby, rooms keep their own limit on the player
+    pub(crate) lobby_chat_limit : ChatLimit,
+    // picked from how the username line ended
+    pub line_ending : LineEnding,
+    // tells apart connections from the same address, 0 for users not behind a socket
+    pub connection : u64,
+    // games finished while connected, gone once they disconnect
+    pub stats : SessionStats
+}
+
+impl User {
+    /// Remembers the state the user just left, for invalid input and the admin login to
+    /// go back to, those two are detours themselves so going back never lands in them
+    /// and bad input while already in one doesn't lose where the user came from
+    fn note_state_change(&mut self, starting_state : ServerState) {
+        if self.state != starting_state &&
+            !matches!(starting_state, ServerState::InvalidInput | ServerState::AdminLogin) {
+            self.prev_state = starting_state;
+        }
+    }
+
+    /// Adds text to the output waiting to be sent, with the user's line endings
+    pub fn queue(&mut self, text : &str) {
+        let text = out(text, self);
+        self.outbound.extend_from_slice(text.as_bytes());
+    }
+}
+
+/// Converts the game's \r\n line endings to the ones the user's client wants
+pub fn out(text : &str, user : &User) -> String {
+    match user.line_ending {
+        LineEnding::CrLf => text.to_string(),
+        LineEnding::Lf => text.replace("\r\n", "\n")
+    }
 }
 
 pub struct GameServerState {
     pub user_state : HashMap<SocketAddr, User>,
-    pub game_rooms : HashMap<i32, GameRoom>
+    pub game_rooms : HashMap<i32, GameRoom>,
+    // room keys and names recently played in, by username, most recent first
+    pub recent_rooms : HashMap<String, VecDeque<(i32, String)>>,
+    // called with the result every time a game in any room ends
+    pub on_game_end : Option<Box<dyn FnMut(GameResult) + Send>>,
+    pub config : ServerConfig,
+    // word lists boards are drawn from, by language code
+    pub(crate) word_lists : Arc<WordLists>,
+    // text of the prompts, built in or from the prompts file
+    pub(crate) prompts : Prompts,
+    // when the server started, for the uptime in the welcome banner
+    started : Instant,
+    // id of the most recently accepted connection
+    last_connection : u64,
+    // users whose prompt or queued output may have changed since the event loop last
+    // went through them, only these and the clients with something to read are polled
+    dirty : HashSet<SocketAddr>,
+    // the rooms the lobby last listed, a change to them has to reach everyone in the lobby
+    lobby_listing : String,
+    // when rooms restored from the save file that nobody came back to are closed
+    reclaim_deadline : Option<Instant>
 }
 
+/// An error from the game logic that the client can't carry on after
+#[derive(Debug)]
 pub struct GameError {
+    pub message : String
+}
 
+impl GameError {
+    pub fn new(message : impl Into<String>) -> GameError {
+        GameError { message: message.into() }
+    }
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
 impl GameServerState {
     fn get_lobby_listing(&self) -> String {
         let rooms = &self.game_rooms;
-        let mut out = "0: New Lobby\r\n".to_string();
+        let mut out = "0: New Lobby ('0 double' for the double agent variant, '0 chat' for a chat room)\r\n".to_string();
         let mut vals : Vec<(i32, &GameRoom)> = rooms.iter().map(|x| (*x.0, x.1)).collect();
-        vals.sort_by(|a, b| a.0.cmp(&b.0));
+        vals.sort_by_key(|a| a.0);
         for room_val in vals {
-            out.push_str(&format!("{}: {:>15}\r\n", room_val.0, room_val.1.name));
+            let locked = if room_val.1.password.is_some() { " (password)" } else { "" };
+            let game = &room_val.1.game;
+            let tag = game.listing_tag().map_or(String::new(), |tag| format!(" ({})", tag));
+            out.push_str(&format!("{}: {:>15}{}{} - {}/{} players, {}\r\n", room_val.0, room_val.1.name, locked,
+                                  tag, game.players().len(), self.config.max_room_players, game.status()));
         }
         out
     }
 
-    pub fn get_client_prompt(&mut self, stream : &mut TcpStream) -> Option<String> {
+    fn get_recent_listing(&self, user_name : &str) -> String {
+        let mut out = String::new();
+        for (room_key, room_name) in self.recent_rooms.get(user_name).into_iter().flatten() {
+            if room_is_alive(&self.game_rooms, *room_key, room_name) {
+                out.push_str(&format!("{}: {:>15}\r\n", room_key, room_name));
+            } else {
+                out.push_str(&format!("-: {:>15} (unavailable)\r\n", room_name));
+            }
+        }
+        if out.is_empty() {
+            out.push_str("No recent rooms\r\n");
+        }
+        out
+    }
+
+    /// The first thing a new client sees, the configured message of the day
+    /// with the player count, room count and uptime filled in, or the default banner
+    fn welcome_banner(&self) -> String {
+        let Some(motd) = &self.config.motd else {
+            return self.prompts.get("welcome", &[]);
+        };
+        let uptime = self.started.elapsed().as_secs();
+        motd.replace("{players}", &self.active_connections().to_string())
+            .replace("{rooms}", &self.active_rooms().to_string())
+            .replace("{uptime}", &format!("{}h {}m", uptime / 3600, uptime / 60 % 60))
+            .lines()
+            // telnet wants \r\n whatever line endings the message was written with
+            .map(|line| line.to_string() + "\r\n")
+            .collect()
+    }
+
+    /// The prompt for the user at the given address, for clients that
+    /// aren't behind a socket, like bots
+    pub fn prompt_for(&mut self, addr : SocketAddr) -> Option<String> {
+        // games seat a player the first time they're prompted, which the rest of the room sees
+        let room_key = self.user_state.get(&addr).and_then(|user| user.game_room_key);
+        let players = self.room_size(room_key);
+        let prompt = self.build_prompt(addr);
+        if self.room_size(room_key) != players {
+            self.mark_rooms_dirty(room_key.as_slice());
+        }
+        prompt
+    }
+
+    fn build_prompt(&mut self, addr : SocketAddr) -> Option<String> {
         let user_state_map = &mut self.user_state;
-        let user_state = get_user_state(user_state_map, stream);
+        let user_state = get_user_state(user_state_map, addr);
         match user_state.state {
-            ServerState::Joined => {
-                Some("Connected to Telnet Codenames\r\n".to_string())
+            ServerState::Joined => Some(self.welcome_banner()),
+            ServerState::UsernameEntry => Some(self.prompts.get("username_entry", &[("max", &MAX_USERNAME_LEN)])),
+            ServerState::LobbySelection => Some(self.prompts.get("lobby", &[]) + &self.get_lobby_listing()),
+            ServerState::RecentRooms => {
+                let user_name = user_state.user_name.to_string();
+                Some(self.prompts.get("recent_rooms", &[]) + &self.get_recent_listing(&user_name))
             },
-            ServerState::UsernameEntry =>
-                Some("Enter in your username, maximum of 25 characters\r\n".to_string()),
-            ServerState::LobbySelection => {
-                Some("Which lobby do you want to join? Or create a new lobby\r\n".to_string() +
-                    &self.get_lobby_listing())
+            ServerState::InvalidInput => Some(self.prompts.get("invalid_input", &[])),
+            ServerState::UsernameTaken => Some(self.prompts.get("username_taken", &[("name", &user_state.user_name)])),
+            ServerState::RoomPasswordSetup => Some(self.prompts.get("password_setup", &[])),
+            ServerState::RoomPasswordEntry => Some(self.prompts.get("password_entry", &[])),
+            ServerState::RoomFull => Some(self.prompts.get("room_full", &[])),
+            ServerState::Kicked => Some(self.prompts.get("kicked", &[])),
+            ServerState::AdminLogin => Some(self.prompts.get("admin_login", &[])),
+            ServerState::JoinInProgress => Some(self.prompts.get("join_in_progress", &[])),
+            ServerState::RejoinOffer => {
+                let room_name = reserved_room(&self.game_rooms, &user_state.user_name)
+                    .map_or("", |room_key| &self.game_rooms[&room_key].name);
+                Some(self.prompts.get("rejoin_offer", &[("room", &room_name)]))
             },
-            ServerState::InvalidInput => {
-                Some("Invalid input, please try again\r\n".to_string())
+            ServerState::InRoom => {
+                let game = room_game(user_state, &mut self.game_rooms)?;
+                game.prompt(addr, user_state_map, &self.prompts)
             },
-            ServerState::InRoom => codenames_prompt(stream,
-                                                    user_state_map,
-                                                    &mut self.game_rooms),
-            ServerState::FatalError => {
-                Some("A fatal error has occurred, disconnecting...\r\n".to_string())
-            }
+            ServerState::FatalError => Some(self.prompts.get("fatal_error", &[]))
         }
     }
     
-    pub fn client_logic(&mut self, stream : &mut TcpStream, line : Option<String>) -> Result<(), GameError> {
+    /// Runs the logic for the user at the given address, None is a poll without input
+    pub fn handle_input(&mut self, addr : SocketAddr, line : Option<String>) -> Result<(), GameError> {
+        let before = self.user_state.get(&addr)
+            .map(|user| (user.state, user.game_room_key, user.outbound.len()));
+        let room_before = before.and_then(|(_, room_key, _)| room_key);
+        let players_before = self.room_size(room_before);
+        let sent_line = line.is_some();
+        let result = self.run_input(addr, line);
+        let after = self.user_state.get(&addr)
+            .map(|user| (user.state, user.game_room_key, user.outbound.len()));
+        let room_after = after.and_then(|(_, room_key, _)| room_key);
+        let moved = before.map(|(state, room_key, _)| (state, room_key)) != after.map(|(state, room_key, _)| (state, room_key));
+        if sent_line || moved || self.room_size(room_before) != players_before {
+            // anything said or done can show up for everyone in the room, or the lobby
+            self.dirty.insert(addr);
+            let room_keys : Vec<i32> = room_before.into_iter().chain(room_after).collect();
+            self.mark_rooms_dirty(&room_keys);
+            if sent_line && before.is_some_and(|(state, _, _)| state == ServerState::LobbySelection) {
+                self.mark_lobby_dirty();
+            }
+        } else if after.is_some_and(|(_, _, queued)| queued > before.map_or(0, |(_, _, queued)| queued)) {
+            self.dirty.insert(addr);
+        }
+        result
+    }
+
+    /// Number of players in the given room, None if there's no such room
+    fn room_size(&self, room_key : Option<i32>) -> Option<usize> {
+        room_key.and_then(|room_key| self.game_rooms.get(&room_key))
+            .map(|room| room.game.players().len())
+    }
+
+    /// Marks the user at the given address as needing to be polled by the event loop
+    pub(crate) fn mark_dirty(&mut self, addr : SocketAddr) {
+        self.dirty.insert(addr);
+    }
+
+    /// Marks everyone in the given rooms as dirty, along with everyone in
+    /// the lobby if that changed the rooms it lists
+    fn mark_rooms_dirty(&mut self, room_keys : &[i32]) {
+        for room_key in room_keys {
+            if let Some(room) = self.game_rooms.get(room_key) {
+                self.dirty.extend(room.game.players());
+            }
+        }
+        let lobby_listing = self.get_lobby_listing();
+        if lobby_listing != self.lobby_listing {
+            self.lobby_listing = lobby_listing;
+            self.mark_lobby_dirty();
+        }
+    }
+
+    /// Marks everyone looking at the lobby or their recent rooms as dirty
+    fn mark_lobby_dirty(&mut self) {
+        self.dirty.extend(self.user_state.values()
+            .filter(|user| matches!(user.state, ServerState::LobbySelection | ServerState::RecentRooms))
+            .map(|user| user.socket_addr));
+    }
+
+    /// Takes the users whose prompt or queued output may have changed, the event loop
+    /// polls them along with the clients that have something to read
+    pub fn take_dirty(&mut self) -> HashSet<SocketAddr> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Whether any user is waiting to be polled
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    fn run_input(&mut self, addr : SocketAddr, line : Option<String>) -> Result<(), GameError> {
+        // admin commands go ahead of everything else, except a password that looks like one
+        let logging_in = self.user_state.get(&addr).is_some_and(|u| u.state == ServerState::AdminLogin);
+        if let Some(line) = line.as_deref().filter(|line| !logging_in && is_admin_command(line)) {
+            let starting_state = get_user_state(&mut self.user_state, addr).state;
+            admin_logic(addr, line, self);
+            get_user_state(&mut self.user_state, addr).note_state_change(starting_state);
+            return Ok(());
+        }
         let user_state_map = &mut self.user_state;
-        let user_state = get_user_state(user_state_map, stream);
+        let user_state = get_user_state(user_state_map, addr);
         let game_rooms = &mut self.game_rooms;
         let starting_state = user_state.state;
+        match line.as_deref().map(|l| l.trim().split_once(' ').unwrap_or((l.trim(), ""))) {
+            // names can be changed from the lobby or a room
+            Some(("/nick", new_name)) if matches!(starting_state, ServerState::LobbySelection | ServerState::InRoom) => {
+                change_username(addr, new_name, user_state_map, game_rooms);
+                return Ok(());
+            },
+            Some(("stats", "")) if matches!(starting_state, ServerState::LobbySelection | ServerState::InRoom) => {
+                let stats = user_state.stats;
+                user_state.queue(&format!("{}\r\n", stats));
+                return Ok(());
+            },
+            Some(("/report", args)) if starting_state == ServerState::InRoom => {
+                report_logic(addr, args, self.config.report_path.as_deref(), user_state_map);
+                return Ok(());
+            },
+            _ => {}
+        }
         match user_state.state {
             ServerState::Joined => {
                 user_state.state = ServerState::UsernameEntry;
             },
-            ServerState::UsernameEntry => {
-                // TODO: factor out into username entry logic?
-                if line.is_some() {
-                    match line.filter(|l| l.len() <= 25) {
-                        Some(l) => {
-                            user_state.user_name = l.trim().to_string();
-                            user_state.state = ServerState::LobbySelection;
-                        },
-                        None => {
-                            user_state.state = ServerState::InvalidInput;
+            ServerState::UsernameEntry => username_entry_logic(user_state, game_rooms, &line),
+            ServerState::LobbySelection => {
+                let line = line.map(|line| expand_lobby_command(&line));
+                if let Some(chat_line) = line.as_deref().filter(|line| is_lobby_chat(line)) {
+                    lobby_chat(addr, chat_line, user_state_map);
+                } else if let Some(room_key) = lobby_selection_logic(user_state, game_rooms, &self.word_lists, &line) {
+                    select_room(addr, room_key, user_state_map, game_rooms, &mut self.recent_rooms,
+                                self.config.max_room_players);
+                }
+            },
+            ServerState::RecentRooms => {
+                if let Some(room_key) = recent_rooms_logic(user_state, game_rooms, &self.recent_rooms, &line) {
+                    select_room(addr, room_key, user_state_map, game_rooms, &mut self.recent_rooms,
+                                self.config.max_room_players);
+                }
+            },
+            ServerState::RoomPasswordSetup => {
+                if let (Some(line), Some(room_key)) = (&line, user_state.pending_room_key.take()) {
+                    if let Some(room) = game_rooms.get_mut(&room_key) {
+                        let password = line.trim();
+                        if !password.is_empty() {
+                            room.password = Some(RoomPassword::new(password));
                         }
                     }
+                    enter_room(addr, room_key, user_state_map, game_rooms, &mut self.recent_rooms,
+                               self.config.max_room_players);
+                }
+            },
+            ServerState::RoomPasswordEntry => {
+                if let (Some(line), Some(room_key)) = (&line, user_state.pending_room_key) {
+                    let password = line.trim();
+                    if password.is_empty() {
+                        user_state.pending_room_key = None;
+                        user_state.player = None;
+                        user_state.state = ServerState::LobbySelection;
+                    } else if game_rooms.get(&room_key)
+                        .and_then(|room| room.password.as_ref())
+                        .is_some_and(|p| p.matches(password)) {
+                        user_state.pending_room_key = None;
+                        enter_room(addr, room_key, user_state_map, game_rooms, &mut self.recent_rooms,
+                                   self.config.max_room_players);
+                    } else {
+                        user_state.state = ServerState::InvalidInput;
+                    }
                 }
             },
-            ServerState::LobbySelection => lobby_selection_logic(user_state, game_rooms, &line),
             ServerState::InvalidInput => {
                 // go back to the last state
                 user_state.state = user_state.prev_state;
             },
+            ServerState::UsernameTaken => {
+                user_state.state = ServerState::UsernameEntry;
+            },
+            ServerState::RoomFull | ServerState::Kicked => {
+                user_state.state = ServerState::LobbySelection;
+            },
+            ServerState::AdminLogin => {
+                if let Some(line) = &line {
+                    let password = line.trim();
+                    if password.is_empty() {
+                        // changed their mind
+                    } else if self.config.admin_password.as_deref() == Some(password) {
+                        user_state.is_admin = true;
+                        user_state.queue("Logged in as admin\r\n");
+                    } else {
+                        user_state.queue("Wrong admin password\r\n");
+                    }
+                    // back to wherever they were, showing it again
+                    user_state.state = user_state.prev_state;
+                    if let Some(player) = user_state.player.as_mut() {
+                        player.reprompt();
+                    }
+                }
+            },
+            ServerState::RejoinOffer => {
+                if let Some(line) = &line {
+                    let room_key = reserved_room(game_rooms, &user_state.user_name);
+                    match (line.trim(), room_key) {
+                        ("y" | "yes", Some(room_key)) =>
+                            enter_room(addr, room_key, user_state_map, game_rooms, &mut self.recent_rooms,
+                               self.config.max_room_players),
+                        _ => user_state.state = ServerState::LobbySelection
+                    }
+                }
+            },
+            ServerState::JoinInProgress => {
+                if let (Some(line), Some(room_key)) = (&line, user_state.pending_room_key.take()) {
+                    if matches!(line.trim(), "y" | "yes") {
+                        let mut player = CodenamesPlayer::new(CodenamesTeam::Floating, CodenamesRole::Observer);
+                        player.queue_chat("You're watching this game, join the room from the lobby once it's \
+                            over to play in the next one\r\n".to_string());
+                        user_state.player = Some(player);
+                        enter_room(addr, room_key, user_state_map, game_rooms, &mut self.recent_rooms,
+                                   self.config.max_room_players);
+                    } else {
+                        user_state.state = ServerState::LobbySelection;
+                    }
+                }
+            },
             ServerState::FatalError => {
-                return Err(GameError {  });
+                return Err(GameError::new("the game hit an unrecoverable error"));
+            }
+            ServerState::InRoom => {
+                let Some(game) = room_game(user_state, game_rooms) else {
+                    return Ok(());
+                };
+                let result = game.logic(addr, user_state_map, &line);
+                if let (Some(result), Some(on_game_end)) = (result, self.on_game_end.as_mut()) {
+                    on_game_end(result);
+                }
             }
-            ServerState::InRoom => codenames_logic(stream,
-                                                   user_state_map,
-                                                   &mut self.game_rooms,
-                                                   &line)
         }
         // keep track of previous states
-        let user_state = get_user_state(user_state_map, stream);
-        if user_state.state != starting_state {
-            user_state.prev_state = starting_state;
-        }
+        get_user_state(user_state_map, addr).note_state_change(starting_state);
         Ok(())
     }
 
-    pub fn client_disconnect(&mut self, stream : &mut TcpStream) {
+    pub fn client_disconnect(&mut self, stream : &mut mio::net::TcpStream, addr : SocketAddr) {
         // do any disconnect actions
-        let _ = super::write(stream, "Goodbye\r\n");
-        let addr = stream.peer_addr().unwrap();
-        codenames_disconnect(addr, &mut self.game_rooms, &mut self.user_state);
+        let goodbye = self.user_state.get(&addr).map_or("Goodbye\r\n".to_string(), |user| out("Goodbye\r\n", user));
+        let _ = super::write(stream, &goodbye);
+        self.remove_client(addr);
+    }
+
+    /// Starts tracking a newly accepted connection, a user still kept under its address
+    /// is left over from an earlier connection whose disconnect was missed, they're taken
+    /// out first so the new client doesn't inherit their name, room and seat
+    pub fn connect(&mut self, addr : SocketAddr) -> &mut User {
+        if let Some(stale) = self.user_state.get(&addr) {
+            log_event(Level::Warn, &format!("Connection {} from {} was never cleaned up, replacing it",
+                                            stale.connection, addr));
+            self.remove_client(addr);
+        }
+        self.last_connection += 1;
+        let user_state = get_user_state(&mut self.user_state, addr);
+        user_state.connection = self.last_connection;
+        user_state
+    }
+
+    /// Takes the user at the given address out of its room and stops tracking it
+    pub fn remove_client(&mut self, addr : SocketAddr) {
+        // notify anyone in the user's room that they've left
+        let mut left = Vec::new();
+        for (room_key, room) in self.game_rooms.iter_mut() {
+            if room.game.players().contains(&addr) {
+                room.game.disconnect(addr, &mut self.user_state);
+                left.push(*room_key);
+            }
+        }
+        // a room still being set up, or one they hadn't been seated in yet
+        if let Some(user_state) = self.user_state.get(&addr) {
+            left.extend(user_state.pending_room_key.into_iter().chain(user_state.game_room_key));
+        }
         // remove user state from being tracked
         self.user_state.remove(&addr);
+        // the last one out closes the room, unless it's holding seats for players coming back
+        self.close_abandoned_rooms(&left);
+        self.mark_rooms_dirty(&left);
+    }
+
+    /// Closes any of the given rooms that nobody is in, on their way into or coming back to
+    fn close_abandoned_rooms(&mut self, room_keys : &[i32]) {
+        for room_key in room_keys {
+            let abandoned = self.game_rooms.get(room_key).is_some_and(|room| {
+                room.game.players().is_empty() && !room.game.holds_seats() && !self.is_heading_into(*room_key)
+            });
+            if abandoned {
+                self.game_rooms.remove(room_key);
+            }
+        }
+    }
+
+    /// Whether any user is on their way into the room, setting it up or about to be seated
+    fn is_heading_into(&self, room_key : i32) -> bool {
+        self.user_state.values().any(|user| {
+            user.game_room_key == Some(room_key) || user.pending_room_key == Some(room_key)
+        })
+    }
+
+    /// Closes the rooms nobody came back to after a restart, the ones someone
+    /// did come back to stop waiting and hold the rest of their seats as usual
+    fn close_unclaimed_rooms(&mut self, now : Instant) {
+        let unclaimed : Vec<i32> = self.game_rooms.iter()
+            .filter(|(room_key, room)| {
+                room.game.players().is_empty() && !self.is_heading_into(**room_key)
+            })
+            .map(|(room_key, _)| *room_key)
+            .collect();
+        for room_key in &unclaimed {
+            log_event(Level::Info, &format!("Closing room {}, nobody came back to it", room_key));
+            self.game_rooms.remove(room_key);
+        }
+        for room in self.game_rooms.values_mut() {
+            room.game.resume(now);
+        }
+        self.mark_rooms_dirty(&[]);
+    }
+
+    /// Number of clients being tracked, whether they're behind a socket or not
+    pub fn active_connections(&self) -> usize {
+        self.user_state.len()
+    }
+
+    /// Number of rooms with at least one player in them
+    pub fn active_rooms(&self) -> usize {
+        self.game_rooms.values()
+            .filter(|room| !room.game.players().is_empty())
+            .count()
+    }
+
+    /// Opens a new empty room, returns its key
+    pub fn create_room(&mut self, name : &str) -> i32 {
+        self.create_room_with_variant(name, RoomVariant::Standard)
+    }
+
+    /// Opens a new empty room played with the given variant, returns its key
+    pub fn create_room_with_variant(&mut self, name : &str, variant : RoomVariant) -> i32 {
+        let room_key = find_empty_slot(&self.game_rooms);
+        self.game_rooms.insert(room_key, GameRoom::new(name.to_string(), GameKind::Codenames, variant,
+                                                       &self.word_lists));
+        room_key
+    }
+
+    /// Seats a user that isn't behind a socket, like a bot, in a room with the given
+    /// team and role, the same checks apply as for users joining from the lobby
+    pub fn join_room(&mut self, addr : SocketAddr, user_name : &str, room_key : i32,
+                     team : CodenamesTeam, role : CodenamesRole) -> Result<(), GameError> {
+        if self.user_state.contains_key(&addr) {
+            return Err(GameError::new(format!("{} is already connected", addr)));
+        }
+        let user_state = get_user_state(&mut self.user_state, addr);
+        user_state.user_name = user_name.to_string();
+        enter_room(addr, room_key, &mut self.user_state, &mut self.game_rooms, &mut self.recent_rooms,
+                   self.config.max_room_players);
+        let user_state = get_user_state(&mut self.user_state, addr);
+        if user_state.state != ServerState::InRoom {
+            self.user_state.remove(&addr);
+            return Err(GameError::new(format!("{} can't join room {}", user_name, room_key)));
+        }
+        user_state.player = Some(CodenamesPlayer::new(team, role));
+        // puts the user in the room's player list
+        self.handle_input(addr, None)
+    }
+
+    /// Sends a line of input from the user at the given address
+    pub fn submit_input(&mut self, addr : SocketAddr, line : &str) -> Result<(), GameError> {
+        self.handle_input(addr, Some(line.to_string()))
+    }
+
+    /// Gives up the seats held for dropped players once their grace period has passed,
+    /// rooms restored from a save that are still empty once the reclaim window is over
+    /// are closed, held seats and all
+    pub fn release_expired_seats(&mut self, now : Instant) {
+        if self.reclaim_deadline.is_some_and(|deadline| now >= deadline) {
+            self.reclaim_deadline = None;
+            self.close_unclaimed_rooms(now);
+        }
+        let mut released = Vec::new();
+        for (room_key, room) in self.game_rooms.iter_mut() {
+            if room.game.release_expired_seats(&mut self.user_state, now) {
+                released.push(*room_key);
+            }
+        }
+        // a game everyone dropped out of is over once the last seat goes
+        self.close_abandoned_rooms(&released);
+        if !released.is_empty() {
+            self.mark_rooms_dirty(&released);
+        }
+    }
+
+    /// Writes the rooms to the save file from the config, if there is one
+    pub fn save_rooms(&self) -> io::Result<()> {
+        match &self.config.save_path {
+            Some(path) => save_rooms(path, &self.game_rooms, &self.user_state),
+            None => Ok(())
+        }
+    }
+
+    /// Runs everything that happens with time rather than input, called every pass of the event loop
+    pub fn tick(&mut self, now : Instant) {
+        self.release_expired_seats(now);
+        let mut expired = Vec::new();
+        for (room_key, room) in self.game_rooms.iter_mut() {
+            if room.game.expire_turn_timer(&mut self.user_state, now) {
+                expired.push(*room_key);
+            }
+        }
+        if !expired.is_empty() {
+            self.mark_rooms_dirty(&expired);
+        }
     }
 
     pub fn new() -> GameServerState {
-        GameServerState { user_state: HashMap::new(), game_rooms: HashMap::new() }
+        GameServerState::with_config(ServerConfig::default())
+            .expect("The embedded word list must be valid")
+    }
+
+    /// Creates the server state from the given config, loading anything it
+    /// refers to up front so problems are reported at startup
+    pub fn with_config(config : ServerConfig) -> io::Result<GameServerState> {
+        set_log_level(config.log_level);
+        let word_lists = Arc::new(load_word_lists(config.word_list_path.as_deref(), config.word_lists_dir.as_deref())?);
+        let prompts = Prompts::load(config.prompts_path.as_deref())?;
+        // games from before a restart wait for their players to come back
+        let game_rooms = match &config.save_path {
+            Some(path) => {
+                let game_rooms = load_rooms(path, &word_lists)?;
+                log_event(Level::Info, &format!("Restored {} rooms from {}", game_rooms.len(), path.display()));
+                game_rooms
+            },
+            None => HashMap::new()
+        };
+        let reclaim_deadline = (!game_rooms.is_empty()).then(|| Instant::now() + RECLAIM_WINDOW);
+        Ok(GameServerState {
+            user_state: HashMap::new(),
+            game_rooms,
+            recent_rooms: HashMap::new(),
+            on_game_end: None,
+            config,
+            word_lists,
+            prompts,
+            started: Instant::now(),
+            last_connection: 0,
+            dirty: HashSet::new(),
+            lobby_listing: String::new(),
+            reclaim_deadline
+        })
     }
 }
 
-pub fn get_user_state<'a>(user_state : &'a mut HashMap<SocketAddr,User>, stream : &TcpStream) -> &'a mut User {
-    let peer_addr = stream.peer_addr().unwrap();
+impl Default for GameServerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn get_user_state(user_state : &mut HashMap<SocketAddr,User>, peer_addr : SocketAddr) -> &mut User {
     user_state.entry(peer_addr).or_insert(User { 
-        prev_prompt: "".to_owned(), 
+        prev_prompt: "".to_owned(),
+        outbound: Vec::new(),
+        inbound: Vec::new(),
+        discarding: false,
         game_room_key: None,
+        pending_room_key: None,
         state: ServerState::Joined,
         prev_state: ServerState::Joined,
         socket_addr: peer_addr,
         player: None,
-        user_name: "default".to_string()
+        user_name: "default".to_string(),
+        is_admin: false,
+        window_width: None,
+        encoding_warned: false,
+        lobby_chat_limit: ChatLimit::new(),
+        line_ending: LineEnding::CrLf,
+        connection: 0,
+        stats: SessionStats::default()
     })
 }
 
+/// Resolves a single typed word to one of the given commands, either the command itself,
+/// its alias, or the start of only one of them, Ok(None) if it isn't a command at all
+/// a start shared by several commands is an error listing all of them
+pub(crate) fn resolve_command<'a>(word : &str, commands : &[&'a str]) -> Result<Option<&'a str>, Vec<&'a str>> {
+    if let Some(command) = commands.iter().find(|command| **command == word) {
+        return Ok(Some(command));
+    }
+    let alias = COMMAND_ALIASES.iter()
+        .find(|(alias, _)| *alias == word)
+        .and_then(|(_, target)| commands.iter().find(|command| *command == target));
+    if let Some(command) = alias {
+        return Ok(Some(command));
+    }
+    if word.is_empty() {
+        return Ok(None);
+    }
+    let candidates : Vec<&str> = commands.iter().copied().filter(|command| command.starts_with(word)).collect();
+    match candidates[..] {
+        [] => Ok(None),
+        [command] => Ok(Some(command)),
+        _ => Err(candidates)
+    }
+}
+
+/// Spells out a shortened lobby command, anything else is left as it was typed
+/// so short words and lines that aren't a whole command still go out as chat
+fn expand_lobby_command(line : &str) -> String {
+    match line.split_whitespace().collect::<Vec<&str>>()[..] {
+        [word] if word.len() >= MIN_LOBBY_PREFIX_LEN => match resolve_command(word, &["recent"]) {
+            Ok(Some(command)) => command.to_string(),
+            _ => line.to_string()
+        },
+        // watching needs a room number to go with it
+        [word, room_idx] if word.len() >= MIN_LOBBY_PREFIX_LEN && room_idx.parse::<usize>().is_ok() => {
+            match resolve_command(word, &["spectate"]) {
+                Ok(Some(command)) => format!("{} {}", command, room_idx),
+                _ => line.to_string()
+            }
+        },
+        _ => line.to_string()
+    }
+}
+
+/// Trims an entered username, returns None if it's empty,
+/// too long, or contains control characters
+fn parse_username(line : &str) -> Option<String> {
+    let user_name = line.trim();
+    if user_name.is_empty() ||
+        user_name.chars().count() > MAX_USERNAME_LEN ||
+        user_name.chars().any(char::is_control) {
+        return None;
+    }
+    Some(user_name.to_string())
+}
+
+/// Renames the user, the new name follows the same rules as the one entered at login
+/// and in a room it can't be someone else's, everyone in the room hears about the change
+fn change_username(addr : SocketAddr, new_name : &str,
+                   user_state_map : &mut HashMap<SocketAddr, User>,
+                   game_rooms : &mut HashMap<i32, GameRoom>) {
+    let user_state = get_user_state(user_state_map, addr);
+    let Some(new_name) = parse_username(new_name) else {
+        user_state.queue(&format!("Usage: /nick <name>, up to {} characters\r\n", MAX_USERNAME_LEN));
+        return;
+    };
+    if new_name == user_state.user_name {
+        user_state.queue(&format!("You're already known as {}\r\n", new_name));
+        return;
+    }
+    let room = user_state.game_room_key
+        .and_then(|room_key| game_rooms.get_mut(&room_key))
+        .map(|room| room.game.as_mut());
+    if room.as_deref().is_some_and(|game| username_in_game(game, &new_name, user_state_map)) {
+        let user_state = get_user_state(user_state_map, addr);
+        user_state.queue(&format!("{} is already taken in this room\r\n", new_name));
+        return;
+    }
+    let user_state = get_user_state(user_state_map, addr);
+    let old_name = std::mem::replace(&mut user_state.user_name, new_name.to_string());
+    match room {
+        Some(game) => game.rename(&old_name, &new_name, user_state_map),
+        None => user_state.queue(&format!("You are now known as {}\r\n", new_name))
+    }
+}
+
+/// Checks if anyone in the game already goes by the given username
+fn username_in_game(game : &dyn Game, user_name : &str,
+                    user_state_map : &HashMap<SocketAddr, User>) -> bool {
+    game.players().iter()
+        .filter_map(|addr| user_state_map.get(addr))
+        .any(|u| u.user_name == user_name)
+}
+
+/// The game in the user's room, a user whose room is gone is sent back to the lobby
+fn room_game<'a>(user_state : &mut User, game_rooms : &'a mut HashMap<i32, GameRoom>) -> Option<&'a mut dyn Game> {
+    let Some(room_key) = user_state.game_room_key else {
+        // there should always be an existing room when in one
+        log_event(Level::Error, &format!("{} is in a room without a room key", user_state.socket_addr));
+        user_state.state = ServerState::FatalError;
+        return None;
+    };
+    let Some(room) = game_rooms.get_mut(&room_key) else {
+        // the room can be removed while the user is still in it,
+        // like a finished game being cleaned up, so they go back to the lobby
+        log_event(Level::Warn, &format!("Room {} is gone but {} is still in it",
+                                        room_key, user_state.socket_addr));
+        user_state.game_room_key = None;
+        user_state.player = None;
+        user_state.state = ServerState::LobbySelection;
+        user_state.queue("Your room no longer exists\r\n");
+        return None;
+    };
+    Some(room.game.as_mut())
+}
+
+fn username_entry_logic(user_state : &mut User, game_rooms : &HashMap<i32, GameRoom>,
+                        line : &Option<String>) {
+    // only process if there's input
+    let Some(line) = line else {
+        return;
+    };
+    // the first line typed shows what the client sends, and so what it expects back
+    if line.ends_with("\r\n") {
+        user_state.line_ending = LineEnding::CrLf;
+    } else if line.ends_with('\n') {
+        user_state.line_ending = LineEnding::Lf;
+    }
+    match parse_username(line) {
+        Some(user_name) => {
+            // offer a seat back to someone who dropped out of a game
+            user_state.state = if reserved_room(game_rooms, &user_name).is_some() {
+                ServerState::RejoinOffer
+            } else {
+                ServerState::LobbySelection
+            };
+            user_state.user_name = user_name;
+        },
+        None => {
+            user_state.state = ServerState::InvalidInput;
+        }
+    }
+}
+
 /// Finds an empty slot in the game room hash map and returns that index
 /// this can/should be turned into a more efficient implementation
 /// that uses vectors and indices
@@ -159,30 +1033,798 @@ fn find_empty_slot(game_rooms : &HashMap<i32, GameRoom>) -> i32 {
     last_idx + 1
 }
 
-fn lobby_selection_logic(user_state : &mut User, game_rooms : &mut HashMap<i32, GameRoom>, line : &Option<String>) {
-    // only process if there's input
-    if line.is_none() {
+/// A recently played room is alive if the same room is still open and its game hasn't ended
+fn room_is_alive(game_rooms : &HashMap<i32, GameRoom>, room_key : i32, room_name : &str) -> bool {
+    game_rooms.get(&room_key).is_some_and(|room| room.name == room_name &&
+        !room.game.has_ended())
+}
+
+/// The room holding a seat for this username, if any
+fn reserved_room(game_rooms : &HashMap<i32, GameRoom>, user_name : &str) -> Option<i32> {
+    game_rooms.iter()
+        .find(|(_, room)| room.game.has_reserved_seat(user_name))
+        .map(|(room_key, _)| *room_key)
+}
+
+/// Enters the room the user picked, asking for its password first if it has one
+fn select_room(addr : SocketAddr, room_key : i32,
+               user_state_map : &mut HashMap<SocketAddr, User>,
+               game_rooms : &mut HashMap<i32, GameRoom>,
+               recent_rooms : &mut HashMap<String, VecDeque<(i32, String)>>,
+               max_players : usize) {
+    if game_rooms.get(&room_key).is_some_and(|room| room.password.is_some()) {
+        let user_state = get_user_state(user_state_map, addr);
+        user_state.pending_room_key = Some(room_key);
+        user_state.state = ServerState::RoomPasswordEntry;
+    } else {
+        enter_room(addr, room_key, user_state_map, game_rooms, recent_rooms, max_players);
+    }
+}
+
+/// Puts the user into the given room and remembers it as their most recent room,
+/// unless someone in the room already has their username or the room is full
+/// users with a seat held in the room get their team and role back, unless they're observing
+fn enter_room(addr : SocketAddr, room_key : i32,
+              user_state_map : &mut HashMap<SocketAddr, User>,
+              game_rooms : &mut HashMap<i32, GameRoom>,
+              recent_rooms : &mut HashMap<String, VecDeque<(i32, String)>>,
+              max_players : usize) {
+    let Some(room) = game_rooms.get_mut(&room_key) else {
+        get_user_state(user_state_map, addr).state = ServerState::InvalidInput;
+        return;
+    };
+    let user_name = get_user_state(user_state_map, addr).user_name.to_string();
+    let name_taken = username_in_game(room.game.as_ref(), &user_name, user_state_map);
+    let full = !room.game.has_space_for(&user_name, max_players);
+    let user_state = get_user_state(user_state_map, addr);
+    if name_taken {
+        user_state.state = ServerState::UsernameTaken;
+        return;
+    }
+    if full {
+        user_state.state = ServerState::RoomFull;
         return;
     }
-    match line.clone().unwrap().trim().parse::<i32>() {
+    // a game that's underway can only be watched, the user gets to choose whether to
+    let observing = user_state.player.as_ref().is_some_and(CodenamesPlayer::is_observer);
+    if !observing && room.game.joins_as_observer(&user_name) {
+        user_state.pending_room_key = Some(room_key);
+        user_state.state = ServerState::JoinInProgress;
+        return;
+    }
+    let recent = recent_rooms.entry(user_name).or_default();
+    recent.retain(|(key, name)| *key != room_key || *name != room.name);
+    recent.push_front((room_key, room.name.to_string()));
+    recent.truncate(MAX_RECENT_ROOMS);
+    room.game.admit(addr, user_state_map);
+    let user_state = get_user_state(user_state_map, addr);
+    user_state.game_room_key = Some(room_key);
+    user_state.state = ServerState::InRoom;
+}
+
+/// Whether lobby input is chat rather than a room number or one of the lobby's commands
+fn is_lobby_chat(line : &str) -> bool {
+    let line = line.trim();
+    let spectate = line.strip_prefix("spectate ").is_some_and(|room_idx| room_idx.trim().parse::<usize>().is_ok());
+    line != "recent" && !spectate
+        && line.split_whitespace().next().is_some_and(|word| word.parse::<i32>().is_err())
+}
+
+/// Sends the line to everyone else in the lobby, with the sender's name
+fn lobby_chat(addr : SocketAddr, chat_line : &str, user_state_map : &mut HashMap<SocketAddr, User>) {
+    let sender = get_user_state(user_state_map, addr);
+    if !sender.lobby_chat_limit.take(Instant::now()) {
+        if !sender.lobby_chat_limit.warned {
+            sender.lobby_chat_limit.warned = true;
+            sender.queue("You're sending messages too fast\r\n");
+        }
+        return;
+    }
+    let chat_line = format!("[lobby] {}: {}\r\n", sender.user_name, chat_line.trim());
+    for user in user_state_map.values_mut() {
+        if user.state == ServerState::LobbySelection && user.socket_addr != addr {
+            user.queue(&chat_line);
+        }
+    }
+}
+
+/// Handles input in the lobby, returns the room the user picked to enter
+fn lobby_selection_logic(user_state : &mut User, game_rooms : &mut HashMap<i32, GameRoom>,
+                         word_lists : &Arc<WordLists>, line : &Option<String>) -> Option<i32> {
+    // only process if there's input
+    let line = line.as_ref()?;
+    if line.trim() == "recent" {
+        user_state.state = ServerState::RecentRooms;
+        return None;
+    }
+    // watching a room means going in as an observer instead of a player
+    if let Some(room_idx) = line.trim().strip_prefix("spectate ") {
+        match room_idx.trim().parse::<i32>() {
+            Ok(room_idx) if room_idx > 0 => {
+                user_state.player = Some(CodenamesPlayer::new(CodenamesTeam::Floating, CodenamesRole::Observer));
+                return Some(room_idx);
+            },
+            _ => {
+                user_state.state = ServerState::InvalidInput;
+                return None;
+            }
+        }
+    }
+    user_state.player = None;
+    // a new lobby can be given a variant, or made a chat room, after the 0
+    let (room_idx, kind, variant) = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+        [room_idx, "chat"] if room_idx == "0" => (room_idx, GameKind::Chat, RoomVariant::Standard),
+        [room_idx, variant] if room_idx == "0" => match RoomVariant::parse(variant) {
+            Some(variant) => (room_idx, GameKind::Codenames, variant),
+            None => {
+                user_state.state = ServerState::InvalidInput;
+                return None;
+            }
+        },
+        _ => (line.trim(), GameKind::Codenames, RoomVariant::Standard)
+    };
+    match room_idx.parse::<i32>() {
         Ok(mut room_idx) => {
             // if this lobby index is valid (within range, or 0 to create a new one)
             // then go into that lobby
             if room_idx == 0 { // create new lobby
-                let room = GameRoom { name: user_state.user_name.to_string() + "'s Room", impl_room: None };
+                let room = GameRoom::new(user_state.user_name.to_string() + "'s Room", kind, variant, word_lists);
                 room_idx = find_empty_slot(game_rooms);
                 game_rooms.insert(room_idx, room);
-            } 
-            let room_option = game_rooms.get(&room_idx);
-            if room_option.is_none() {
-                user_state.state = ServerState::InvalidInput;
-                return;
+                // the creator picks a password before going in
+                user_state.pending_room_key = Some(room_idx);
+                user_state.state = ServerState::RoomPasswordSetup;
+                return None;
             }
-            user_state.game_room_key = Some(room_idx);
-            user_state.state = ServerState::InRoom;
+            Some(room_idx)
         },
         Err(_) => {
             user_state.state = ServerState::InvalidInput;
+            None
         }
-    };
-}
\ No newline at end of file
+    }
+}
+
+/// Handles input when listing recent rooms, returns the room the user picked to rejoin
+fn recent_rooms_logic(user_state : &mut User, game_rooms : &HashMap<i32, GameRoom>,
+                      recent_rooms : &HashMap<String, VecDeque<(i32, String)>>,
+                      line : &Option<String>) -> Option<i32> {
+    // only process if there's input
+    let line = line.as_ref()?;
+    match line.trim().parse::<i32>() {
+        Ok(room_idx) => {
+            // only rooms from this user's recent list that are still alive can be rejoined
+            let room_name = recent_rooms.get(&user_state.user_name)
+                .and_then(|recent| recent.iter().find(|(key, _)| *key == room_idx))
+                .filter(|(_, name)| room_is_alive(game_rooms, room_idx, name));
+            if room_name.is_none() {
+                user_state.state = ServerState::InvalidInput;
+                return None;
+            }
+            Some(room_idx)
+        },
+        Err(_) => {
+            user_state.state = ServerState::LobbySelection;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashSet;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicU16, Ordering};
+    use std::time::Instant;
+    use crate::codenames::RECONNECT_GRACE;
+    use crate::game::{GameServerState, ServerState, resolve_command};
+
+    /// Hands out a distinct client address, no socket is opened
+    pub(crate) fn new_addr() -> SocketAddr {
+        static NEXT_PORT : AtomicU16 = AtomicU16::new(1);
+        SocketAddr::from(([127, 0, 0, 1], NEXT_PORT.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    pub(crate) fn send(game_server_state : &mut GameServerState, addr : SocketAddr, line : &str) {
+        let _ = game_server_state.handle_input(addr, Some(line.to_string()));
+    }
+
+    pub(crate) fn login(game_server_state : &mut GameServerState, addr : SocketAddr, user_name : &str) {
+        let _ = game_server_state.handle_input(addr, None);
+        send(game_server_state, addr, user_name);
+    }
+
+    /// Creates a new room without a password
+    pub(crate) fn create_room(game_server_state : &mut GameServerState, addr : SocketAddr) {
+        send(game_server_state, addr, "0");
+        send(game_server_state, addr, "");
+    }
+
+    fn user_state(game_server_state : &GameServerState, addr : SocketAddr) -> ServerState {
+        game_server_state.user_state[&addr].state
+    }
+
+    fn try_username(line : &str) -> (ServerState, String) {
+        let mut game_server_state = GameServerState::new();
+        let addr = new_addr();
+        login(&mut game_server_state, addr, line);
+        let user = &game_server_state.user_state[&addr];
+        (user.state, user.user_name.to_string())
+    }
+
+    #[test]
+    fn username_trimmed() {
+        assert_eq!(try_username("  alice \r\n"), (ServerState::LobbySelection, "alice".to_string()));
+        let longest = "a".repeat(25);
+        assert_eq!(try_username(&(longest.to_string() + "\r\n")), (ServerState::LobbySelection, longest));
+    }
+
+    #[test]
+    fn username_empty_rejected() {
+        assert_eq!(try_username("\r\n").0, ServerState::InvalidInput);
+        assert_eq!(try_username(" \t \r\n").0, ServerState::InvalidInput);
+    }
+
+    #[test]
+    fn username_too_long_rejected() {
+        assert_eq!(try_username(&"a".repeat(26)).0, ServerState::InvalidInput);
+    }
+
+    #[test]
+    fn username_control_characters_rejected() {
+        assert_eq!(try_username("ali\x1bce\r\n").0, ServerState::InvalidInput);
+        assert_eq!(try_username("ali\x08ce").0, ServerState::InvalidInput);
+    }
+
+    #[test]
+    fn username_taken_in_room() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let other = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.handle_input(alice, None);
+
+        login(&mut game_server_state, other, "alice");
+        send(&mut game_server_state, other, "1");
+        assert_eq!(user_state(&game_server_state, other), ServerState::UsernameTaken);
+        assert_eq!(game_server_state.prompt_for(other).unwrap(),
+                   "alice is already taken in that room, please pick another username\r\n");
+        let _ = game_server_state.handle_input(other, None);
+        assert_eq!(user_state(&game_server_state, other), ServerState::UsernameEntry);
+        send(&mut game_server_state, other, "alice2");
+        send(&mut game_server_state, other, "1");
+        assert_eq!(user_state(&game_server_state, other), ServerState::InRoom);
+    }
+
+    #[test]
+    fn recent_room_rejoin() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        login(&mut game_server_state, bob, "bob");
+        send(&mut game_server_state, bob, "1");
+        let _ = game_server_state.handle_input(bob, None);
+        game_server_state.remove_client(alice);
+
+        // alice comes back on a new connection, bob is still keeping the room alive
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "recent");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::RecentRooms);
+        let prompt = game_server_state.prompt_for(alice).unwrap();
+        assert!(prompt.starts_with("Enter a room number") && prompt.contains("1:    alice's Room"));
+        assert!(!prompt.contains("unavailable"));
+
+        send(&mut game_server_state, alice, "1");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InRoom);
+        assert_eq!(game_server_state.user_state[&alice].game_room_key, Some(1));
+    }
+
+    /// Seats alice/bob on Red and carol/dave on Blue, spymasters first,
+    /// then has alice apply the settings commands and start the game in room 1,
+    /// which alice creates unless the test already opened it
+    /// returns each player's address
+    pub(crate) fn start_game(game_server_state : &mut GameServerState, settings : &[&str]) -> Vec<SocketAddr> {
+        let setup = [("alice", "red", "spymaster"), ("bob", "red", "teammate"),
+                     ("carol", "blue", "spymaster"), ("dave", "blue", "teammate")];
+        let mut players = Vec::new();
+        for (i, (user_name, team, role)) in setup.iter().enumerate() {
+            let addr = new_addr();
+            login(game_server_state, addr, user_name);
+            if i == 0 && !game_server_state.game_rooms.contains_key(&1) {
+                create_room(game_server_state, addr);
+            } else {
+                send(game_server_state, addr, "1");
+            }
+            send(game_server_state, addr, team);
+            send(game_server_state, addr, role);
+            players.push(addr);
+        }
+        for setting in settings {
+            send(game_server_state, players[0], setting);
+        }
+        send(game_server_state, players[0], "start");
+        players
+    }
+
+    #[test]
+    fn joining_mid_game_spectates() {
+        use crate::{CodenamesTeam, CodenamesRole};
+        let mut game_server_state = GameServerState::new();
+        start_game(&mut game_server_state, &[]);
+        let erin = new_addr();
+        login(&mut game_server_state, erin, "erin");
+        assert!(game_server_state.prompt_for(erin).unwrap().contains("1:    alice's Room - 4/20 players, In Progress"));
+        send(&mut game_server_state, erin, "1");
+        assert_eq!(user_state(&game_server_state, erin), ServerState::JoinInProgress);
+        assert_eq!(game_server_state.prompt_for(erin).unwrap(), "Game in progress, join as spectator? (y/n)\r\n");
+        // turning it down goes back to the lobby
+        send(&mut game_server_state, erin, "n");
+        assert_eq!(user_state(&game_server_state, erin), ServerState::LobbySelection);
+
+        send(&mut game_server_state, erin, "1");
+        send(&mut game_server_state, erin, "y");
+        assert_eq!(user_state(&game_server_state, erin), ServerState::InRoom);
+        let prompt = game_server_state.prompt_for(erin).unwrap();
+        assert!(prompt.contains("You're watching this game"));
+        // they see what a teammate sees, none of the card types
+        let room = game_server_state.game_rooms[&1].codenames().unwrap();
+        assert!(prompt.contains(&room.get_board(CodenamesTeam::Floating, CodenamesRole::Teammate)));
+        assert!(!prompt.contains(&room.get_board(CodenamesTeam::Floating, CodenamesRole::Spectator)));
+        send(&mut game_server_state, erin, "!anything");
+        assert!(game_server_state.prompt_for(erin).unwrap().contains("Observers cannot guess"));
+    }
+
+    #[test]
+    fn reconnect_resumes_seat() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        game_server_state.remove_client(players[2]);
+
+        let carol = new_addr();
+        login(&mut game_server_state, carol, "carol");
+        assert_eq!(user_state(&game_server_state, carol), ServerState::RejoinOffer);
+        assert_eq!(game_server_state.prompt_for(carol).unwrap(),
+                   "You dropped out of a game in alice's Room, rejoin it? (y/n)\r\n");
+        send(&mut game_server_state, carol, "y");
+        let _ = game_server_state.handle_input(carol, None);
+        assert_eq!(user_state(&game_server_state, carol), ServerState::InRoom);
+        assert!(!game_server_state.game_rooms[&1].codenames().unwrap().has_reserved_seat("carol"));
+        // carol is Blue's spymaster again once Red passes
+        send(&mut game_server_state, players[0], "fruit,unlimited");
+        send(&mut game_server_state, players[1], "!!");
+        let prompt = game_server_state.prompt_for(carol).unwrap();
+        assert!(prompt.contains("Blue Team's Turn") && prompt.contains("Type in your clue"));
+    }
+
+    #[test]
+    fn reserved_seat_expires() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        game_server_state.remove_client(players[2]);
+        game_server_state.release_expired_seats(Instant::now());
+        assert!(game_server_state.game_rooms[&1].codenames().unwrap().has_reserved_seat("carol"));
+        game_server_state.release_expired_seats(Instant::now() + RECONNECT_GRACE);
+
+        let carol = new_addr();
+        login(&mut game_server_state, carol, "carol");
+        assert_eq!(user_state(&game_server_state, carol), ServerState::LobbySelection);
+    }
+
+    #[test]
+    fn full_room_rejected() {
+        let mut game_server_state = GameServerState::new();
+        game_server_state.config.max_room_players = 2;
+        let mut players = Vec::new();
+        for user_name in ["alice", "bob", "carol"] {
+            let addr = new_addr();
+            login(&mut game_server_state, addr, user_name);
+            if user_name == "alice" {
+                create_room(&mut game_server_state, addr);
+            } else {
+                send(&mut game_server_state, addr, "1");
+            }
+            let _ = game_server_state.handle_input(addr, None);
+            players.push(addr);
+        }
+        assert_eq!(user_state(&game_server_state, players[1]), ServerState::InRoom);
+        assert_eq!(user_state(&game_server_state, players[2]), ServerState::LobbySelection);
+        assert!(!game_server_state.game_rooms[&1].codenames().unwrap().has_space_for("carol", 2));
+
+        // once someone leaves there's space again
+        game_server_state.remove_client(players[1]);
+        send(&mut game_server_state, players[2], "1");
+        assert_eq!(user_state(&game_server_state, players[2]), ServerState::InRoom);
+    }
+
+    #[test]
+    fn rooms_close_when_the_last_player_disconnects() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.handle_input(alice, None);
+        login(&mut game_server_state, bob, "bob");
+        send(&mut game_server_state, bob, "1");
+        let _ = game_server_state.handle_input(bob, None);
+
+        game_server_state.remove_client(alice);
+        assert!(game_server_state.game_rooms.contains_key(&1));
+        game_server_state.remove_client(bob);
+        assert!(game_server_state.game_rooms.is_empty());
+        assert!(!game_server_state.get_lobby_listing().contains("alice's Room"));
+
+        // a game in progress waits for its players to come back, until their seats go
+        for addr in start_game(&mut game_server_state, &[]) {
+            game_server_state.remove_client(addr);
+        }
+        assert!(game_server_state.game_rooms.contains_key(&1));
+        game_server_state.release_expired_seats(Instant::now() + RECONNECT_GRACE);
+        assert!(game_server_state.game_rooms.is_empty());
+    }
+
+    #[test]
+    fn rooms_close_when_their_creator_leaves_during_setup() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "0");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::RoomPasswordSetup);
+        game_server_state.remove_client(alice);
+        assert!(game_server_state.game_rooms.is_empty());
+        login(&mut game_server_state, bob, "bob");
+        assert!(!game_server_state.prompt_for(bob).unwrap().contains("alice's Room"));
+
+        // someone else on their way in keeps it open
+        let carol = new_addr();
+        login(&mut game_server_state, carol, "carol");
+        send(&mut game_server_state, carol, "0");
+        send(&mut game_server_state, bob, "1");
+        game_server_state.remove_client(carol);
+        assert_eq!(game_server_state.game_rooms.len(), 1);
+    }
+
+    #[test]
+    fn reused_addresses_start_from_scratch() {
+        let mut game_server_state = GameServerState::new();
+        let players = start_game(&mut game_server_state, &[]);
+        let first = game_server_state.connect(new_addr()).connection;
+
+        // the address of a player whose disconnect never got handled
+        let user_state = game_server_state.connect(players[0]);
+        assert!(user_state.connection > first);
+        assert_eq!((user_state.state, user_state.user_name.as_str()), (ServerState::Joined, "default"));
+        assert!(user_state.game_room_key.is_none() && user_state.player.is_none());
+        let room = game_server_state.game_rooms[&1].codenames().unwrap();
+        assert!(room.player_count() == 3 && room.has_reserved_seat("alice"));
+    }
+
+    #[test]
+    fn lobby_lists_player_counts_and_game_state() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        // the seat is taken on the next poll
+        let _ = game_server_state.handle_input(alice, None);
+        // a room nobody has gone into yet
+        game_server_state.create_room("Empty Room");
+        let listing = game_server_state.get_lobby_listing();
+        assert_eq!(listing.lines().collect::<Vec<&str>>(), [
+            "0: New Lobby ('0 double' for the double agent variant, '0 chat' for a chat room)",
+            "1:    alice's Room - 1/20 players, Waiting",
+            "2:      Empty Room - 0/20 players, Waiting"
+        ]);
+        for (name, team, role) in [("bob", "red", "teammate"), ("carol", "blue", "spymaster"), ("dave", "blue", "teammate")] {
+            let addr = new_addr();
+            login(&mut game_server_state, addr, name);
+            send(&mut game_server_state, addr, "1");
+            send(&mut game_server_state, addr, team);
+            send(&mut game_server_state, addr, role);
+        }
+        send(&mut game_server_state, alice, "red");
+        send(&mut game_server_state, alice, "spymaster");
+        send(&mut game_server_state, alice, "start");
+        assert!(game_server_state.get_lobby_listing().contains("1:    alice's Room - 4/20 players, In Progress\r\n"));
+    }
+
+    #[test]
+    fn lobby_chat_reaches_the_lobby() {
+        let mut game_server_state = GameServerState::new();
+        let (alice, bob, carol) = (new_addr(), new_addr(), new_addr());
+        login(&mut game_server_state, alice, "alice");
+        login(&mut game_server_state, bob, "bob");
+        login(&mut game_server_state, carol, "carol");
+        create_room(&mut game_server_state, carol);
+        send(&mut game_server_state, alice, "anyone up for a game?");
+        assert_eq!(game_server_state.user_state[&bob].outbound, b"[lobby] alice: anyone up for a game?\r\n");
+        // not to the sender or anyone already in a room
+        assert!(game_server_state.user_state[&alice].outbound.is_empty());
+        assert!(game_server_state.user_state[&carol].outbound.is_empty());
+        assert_eq!(user_state(&game_server_state, alice), ServerState::LobbySelection);
+        // room numbers and commands still work
+        send(&mut game_server_state, bob, "1");
+        assert_eq!(user_state(&game_server_state, bob), ServerState::InRoom);
+        send(&mut game_server_state, alice, "0 nonsense");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InvalidInput);
+    }
+
+    #[test]
+    fn password_protected_room() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "0");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::RoomPasswordSetup);
+        send(&mut game_server_state, alice, "hunter2");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InRoom);
+        let password = game_server_state.game_rooms[&1].password.as_ref().unwrap();
+        assert!(password.matches("hunter2") && !password.matches("hunter3"));
+
+        login(&mut game_server_state, bob, "bob");
+        assert!(game_server_state.prompt_for(bob).unwrap().contains("alice's Room (password)"));
+        send(&mut game_server_state, bob, "1");
+        assert_eq!(user_state(&game_server_state, bob), ServerState::RoomPasswordEntry);
+        send(&mut game_server_state, bob, "wrong");
+        assert_eq!(user_state(&game_server_state, bob), ServerState::InvalidInput);
+        let _ = game_server_state.handle_input(bob, None);
+        assert_eq!(user_state(&game_server_state, bob), ServerState::RoomPasswordEntry);
+        send(&mut game_server_state, bob, "hunter2");
+        assert_eq!(user_state(&game_server_state, bob), ServerState::InRoom);
+    }
+
+    #[test]
+    fn double_agent_room_from_lobby() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "0 triple");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InvalidInput);
+        let _ = game_server_state.handle_input(alice, None);
+        send(&mut game_server_state, alice, "0 double");
+        send(&mut game_server_state, alice, "");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InRoom);
+        assert_eq!(game_server_state.game_rooms[&1].game.listing_tag().as_deref(), Some("double agent"));
+
+        login(&mut game_server_state, bob, "bob");
+        assert!(game_server_state.prompt_for(bob).unwrap().contains("alice's Room (double agent)"));
+    }
+
+    #[test]
+    fn connection_and_room_counts() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        login(&mut game_server_state, bob, "bob");
+        assert_eq!((game_server_state.active_connections(), game_server_state.active_rooms()), (2, 0));
+        // a room only counts once someone is in it
+        send(&mut game_server_state, alice, "0");
+        assert_eq!(game_server_state.active_rooms(), 0);
+        send(&mut game_server_state, alice, "");
+        let _ = game_server_state.prompt_for(alice);
+        assert_eq!(game_server_state.active_rooms(), 1);
+        game_server_state.remove_client(alice);
+        assert_eq!((game_server_state.active_connections(), game_server_state.active_rooms()), (1, 0));
+    }
+
+    #[test]
+    fn bots_play_through_the_api() {
+        use crate::{CodenamesTeam, CodenamesRole};
+        let mut game_server_state = GameServerState::new();
+        let room_key = game_server_state.create_room("Bot Room");
+        let bots : Vec<std::net::SocketAddr> = (1..=4).map(|i| format!("10.0.0.{}:5000", i).parse().unwrap()).collect();
+        for (addr, (user_name, team, role)) in bots.iter().zip([
+                ("red-spy", CodenamesTeam::Red, CodenamesRole::Spymaster),
+                ("red-guesser", CodenamesTeam::Red, CodenamesRole::Teammate),
+                ("blue-spy", CodenamesTeam::Blue, CodenamesRole::Spymaster),
+                ("blue-guesser", CodenamesTeam::Blue, CodenamesRole::Teammate)]) {
+            assert!(game_server_state.join_room(*addr, user_name, room_key, team, role).is_ok());
+        }
+        // names are still unique within a room
+        let copycat = "10.0.0.5:5000".parse().unwrap();
+        assert!(game_server_state.join_room(copycat, "red-spy", room_key,
+                                            CodenamesTeam::Red, CodenamesRole::Spymaster).is_err());
+        assert!(!game_server_state.user_state.contains_key(&copycat));
+        assert!(game_server_state.submit_input(bots[0], "start").is_ok());
+        assert!(game_server_state.prompt_for(bots[0]).unwrap().contains("Type in your clue"));
+        assert!(game_server_state.submit_input(bots[0], "robot,1").is_ok());
+        assert!(game_server_state.prompt_for(bots[1]).unwrap().contains("Spymaster Clue: robot, 1"));
+
+        game_server_state.remove_client(bots[3]);
+        assert!(!game_server_state.user_state.contains_key(&bots[3]));
+    }
+
+    #[test]
+    fn recent_room_unavailable() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        game_server_state.remove_client(alice);
+        game_server_state.game_rooms.clear();
+
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "recent");
+        let prompt = game_server_state.prompt_for(alice).unwrap();
+        assert!(prompt.contains("alice's Room (unavailable)"));
+        send(&mut game_server_state, alice, "1");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InvalidInput);
+    }
+
+    #[test]
+    fn invalid_input_returns_to_where_it_came_from() {
+        let mut game_server_state = GameServerState::new();
+        game_server_state.config.admin_password = Some("letmein".to_string());
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InvalidInput);
+        let _ = game_server_state.handle_input(alice, None);
+        assert_eq!(user_state(&game_server_state, alice), ServerState::UsernameEntry);
+
+        send(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "5");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InvalidInput);
+        let _ = game_server_state.handle_input(alice, None);
+        assert_eq!(user_state(&game_server_state, alice), ServerState::LobbySelection);
+
+        send(&mut game_server_state, alice, "recent");
+        send(&mut game_server_state, alice, "7");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InvalidInput);
+        let _ = game_server_state.handle_input(alice, None);
+        assert_eq!(user_state(&game_server_state, alice), ServerState::RecentRooms);
+
+        // more bad input before the poll doesn't lose the lobby to go back to
+        send(&mut game_server_state, alice, "");
+        send(&mut game_server_state, alice, "5");
+        send(&mut game_server_state, alice, "5");
+        let _ = game_server_state.handle_input(alice, None);
+        assert_eq!(user_state(&game_server_state, alice), ServerState::LobbySelection);
+
+        // logging in as admin from the invalid input prompt goes back past it
+        send(&mut game_server_state, alice, "5");
+        send(&mut game_server_state, alice, "/admin");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::AdminLogin);
+        send(&mut game_server_state, alice, "letmein");
+        assert!(game_server_state.user_state[&alice].is_admin);
+        let _ = game_server_state.handle_input(alice, None);
+        assert_eq!(user_state(&game_server_state, alice), ServerState::LobbySelection);
+    }
+
+    #[test]
+    fn only_users_something_changed_for_are_dirty() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        let bob = new_addr();
+        let carol = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.prompt_for(alice);
+        login(&mut game_server_state, bob, "bob");
+        login(&mut game_server_state, carol, "carol");
+        send(&mut game_server_state, carol, "1");
+        let _ = game_server_state.prompt_for(carol);
+        game_server_state.take_dirty();
+
+        // a poll that changes nothing leaves everyone be
+        let _ = game_server_state.handle_input(alice, None);
+        assert!(!game_server_state.is_dirty());
+        // chat in a room reaches the room, not the lobby
+        send(&mut game_server_state, alice, "hi carol");
+        assert_eq!(game_server_state.take_dirty(), HashSet::from([alice, carol]));
+        send(&mut game_server_state, carol, "red");
+        assert!(game_server_state.take_dirty().contains(&carol));
+        // the lobby hears about a room's player count changing
+        game_server_state.remove_client(carol);
+        assert_eq!(game_server_state.take_dirty(), HashSet::from([alice, bob]));
+    }
+
+    #[test]
+    fn missing_room_returns_to_lobby() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.prompt_for(alice);
+        game_server_state.game_rooms.clear();
+
+        assert!(game_server_state.handle_input(alice, Some("hello".to_string())).is_ok());
+        let alice_state = &game_server_state.user_state[&alice];
+        assert_eq!((alice_state.state, alice_state.game_room_key), (ServerState::LobbySelection, None));
+        assert_eq!(alice_state.outbound, b"Your room no longer exists\r\n");
+    }
+
+    #[test]
+    fn nick_changes_the_username() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alcie");
+        send(&mut game_server_state, alice, "/nick alice");
+        assert_eq!(game_server_state.user_state[&alice].user_name, "alice");
+        assert_eq!(game_server_state.user_state[&alice].outbound, b"You are now known as alice\r\n");
+        send(&mut game_server_state, alice, "/nick");
+        assert_eq!(game_server_state.user_state[&alice].user_name, "alice");
+
+        create_room(&mut game_server_state, alice);
+        let _ = game_server_state.prompt_for(alice);
+        let bob = new_addr();
+        login(&mut game_server_state, bob, "bob");
+        send(&mut game_server_state, bob, "1");
+        let _ = game_server_state.handle_input(bob, None);
+        let _ = game_server_state.prompt_for(bob);
+        send(&mut game_server_state, bob, "/nick alice");
+        assert_eq!(game_server_state.user_state[&bob].user_name, "bob");
+        assert!(game_server_state.user_state[&bob].outbound.ends_with(b"alice is already taken in this room\r\n"));
+        send(&mut game_server_state, bob, "/nick robert");
+        assert_eq!(game_server_state.user_state[&bob].user_name, "robert");
+        let prompt = game_server_state.prompt_for(alice).unwrap();
+        // the roles table is shown again with the new name
+        assert!(prompt.contains("* bob is now known as robert") && prompt.contains(&format!("{:>25}", "robert")));
+    }
+
+    #[test]
+    fn commands_resolve_from_aliases_and_prefixes() {
+        let commands = ["teammate", "teams", "spymaster", "spectator", "show", "red"];
+        assert_eq!(resolve_command("teams", &commands), Ok(Some("teams")));
+        assert_eq!(resolve_command("sm", &commands), Ok(Some("spymaster")));
+        assert_eq!(resolve_command("s", &commands), Ok(Some("show")));
+        assert_eq!(resolve_command("spy", &commands), Ok(Some("spymaster")));
+        assert_eq!(resolve_command("teamm", &commands), Ok(Some("teammate")));
+        assert_eq!(resolve_command("te", &commands), Err(vec!["teammate", "teams"]));
+        assert_eq!(resolve_command("sp", &commands), Err(vec!["spymaster", "spectator"]));
+        // an alias only counts for a command that's on offer
+        assert_eq!(resolve_command("b", &commands), Ok(None));
+        assert_eq!(resolve_command("hello", &commands), Ok(None));
+    }
+
+    #[test]
+    fn lobby_commands_can_be_shortened() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        send(&mut game_server_state, alice, "rec");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::RecentRooms);
+        let _ = game_server_state.handle_input(alice, Some("back".to_string()));
+
+        game_server_state.create_room("Big Game");
+        send(&mut game_server_state, alice, "spec 1");
+        assert_eq!(user_state(&game_server_state, alice), ServerState::InRoom);
+        assert!(game_server_state.user_state[&alice].player.as_ref().unwrap().is_observer());
+    }
+
+    #[test]
+    fn short_lobby_lines_stay_chat() {
+        let mut game_server_state = GameServerState::new();
+        let (alice, bob) = (new_addr(), new_addr());
+        login(&mut game_server_state, alice, "alice");
+        login(&mut game_server_state, bob, "bob");
+        game_server_state.create_room("Big Game");
+        // too short to be a command, or spectate without a room number to watch
+        for line in ["r", "re", "s up", "spec this", "spectate this"] {
+            send(&mut game_server_state, alice, line);
+            assert_eq!(user_state(&game_server_state, alice), ServerState::LobbySelection);
+            let received = std::mem::take(&mut game_server_state.user_state.get_mut(&bob).unwrap().outbound);
+            assert_eq!(received, format!("[lobby] alice: {}\r\n", line).into_bytes());
+        }
+    }
+
+    #[test]
+    fn welcome_banner_from_config() {
+        let mut game_server_state = GameServerState::new();
+        let alice = new_addr();
+        assert_eq!(game_server_state.prompt_for(alice).unwrap(), "Connected to Telnet Codenames\r\n");
+
+        game_server_state.config.motd = Some("Welcome to the club\n{players} online in {rooms} rooms, up {uptime}\n".to_string());
+        let bob = new_addr();
+        assert_eq!(game_server_state.prompt_for(bob).unwrap(),
+                   "Welcome to the club\r\n2 online in 0 rooms, up 0h 0m\r\n");
+    }
+}
@@ -1,71 +1,165 @@
-use std::{net::{TcpListener, TcpStream, Shutdown}, io::{Read, ErrorKind, Write}};
-use std::thread::sleep;
-use std::time::Duration;
+use std::{net::{TcpListener, Shutdown, SocketAddr, Ipv4Addr, Ipv6Addr}, io::{Read, ErrorKind, Write}};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use game::GameServerState;
+pub use game::{GameServerState, GameError};
+pub use codenames::{GameResult, CodenamesTeam, CodenamesRole, RoomVariant};
+pub use config::{ServerConfig, IpMode};
+pub use event_log::Level;
+use event_log::log_event;
+use mio::{Events, Interest, Poll, Token};
+use mio::net::TcpStream;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+mod admin;
+mod chat_room;
 mod codenames;
+mod config;
+mod event_log;
 mod game;
+mod persist;
+mod prompts;
+mod report;
+mod telnet;
 
-fn handle_client(stream : &mut TcpStream, game_server_state : &mut GameServerState) -> bool {
-    let _ = stream.set_nonblocking(true)
-        .expect("Non blocking sockets must be supported");
+// longest line a client can send before it's discarded
+const MAX_LINE_LEN : usize = 1024;
+// most bytes read from one client per call to read_until_block
+const READ_BUDGET : usize = 256;
+// most output left waiting for a client that isn't reading before it's dropped
+const MAX_PENDING_OUTPUT : usize = 64 * 1024;
+// how often the connection and room counts are logged
+const STATS_INTERVAL : Duration = Duration::from_secs(60);
+// how often rooms are written to the save file, if there is one
+const SAVE_INTERVAL : Duration = Duration::from_secs(30);
+// readiness token of the listener, clients get the ones after it in the order they connect
+const LISTENER : Token = Token(0);
+
+/// Runs one poll of a client, addr is the address it was accepted from
+/// which the game keys the client on, returns None once the connection should be dropped,
+/// otherwise whether the client sent anything this poll
+fn handle_client(stream : &mut TcpStream, addr : SocketAddr, game_server_state : &mut GameServerState) -> Option<bool> {
+    let mut active = false;
     loop {
         // Get the client prompt for the current stream's state
-        let prompt = game_server_state.get_client_prompt(stream);
+        let prompt = game_server_state.prompt_for(addr);
         match prompt {
             Some(prompt) => {
-                let user_state = game_server_state.user_state.get(&stream.peer_addr().unwrap());
+                let user_state = game_server_state.user_state.get(&addr);
                 if user_state.is_none() || user_state.is_some_and(|x| x.prev_prompt != prompt) {
-                    match write(stream, &prompt) {
-                        Ok(_) => {
-                            game::get_user_state(&mut game_server_state.user_state, stream).prev_prompt = prompt;
-                        },
-                        Err(_) => {
-                            println!("Unrecoverable write error encountered, dropping connection to {}", stream.peer_addr().unwrap());
-                            return false;
-                        }
-                    }
+                    log_write(stream, &prompt);
+                    let user_state = game::get_user_state(&mut game_server_state.user_state, addr);
+                    user_state.queue(&prompt);
+                    user_state.prev_prompt = prompt;
                 }
             },
             None => {
                 // Don't do anything if there's no prompt for the current state of this user
             }
         }
+        // send as much of the queued output as the socket takes, the rest goes out on later passes
+        let user_state = game::get_user_state(&mut game_server_state.user_state, addr);
+        if flush_pending(stream, &mut user_state.outbound).is_err() {
+            log_event(Level::Error, &format!("Unrecoverable write error encountered, dropping connection to {}",
+                                             addr));
+            // nothing more gets through, so there's no goodbye, but the room still has to let them go
+            game_server_state.remove_client(addr);
+            return None;
+        }
+        // a slow link gets its output later, a client that stopped reading altogether goes
+        if user_state.outbound.len() > MAX_PENDING_OUTPUT {
+            log_event(Level::Warn, &format!("{} has {} bytes of output waiting, dropping connection",
+                                            addr, user_state.outbound.len()));
+            game_server_state.client_disconnect(stream, addr);
+            return None;
+        }
         // based on the returned value, get the response and run the logic for that
-        match read_until_block(stream, 10) {
-            Ok(line) => {
-                match game_server_state.client_logic(stream, Some(line)) {
-                    Ok(_) => {},
-                    Err(_) => game_server_state.client_disconnect(stream)
+        let (buf_size, lossy) = (game_server_state.config.read_buf_size, game_server_state.config.lossy_utf8);
+        let user_state = game::get_user_state(&mut game_server_state.user_state, addr);
+        let waiting = user_state.inbound.len();
+        match read_until_block(stream, buf_size, MAX_LINE_LEN, lossy, &mut user_state.inbound,
+                               &mut user_state.discarding, &mut user_state.window_width) {
+            Ok(data) => {
+                active |= !data.is_empty();
+                if data.contains(char::REPLACEMENT_CHARACTER) {
+                    warn_encoding(user_state, "Your client isn't sending UTF-8, characters that couldn't be \
+                        read were replaced\r\n");
+                }
+                // several lines can arrive in one read, run them one at a time
+                // and keep an unfinished last line until the rest of it arrives,
+                // ahead of any telnet command that's still arriving
+                let (lines, rest) = data.split_at(data.rfind('\n').map_or(0, |i| i + 1));
+                let mut inbound = rest.as_bytes().to_vec();
+                inbound.append(&mut user_state.inbound);
+                user_state.inbound = inbound;
+                for line in lines.split_inclusive('\n') {
+                    if let Err(e) = game_server_state.handle_input(addr, Some(line.to_string())) {
+                        game_error_disconnect(stream, addr, game_server_state, e);
+                        return None;
+                    }
                 }
             },
             Err(e) if e.error_type == ReadLineErrorType::StringParsing => {
-                println!("String parsing error encountered");
+                log_event(Level::Warn, &format!("String parsing error encountered from {}", addr));
+                warn_encoding(user_state, "Your client isn't sending UTF-8, input that isn't \
+                    was ignored\r\n");
+                continue;
+            },
+            Err(e) if e.error_type == ReadLineErrorType::LineTooLong => {
+                log_event(Level::Warn, &format!("Line too long from {}, discarded", addr));
+                log_write(stream, "Line too long, discarded\r\n");
+                user_state.queue("Line too long, discarded\r\n");
                 continue;
             },
             Err(e) if e.error_type == ReadLineErrorType::WouldBlock => {
-                match game_server_state.client_logic(stream, None) {
+                // a read cut short by the budget leaves the rest on the socket for the next poll
+                active |= user_state.inbound.len() != waiting;
+                match game_server_state.handle_input(addr, None) {
                     Ok(_) => {},
-                    Err(_) => {
-                        game_server_state.client_disconnect(stream);
-                        return false;
+                    Err(e) => {
+                        game_error_disconnect(stream, addr, game_server_state, e);
+                        return None;
                     }
                 }
                 break;
             },
             Err(e) if e.error_type == ReadLineErrorType::Disconnected => {
-                println!("Disconnected from {}", stream.peer_addr().unwrap());
-                game_server_state.client_disconnect(stream);
-                return false;
+                log_event(Level::Info, &format!("Disconnected from {}", addr));
+                game_server_state.client_disconnect(stream, addr);
+                return None;
             }
             Err(_) => {
-                game_server_state.client_disconnect(stream);
-                println!("Unrecoverable error encountered, dropping connection to {}", stream.peer_addr().unwrap());
-                return false;
+                game_server_state.client_disconnect(stream, addr);
+                log_event(Level::Error, &format!("Unrecoverable error encountered, dropping connection to {}",
+                                                 addr));
+                return None;
             }
         }
     }
-    true
+    Some(active)
+}
+
+/// Tells the user their client's text encoding is a problem, only the first time it comes up
+fn warn_encoding(user_state : &mut game::User, notice : &str) {
+    if !user_state.encoding_warned {
+        user_state.encoding_warned = true;
+        user_state.queue(notice);
+    }
+}
+
+/// Drops a client the game logic can't carry on with, making sure
+/// the prompt explaining why gets out before the goodbye
+fn game_error_disconnect(stream : &mut TcpStream, addr : SocketAddr,
+                         game_server_state : &mut GameServerState, error : GameError) {
+    log_event(Level::Error, &format!("Game error for {}, dropping connection: {}", addr, error));
+    let prompt = game_server_state.prompt_for(addr);
+    let user_state = game::get_user_state(&mut game_server_state.user_state, addr);
+    if let Some(prompt) = prompt.filter(|prompt| *prompt != user_state.prev_prompt) {
+        log_write(stream, &prompt);
+        user_state.queue(&prompt);
+    }
+    let _ = flush_pending(stream, &mut user_state.outbound);
+    game_server_state.client_disconnect(stream, addr);
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -73,7 +167,8 @@ pub enum ReadLineErrorType {
     StringParsing,
     Unrecoverable,
     WouldBlock,
-    Disconnected
+    Disconnected,
+    LineTooLong
 }
 
 #[derive(Debug, Clone)]
@@ -83,9 +178,23 @@ pub struct ReadLineError {
 
 /// Reads from the given socket until it would block
 /// requires the input socket to be non blocking
-/// buf_size is the size of the buffer used when copying from the socket
-pub fn read_until_block(stream : &mut TcpStream, buf_size : usize) -> Result<String, ReadLineError> {
-    let mut line: Vec<u8> = Vec::new();
+/// buf_size is the size of the buffer used when copying from the socket, each read
+/// is a system call so a line takes about its length divided by buf_size of them
+/// lines longer than max_line are discarded, discarding is set while the rest of one is
+/// still to come and everything up to its newline is dropped, even over later calls
+/// partial holds a line that's still arriving, it's picked up again on the next call
+/// input that isn't valid UTF-8 is a StringParsing error, or has the bad bytes replaced if lossy is set
+/// telnet commands are taken out of the data, window_width is set if the client reports its width
+/// a line of just Ctrl-C or Ctrl-D is taken as the client disconnecting
+///
+/// Fairness: at most READ_BUDGET bytes (plus one buffer) are read per call, once that's
+/// reached WouldBlock is returned with the data kept in partial, so a client that keeps
+/// sending can't hold up the event loop's other clients, not even while it's being discarded
+pub fn read_until_block(stream : &mut TcpStream, buf_size : usize, max_line : usize, lossy : bool,
+                        partial : &mut Vec<u8>, discarding : &mut bool,
+                        window_width : &mut Option<u16>) -> Result<String, ReadLineError> {
+    let mut line = std::mem::take(partial);
+    let mut read_this_call = 0;
     loop {
         let mut buf = vec![0; buf_size];
         let read_size = match stream.read(&mut buf) {
@@ -94,17 +203,31 @@ pub fn read_until_block(stream : &mut TcpStream, buf_size : usize) -> Result<Str
             Err(err) if err.kind() == ErrorKind::WouldBlock => {
                 // if would block, then we either have an entire line
                 // or there's no more data right now to grab
-                if line.len() == 0 {
+                let (text, reported_width, unfinished) = telnet::strip_commands(&line);
+                if read_this_call > 0 && asks_to_hang_up(&text) {
+                    return Err(ReadLineError { error_type: ReadLineErrorType::Disconnected });
+                }
+                if read_this_call == 0 && !text.contains(&b'\n') {
+                    // nothing new, keep any unfinished line for later, lines left
+                    // over from a call that used up its budget still go out
+                    *partial = line;
                     return Err(ReadLineError { error_type: ReadLineErrorType::WouldBlock });
                 }
-                return String::from_utf8(line)
+                if reported_width.is_some() {
+                    *window_width = reported_width;
+                }
+                *partial = unfinished;
+                let text = match String::from_utf8(text) {
+                    Err(e) if lossy => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+                    text => text
+                };
+                return text
                     .map_err(|_| ReadLineError { error_type: ReadLineErrorType::StringParsing })
-                    .map(|line| {
-                        println!("{} <- {}: {:?}", 
-                            stream.local_addr().unwrap(), 
-                            stream.peer_addr().unwrap(),
-                            line.trim_end_matches('\n').trim_end_matches('\r'));
-                        line
+                    .inspect(|line| {
+                        log_event(Level::Info, &format!("{} <- {}: {:?}",
+                            describe_addr(stream.local_addr()),
+                            describe_addr(stream.peer_addr()),
+                            line.trim_end_matches('\n').trim_end_matches('\r')));
                     });
             },
             Err(_) => return Err(ReadLineError { error_type: ReadLineErrorType::Unrecoverable })
@@ -112,64 +235,298 @@ pub fn read_until_block(stream : &mut TcpStream, buf_size : usize) -> Result<Str
         if read_size == 0 {
             return Err(ReadLineError { error_type: ReadLineErrorType::Disconnected })
         }
-        line.extend_from_slice(&buf[..read_size]);
+        append_input(&mut line, &buf[..read_size], discarding);
+        read_this_call += read_size;
+        // only the line still arriving counts, the ones before it are finished
+        let line_start = line.iter().rposition(|byte| *byte == b'\n').map_or(0, |end| end + 1);
+        if line.len() - line_start > max_line {
+            // throw away the line, the rest of it is dropped by later calls as it's read,
+            // within their budgets, the finished lines before it are kept for the next call
+            line.truncate(line_start);
+            *discarding = true;
+            *partial = line;
+            return Err(ReadLineError { error_type: ReadLineErrorType::LineTooLong });
+        }
+        if read_this_call >= READ_BUDGET {
+            // give the other clients a turn, the rest is read on the next poll
+            *partial = line;
+            return Err(ReadLineError { error_type: ReadLineErrorType::WouldBlock });
+        }
     }
 }
 
+/// Adds what was read to the line, unless it's the rest of an overlong line being
+/// discarded, then only what comes after its newline is kept
+fn append_input(line : &mut Vec<u8>, data : &[u8], discarding : &mut bool) {
+    let data = if *discarding {
+        match data.iter().position(|byte| *byte == b'\n') {
+            Some(end) => {
+                *discarding = false;
+                &data[end + 1..]
+            },
+            None => return
+        }
+    } else {
+        data
+    };
+    line.extend_from_slice(data);
+}
+
+/// Whether the client sent Ctrl-C or Ctrl-D on a line of its own, the way clients
+/// without a quit command of their own hang up, anywhere else they're left in as text
+fn asks_to_hang_up(text : &[u8]) -> bool {
+    text.split(|b| *b == b'\n')
+        .any(|line| matches!(line, [telnet::END_OF_TEXT | telnet::END_OF_TRANSMISSION]
+                                  | [telnet::END_OF_TEXT | telnet::END_OF_TRANSMISSION, b'\r']))
+}
+
 pub fn write(stream : &mut TcpStream, line : &str) -> Result<(), std::io::Error> {
-    println!("{} -> {}: {:?}", 
-        stream.local_addr().unwrap(), 
-        stream.peer_addr().unwrap(),
-        line.trim_end_matches('\n').trim_end_matches('\r'));
+    log_write(stream, line);
     stream.write_all(line.as_bytes())
 }
 
+fn log_write(stream : &TcpStream, line : &str) {
+    log_event(Level::Info, &format!("{} -> {}: {:?}",
+        describe_addr(stream.local_addr()),
+        describe_addr(stream.peer_addr()),
+        line.trim_end_matches('\n').trim_end_matches('\r')));
+}
+
+/// Formats a socket address for logging, a connection reset by the other end
+/// no longer has a peer address so this mustn't fail
+fn describe_addr(addr : std::io::Result<SocketAddr>) -> String {
+    addr.map_or_else(|_| "unknown".to_string(), |addr| addr.to_string())
+}
+
+/// Writes as much of the pending output as the socket accepts without blocking,
+/// whatever couldn't be sent is left in pending for the next call
+/// requires the input socket to be non blocking
+pub fn flush_pending(stream : &mut TcpStream, pending : &mut Vec<u8>) -> Result<(), std::io::Error> {
+    while !pending.is_empty() {
+        match stream.write(pending) {
+            Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero)),
+            Ok(written) => {
+                pending.drain(..written);
+            },
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(())
+}
+
+/// Writes the rooms to the save file, a failed save is logged and tried again next time
+fn save_rooms(game_server_state : &GameServerState) {
+    if let Err(e) = game_server_state.save_rooms() {
+        log_event(Level::Error, &format!("Couldn't save rooms: {}", e));
+    }
+}
+
+/// Binds the listener for the server on the given port, for the IP versions the mode allows
+pub fn bind_listener(ip_mode : IpMode, port : u16) -> std::io::Result<TcpListener> {
+    match ip_mode {
+        IpMode::Ipv4 => TcpListener::bind((Ipv4Addr::LOCALHOST, port)),
+        IpMode::Ipv6 => TcpListener::bind((Ipv6Addr::LOCALHOST, port)),
+        IpMode::DualStack => {
+            // whether an IPv6 socket takes IPv4 clients by default differs between systems
+            let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+            socket.set_only_v6(false)?;
+            // same as TcpListener::bind does
+            #[cfg(not(windows))]
+            socket.set_reuse_address(true)?;
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+            socket.listen(128)?;
+            Ok(socket.into())
+        }
+    }
+}
+
 /// The event loop for the TCP server
 /// Handles all the sockets connections and disconnections
 pub fn event_loop(listener : TcpListener) -> std::io::Result<()> {
-    let _ = listener.set_nonblocking(true)
+    event_loop_with_state(listener, GameServerState::new(), &AtomicBool::new(false))
+}
+
+/// Runs the event loop with an existing game server state,
+/// e.g. one with an end-of-game hook set
+/// returns once the shutdown flag is set, after notifying and disconnecting every client
+///
+/// The loop sleeps until a socket is ready or the idle timeout passes, each pass only polls
+/// the clients whose sockets are ready, the ones the game marked as dirty because another
+/// client or a timer changed what they see, and the ones the read budget left input for
+pub fn event_loop_with_state(listener : TcpListener, mut game_server_state : GameServerState,
+                             shutdown : &AtomicBool) -> std::io::Result<()> {
+    listener.set_nonblocking(true)
         .expect("Non blocking sockets must be supported");
+    let mut listener = mio::net::TcpListener::from_std(listener);
+    let mut poll = Poll::new()?;
+    poll.registry().register(&mut listener, LISTENER, Interest::READABLE)?;
+    let mut events = Events::with_capacity(1024);
 
-    let mut game_server_state = GameServerState::new();
-    let mut open_streams = Vec::new();
-    loop {
-        // get incoming connections
-        let mut incoming = listener.incoming();
-        while let Some(stream) = incoming.next() {
-            match stream {
-                Ok(stream) => {
-                    println!("New connection {}", stream.peer_addr().unwrap()); 
-                    open_streams.push(stream);
+    let mut open_streams : HashMap<Token, (TcpStream, SocketAddr)> = HashMap::new();
+    // the token of each client's socket, for the users the game marks as dirty
+    let mut tokens : HashMap<SocketAddr, Token> = HashMap::new();
+    let mut next_token = LISTENER.0 + 1;
+    // clients that sent something last pass, readiness is only reported again for new data
+    // so whatever the read budget left on the socket is picked up by going straight on
+    let mut busy : HashSet<Token> = HashSet::new();
+    let mut next_stats = Instant::now() + STATS_INTERVAL;
+    let mut next_save = Instant::now() + SAVE_INTERVAL;
+    let mut next_keepalive = game_server_state.config.keepalive.map(|interval| Instant::now() + interval);
+    while !shutdown.load(Ordering::SeqCst) {
+        let timeout = if busy.is_empty() && !game_server_state.is_dirty() {
+            game_server_state.config.idle_sleep
+        } else {
+            Duration::ZERO
+        };
+        match poll.poll(&mut events, Some(timeout)) {
+            // a signal, the shutdown flag is checked again before waiting any longer
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            result => result?
+        }
+        let mut ready = std::mem::take(&mut busy);
+        for event in events.iter() {
+            if event.token() != LISTENER {
+                ready.insert(event.token());
+                continue;
+            }
+            // get incoming connections, keeping the address each was accepted from
+            // since a reset connection can't be asked for it later
+            loop {
+                match listener.accept() {
+                    Ok((mut stream, addr)) if open_streams.len() >= game_server_state.config.max_connections => {
+                        // turned away without any state being kept for them
+                        log_event(Level::Warn, &format!("Server full, turning away {}", addr));
+                        let _ = write(&mut stream, "Server full, try later\r\n");
+                        let _ = stream.shutdown(Shutdown::Both);
+                    },
+                    Ok((mut stream, addr)) => {
+                        // IPv4 clients of a dual stack listener show up as mapped IPv6 addresses,
+                        // key them on their IPv4 address like they would be otherwise
+                        let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+                        let token = Token(next_token);
+                        next_token += 1;
+                        poll.registry().register(&mut stream, token, Interest::READABLE | Interest::WRITABLE)?;
+                        // the system probes the connection too, for clients that never get any output
+                        if let Some(interval) = game_server_state.config.keepalive {
+                            if let Err(e) = SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(interval)) {
+                                log_event(Level::Warn, &format!("Couldn't turn on keepalives for {}: {}", addr, e));
+                            }
+                        }
+                        // a socket from an earlier connection on the same address is long gone
+                        open_streams.retain(|_, (_, open_addr)| *open_addr != addr);
+                        let user_state = game_server_state.connect(addr);
+                        log_event(Level::Info, &format!("New connection {} from {}", user_state.connection, addr));
+                        // ask for the window size so boards can be fit to it
+                        user_state.outbound.extend_from_slice(&telnet::DO_NAWS);
+                        open_streams.insert(token, (stream, addr));
+                        tokens.insert(addr, token);
+                        ready.insert(token);
+                    },
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
+                        log_event(Level::Warn, "Connection reset before it was accepted");
+                        continue;
+                    },
+                    Err(e) => return Err(e)
+                }
+            }
+        }
+        // a client that's gone without closing the connection only shows up once a write to it fails
+        if next_keepalive.is_some_and(|due| Instant::now() >= due) {
+            for (token, (_, addr)) in &open_streams {
+                game::get_user_state(&mut game_server_state.user_state, *addr)
+                    .outbound.extend_from_slice(&telnet::KEEPALIVE);
+                ready.insert(*token);
+            }
+            next_keepalive = game_server_state.config.keepalive.map(|interval| Instant::now() + interval);
+        }
+        let now = Instant::now();
+        // timers go first so what they changed goes out this pass
+        game_server_state.tick(now);
+        ready.extend(game_server_state.take_dirty().iter().filter_map(|addr| tokens.get(addr)));
+        for token in ready {
+            let Some((stream, addr)) = open_streams.get_mut(&token) else {
+                continue;
+            };
+            match handle_client(stream, *addr, &mut game_server_state) {
+                Some(true) => {
+                    busy.insert(token);
                 },
-                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
-                Err(e) => return Err(e)
+                Some(false) => {},
+                None => {
+                    let _ = poll.registry().deregister(stream);
+                    let _ = stream.shutdown(Shutdown::Both);
+                    tokens.remove(addr);
+                    open_streams.remove(&token);
+                }
             }
         }
-        // iterate through open streams and process
-        open_streams.retain_mut(|stream| {
-            let retain = handle_client(stream, &mut game_server_state);
-            if !retain {
-                let _ = stream.shutdown(Shutdown::Both);
+        if now >= next_stats {
+            log_event(Level::Info, &format!("{} connections, {} active rooms",
+                                            game_server_state.active_connections(),
+                                            game_server_state.active_rooms()));
+            next_stats = now + STATS_INTERVAL;
+        }
+        if now >= next_save {
+            save_rooms(&game_server_state);
+            next_save = now + SAVE_INTERVAL;
+        }
+    }
+    // saved while everyone is still seated
+    save_rooms(&game_server_state);
+    for (stream, addr) in open_streams.values_mut() {
+        let addr = *addr;
+        // finish any half sent prompt first so the notice isn't spliced into it
+        match game_server_state.user_state.get_mut(&addr) {
+            Some(user_state) => {
+                user_state.queue("Server shutting down\r\n");
+                let _ = stream.write_all(&user_state.outbound);
+            },
+            None => {
+                let _ = write(stream, "Server shutting down\r\n");
             }
-            retain
-        });
-        sleep(Duration::from_millis(15))
+        }
+        game_server_state.client_disconnect(stream, addr);
+        let _ = stream.shutdown(Shutdown::Both);
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{net::{TcpListener, TcpStream, Shutdown}, io::Write};
-    use crate::{read_until_block, ReadLineErrorType};
+    use std::{net::{TcpListener, TcpStream, Shutdown, SocketAddr, Ipv4Addr, Ipv6Addr}, io::{Read, Write}};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use crate::{read_until_block, flush_pending, handle_client, ReadLineErrorType, event_loop_with_state,
+                bind_listener, telnet, GameServerState, IpMode, ServerConfig, MAX_PENDING_OUTPUT, READ_BUDGET};
+    use crate::game::ServerState;
+
+    /// Accepts the next connection as the non blocking stream the event loop would have
+    fn accept(listener : &TcpListener) -> (mio::net::TcpStream, SocketAddr) {
+        let (stream, addr) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        (mio::net::TcpStream::from_std(stream), addr)
+    }
+
+    // the buffer sizes lines are read back with, small ones split lines over many reads
+    const BUF_SIZES : [usize; 3] = [1, 10, 1024];
 
     fn run_line_test(send_line : &str) {
+        for buf_size in BUF_SIZES {
+            run_line_test_with_buf_size(send_line, buf_size);
+        }
+    }
+
+    fn run_line_test_with_buf_size(send_line : &str, buf_size : usize) {
         // create a listener
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         // create a client socket 
         let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
-        // get client connection from listener, make it non blocking
-        let mut stream = listener.accept().unwrap().0;
-        stream.set_nonblocking(true).unwrap();
+        // get client connection from listener, non blocking like the event loop's
+        let mut stream = accept(&listener).0;
         // make sure it connected correctly
         assert_eq!(stream.local_addr().unwrap(), client.peer_addr().unwrap());
         assert_eq!(stream.peer_addr().unwrap(), client.local_addr().unwrap());
@@ -177,8 +534,9 @@ mod tests {
         client.write_all(send_line.as_bytes()).unwrap(); 
         client.flush().unwrap();
         // receive it
+        let mut partial = Vec::new();
         loop {
-            match read_until_block(&mut stream, 10) {
+            match read_until_block(&mut stream, buf_size, 1024, false, &mut partial, &mut false, &mut None) {
                 Ok(recv_line) => {
                     // assert it's the same
                     assert_eq!(send_line, recv_line);
@@ -218,4 +576,596 @@ mod tests {
     fn simple_read_utf8() {
         run_line_test("😀 😃 😄 😁 😆 😅 😂 🤣 🥲 🥹");
     }
+
+    #[test]
+    fn polls_report_whether_the_client_sent_anything() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        let addr = stream.peer_addr().unwrap();
+        let mut game_server_state = GameServerState::new();
+        assert_eq!(handle_client(&mut stream, addr, &mut game_server_state), Some(false));
+        client.write_all(b"alice\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(handle_client(&mut stream, addr, &mut game_server_state), Some(true));
+        // a quiet client lets the event loop rest
+        assert_eq!(handle_client(&mut stream, addr, &mut game_server_state), Some(false));
+    }
+
+    #[test]
+    fn lines_in_one_write_are_run_separately() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        let addr = stream.peer_addr().unwrap();
+        let mut game_server_state = GameServerState::new();
+        // the first poll moves the new user to username entry
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        client.write_all(b"alice\r\n0").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        let user = &game_server_state.user_state[&addr];
+        assert_eq!((user.user_name.as_str(), user.state), ("alice", ServerState::LobbySelection));
+        assert_eq!(user.inbound, b"0");
+        // the rest of the line finishes the command
+        client.write_all(b"\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert_eq!(game_server_state.user_state[&addr].state, ServerState::RoomPasswordSetup);
+    }
+
+    #[test]
+    fn bare_newline_clients_get_bare_newlines_back() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(std::time::Duration::from_millis(200))).unwrap();
+        let mut stream = accept(&listener).0;
+        let addr = stream.peer_addr().unwrap();
+        let mut game_server_state = GameServerState::new();
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        client.write_all(b"alice\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert_eq!(game_server_state.user_state[&addr].line_ending, crate::game::LineEnding::Lf);
+        let mut received = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(read @ 1..) = client.read(&mut buf) {
+            received.extend_from_slice(&buf[..read]);
+        }
+        let received = String::from_utf8_lossy(&received);
+        // everything up to the username went out before the client's line ending was known
+        let (before, lobby) = received.split_once("Which lobby").unwrap();
+        assert!(before.ends_with("characters\r\n"));
+        assert!(lobby.contains("\n0: New Lobby") && !lobby.contains('\r'));
+    }
+
+    #[test]
+    fn telnet_window_size_is_picked_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        let addr = stream.peer_addr().unwrap();
+        let mut game_server_state = GameServerState::new();
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        // the report is split over two reads, with the name around it
+        client.write_all(&[b'a', b'l', 255, 250, 31, 0]).unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        client.write_all(&[80, 0, 24, 255, 240, b'i', b'c', b'e', b'\r', b'\n']).unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        let user = &game_server_state.user_state[&addr];
+        assert_eq!((user.user_name.as_str(), user.window_width), ("alice", Some(80)));
+    }
+
+    #[test]
+    fn invalid_utf8_is_dropped_or_replaced() {
+        for (lossy, expected) in [(false, None), (true, Some("caf\u{FFFD} au lait\r\n"))] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let mut stream = accept(&listener).0;
+            // Latin-1 for "café au lait"
+            client.write_all(b"caf\xe9 au lait\r\n").unwrap();
+            thread::sleep(std::time::Duration::from_millis(50));
+            let mut partial = Vec::new();
+            let read = read_until_block(&mut stream, 10, 1024, lossy, &mut partial, &mut false, &mut None);
+            match expected {
+                Some(line) => assert_eq!(read.unwrap(), line),
+                None => assert_eq!(read.unwrap_err().error_type, ReadLineErrorType::StringParsing)
+            }
+            assert!(partial.is_empty());
+        }
+    }
+
+    #[test]
+    fn clients_are_told_once_about_their_encoding() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        let addr = stream.peer_addr().unwrap();
+        let mut game_server_state = GameServerState::new();
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        for _ in 0..2 {
+            client.write_all(b"\xe9lise\r\n").unwrap();
+            thread::sleep(std::time::Duration::from_millis(50));
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        }
+        // the client isn't dropped, a UTF-8 line still gets through
+        client.write_all(b"alice\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert_eq!(game_server_state.user_state[&addr].state, ServerState::LobbySelection);
+        stream.shutdown(Shutdown::Both).unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        let received = String::from_utf8_lossy(&received);
+        assert_eq!(received.matches("Your client isn't sending UTF-8").count(), 1);
+    }
+
+    #[test]
+    fn ctrl_c_and_ctrl_d_hang_up() {
+        for hang_up in [b"\x04".as_slice(), b"\x03\r\n", &[255, 244]] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let mut stream = accept(&listener).0;
+            let addr = stream.peer_addr().unwrap();
+            let mut game_server_state = GameServerState::new();
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+            client.write_all(b"alice\r\n").unwrap();
+            thread::sleep(std::time::Duration::from_millis(50));
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+            client.write_all(hang_up).unwrap();
+            thread::sleep(std::time::Duration::from_millis(50));
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_none());
+            assert!(!game_server_state.user_state.contains_key(&addr));
+        }
+    }
+
+    #[test]
+    fn game_errors_drop_the_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        let addr = stream.peer_addr().unwrap();
+        let mut game_server_state = GameServerState::new();
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        client.write_all(b"alice\r\n0\r\n\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert_eq!(game_server_state.user_state[&addr].state, ServerState::InRoom);
+        // being in a room without knowing which one is something the game can't recover from
+        game_server_state.user_state.get_mut(&addr).unwrap().game_room_key = None;
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_none());
+        assert!(!game_server_state.user_state.contains_key(&addr));
+        stream.shutdown(Shutdown::Both).unwrap();
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert!(received.ends_with("A fatal error has occurred, disconnecting...\r\nGoodbye\r\n"));
+    }
+
+    #[test]
+    fn reads_yield_after_the_budget() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        let sent = "a".repeat(3 * READ_BUDGET) + "\r\n";
+        client.write_all(sent.as_bytes()).unwrap();
+        // let all of it arrive so the budget is what stops the read
+        thread::sleep(std::time::Duration::from_millis(50));
+        let mut partial = Vec::new();
+        let first = read_until_block(&mut stream, 10, 1024, false, &mut partial, &mut false, &mut None);
+        assert_eq!(first.unwrap_err().error_type, ReadLineErrorType::WouldBlock);
+        assert!((READ_BUDGET..READ_BUDGET + 10).contains(&partial.len()));
+        // the line is put back together over the next polls
+        loop {
+            match read_until_block(&mut stream, 10, 1024, false, &mut partial, &mut false, &mut None) {
+                Ok(line) => {
+                    assert_eq!(line, sent);
+                    break;
+                },
+                Err(e) if e.error_type == ReadLineErrorType::WouldBlock => continue,
+                Err(e) => panic!("{:?}", e)
+            }
+        }
+    }
+
+    /// Number of read_until_block polls it takes for a line to come back whole,
+    /// each poll is at least one read of the socket
+    fn polls_to_read(line : &str, buf_size : usize) -> usize {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        client.write_all(line.as_bytes()).unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        let mut partial = Vec::new();
+        for polls in 1.. {
+            match read_until_block(&mut stream, buf_size, 1024, false, &mut partial, &mut false, &mut None) {
+                Ok(received) => {
+                    assert_eq!(received, line);
+                    return polls;
+                },
+                Err(e) if e.error_type == ReadLineErrorType::WouldBlock => continue,
+                Err(e) => panic!("{:?}", e)
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn bigger_buffers_read_a_paste_in_fewer_reads() {
+        let paste = "a".repeat(1000) + "\r\n";
+        // a 10 byte buffer takes 101 reads, spread out over polls by the read budget
+        assert!(polls_to_read(&paste, 10) > paste.len() / READ_BUDGET);
+        // the default buffer reads it all at once, the next poll finds the socket empty
+        assert!(polls_to_read(&paste, ServerConfig::default().read_buf_size) <= 2);
+    }
+
+    #[test]
+    fn overlong_line_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        // write from another thread, 100KB may not fit in the socket buffers
+        let writer = thread::spawn(move || {
+            // may fail once the server side is closed below
+            let _ = client.write_all(&[b'a'; 100 * 1024]);
+            client
+        });
+        let mut partial = Vec::new();
+        loop {
+            match read_until_block(&mut stream, 10, 1024, false, &mut partial, &mut false, &mut None) {
+                Err(e) if e.error_type == ReadLineErrorType::LineTooLong => break,
+                Err(e) if e.error_type == ReadLineErrorType::WouldBlock => continue,
+                // the writer may not have gotten far yet
+                Ok(line) => assert!(line.len() <= 1024),
+                Err(e) => panic!("{:?}", e)
+            }
+        }
+        drop(stream);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn overlong_lines_are_discarded_up_to_their_newline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        let addr = stream.peer_addr().unwrap();
+        let mut game_server_state = GameServerState::new();
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        client.write_all(b"alice\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        client.write_all(&[b'a'; 2000]).unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        while !game_server_state.user_state[&addr].discarding {
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        }
+        // the end of the line comes in a later write, it's still part of the discarded line
+        client.write_all(b"0\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert_eq!(game_server_state.user_state[&addr].state, ServerState::LobbySelection);
+        client.write_all(b"0\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert_eq!(game_server_state.user_state[&addr].state, ServerState::RoomPasswordSetup);
+    }
+
+    #[test]
+    fn pasted_lines_are_measured_one_at_a_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        // together they're past the limit, each of them is well within it
+        let paste = format!("{}\r\n", "a".repeat(300)).repeat(5);
+        client.write_all(paste.as_bytes()).unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        let mut partial = Vec::new();
+        let mut received = String::new();
+        while received.len() < paste.len() {
+            match read_until_block(&mut stream, 1024, 1024, false, &mut partial, &mut false, &mut None) {
+                Ok(lines) => received += &lines,
+                Err(e) if e.error_type == ReadLineErrorType::WouldBlock => continue,
+                Err(e) => panic!("{:?}", e)
+            }
+        }
+        assert_eq!(received, paste);
+
+        // the lines before an overlong one still go through
+        client.write_all(format!("alice\r\n{}", "a".repeat(2000)).as_bytes()).unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        let mut discarding = false;
+        loop {
+            match read_until_block(&mut stream, 1024, 1024, false, &mut partial, &mut discarding, &mut None) {
+                Err(e) if e.error_type == ReadLineErrorType::LineTooLong => break,
+                Err(e) if e.error_type == ReadLineErrorType::WouldBlock => continue,
+                other => panic!("{:?}", other)
+            }
+        }
+        assert!(discarding);
+        assert_eq!(read_until_block(&mut stream, 1024, 1024, false, &mut partial, &mut discarding, &mut None).unwrap(),
+                   "alice\r\n");
+    }
+
+    #[test]
+    fn discarding_stays_within_the_read_budget() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        client.write_all(&[b'a'; 8 * 1024]).unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        let mut partial = Vec::new();
+        let mut discarding = false;
+        let mut polls = 0;
+        loop {
+            polls += 1;
+            match read_until_block(&mut stream, 10, 1024, false, &mut partial, &mut discarding, &mut None) {
+                Err(e) if e.error_type == ReadLineErrorType::LineTooLong => break,
+                Err(e) if e.error_type == ReadLineErrorType::WouldBlock => continue,
+                other => panic!("{:?}", other)
+            }
+        }
+        // the rest of the line isn't drained in one go, a poll only takes a budget of it
+        let discarded = read_until_block(&mut stream, 10, 1024, false, &mut partial, &mut discarding, &mut None);
+        assert_eq!(discarded.unwrap_err().error_type, ReadLineErrorType::WouldBlock);
+        assert!(partial.is_empty());
+        let mut rest = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(read) => rest.extend_from_slice(&buf[..read]),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{:?}", e)
+            }
+        }
+        assert!(rest.len() > 4 * 1024);
+        assert!(discarding && polls > 1);
+        // the next line after the overlong one goes through
+        client.write_all(b"\r\nalice\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(read_until_block(&mut stream, 10, 1024, false, &mut partial, &mut discarding, &mut None).unwrap(),
+                   "alice\r\n");
+        assert!(!discarding);
+    }
+
+    #[test]
+    fn large_writes_are_not_truncated() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = accept(&listener).0;
+        // more than the socket buffers hold while nobody is reading
+        let sent : Vec<u8> = (0..8 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut pending = sent.clone();
+        flush_pending(&mut stream, &mut pending).unwrap();
+        assert!(!pending.is_empty());
+
+        let reader = thread::spawn(move || {
+            let mut received = vec![0; sent.len()];
+            client.read_exact(&mut received).unwrap();
+            assert!(received == sent);
+        });
+        while !pending.is_empty() {
+            flush_pending(&mut stream, &mut pending).unwrap();
+        }
+        reader.join().unwrap();
+    }
+
+    #[test]
+    fn clients_that_stop_reading_are_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, addr) = accept(&listener);
+        let mut game_server_state = GameServerState::new();
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        // fill the socket up with nobody reading on the other end
+        while stream.write(&[b'a'; 4096]).is_ok() {}
+        // a backlog within the limit waits for the client to catch up
+        game_server_state.user_state.get_mut(&addr).unwrap().outbound = vec![b'a'; MAX_PENDING_OUTPUT / 2];
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert!(!game_server_state.user_state[&addr].outbound.is_empty());
+        // past it, the client is let go
+        game_server_state.user_state.get_mut(&addr).unwrap().outbound.extend(vec![b'a'; MAX_PENDING_OUTPUT]);
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_none());
+        assert!(!game_server_state.user_state.contains_key(&addr));
+    }
+
+    #[test]
+    fn failed_writes_take_the_player_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, addr) = accept(&listener);
+        let mut game_server_state = GameServerState::new();
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        client.write_all(b"alice\r\n0\r\n\r\n").unwrap();
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+        assert_eq!(game_server_state.game_rooms[&1].codenames().unwrap().player_count(), 1);
+        // the client goes without a goodbye, resetting the connection instead of closing it
+        socket2::SockRef::from(&client).set_linger(Some(std::time::Duration::ZERO)).unwrap();
+        drop(client);
+        thread::sleep(std::time::Duration::from_millis(50));
+        game_server_state.user_state.get_mut(&addr).unwrap().outbound.extend_from_slice(&telnet::KEEPALIVE);
+        assert!(handle_client(&mut stream, addr, &mut game_server_state).is_none());
+        assert!(!game_server_state.user_state.contains_key(&addr));
+        // they were the only one in the room, so it closed with them gone
+        assert!(!game_server_state.game_rooms.contains_key(&1));
+    }
+
+    #[test]
+    fn keepalives_reach_quiet_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_shutdown = shutdown.clone();
+        let mut game_server_state = GameServerState::new();
+        game_server_state.config.keepalive = Some(std::time::Duration::from_millis(100));
+        let server = thread::spawn(move || {
+            event_loop_with_state(listener, game_server_state, &server_shutdown)
+        });
+        client.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        // the client never sends anything, the no-op still comes
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        while !received.windows(2).any(|bytes| bytes == telnet::KEEPALIVE) {
+            let read = client.read(&mut buf).unwrap();
+            assert!(read > 0);
+            received.extend_from_slice(&buf[..read]);
+        }
+        shutdown.store(true, Ordering::SeqCst);
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn connections_past_the_limit_are_turned_away() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_shutdown = shutdown.clone();
+        let mut game_server_state = GameServerState::new();
+        game_server_state.config.max_connections = 2;
+        let server = thread::spawn(move || {
+            event_loop_with_state(listener, game_server_state, &server_shutdown)
+        });
+        let mut buf = [0; 64];
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let mut client = TcpStream::connect(server_addr).unwrap();
+            // wait for the server to pick up the connection
+            assert!(client.read(&mut buf).unwrap() > 0);
+            clients.push(client);
+        }
+        let mut turned_away = TcpStream::connect(server_addr).unwrap();
+        let mut received = String::new();
+        turned_away.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "Server full, try later\r\n");
+
+        // a slot opens up once someone leaves
+        drop(clients.pop());
+        thread::sleep(std::time::Duration::from_millis(100));
+        let mut client = TcpStream::connect(server_addr).unwrap();
+        assert!(client.read(&mut buf).unwrap() > 0);
+        assert!(!String::from_utf8_lossy(&buf).contains("Server full"));
+        shutdown.store(true, Ordering::SeqCst);
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn shutdown_notifies_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_shutdown = shutdown.clone();
+        let server = thread::spawn(move || {
+            event_loop_with_state(listener, GameServerState::new(), &server_shutdown)
+        });
+        // wait for the server to pick up the connection
+        let mut buf = [0; 64];
+        assert!(client.read(&mut buf).unwrap() > 0);
+        shutdown.store(true, Ordering::SeqCst);
+        assert!(server.join().unwrap().is_ok());
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).unwrap();
+        assert!(rest.contains("Server shutting down\r\nGoodbye\r\n"));
+    }
+
+    #[test]
+    fn input_past_the_read_budget_is_picked_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_shutdown = shutdown.clone();
+        let server = thread::spawn(move || {
+            event_loop_with_state(listener, GameServerState::new(), &server_shutdown)
+        });
+        let mut buf = [0; 1024];
+        assert!(client.read(&mut buf).unwrap() > 0);
+        // the name comes after more than one read budget of blank lines, all sent at once,
+        // so the socket doesn't become readable again for it
+        let input = "\r\n".repeat(READ_BUDGET) + "alice\r\n";
+        client.write_all(input.as_bytes()).unwrap();
+        client.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        let mut received = String::new();
+        while !received.contains("Which lobby do you want to join?") {
+            let read = client.read(&mut buf).unwrap();
+            assert!(read > 0);
+            received += &String::from_utf8_lossy(&buf[..read]);
+        }
+        shutdown.store(true, Ordering::SeqCst);
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn ipv6_clients_play_in_rooms() {
+        let listener = bind_listener(IpMode::Ipv6, 0).unwrap();
+        let mut game_server_state = GameServerState::new();
+        let mut clients = Vec::new();
+        for input in ["alice\r\n0\r\n\r\n", "bob\r\n1\r\n"] {
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (mut stream, addr) = accept(&listener);
+            assert!(addr.is_ipv6());
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+            client.write_all(input.as_bytes()).unwrap();
+            thread::sleep(std::time::Duration::from_millis(50));
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+            // joins the room on the next poll
+            assert!(handle_client(&mut stream, addr, &mut game_server_state).is_some());
+            assert_eq!(game_server_state.user_state[&addr].game_room_key, Some(1));
+            clients.push((client, stream, addr));
+        }
+        let room = game_server_state.game_rooms[&1].codenames().unwrap();
+        assert_eq!(room.player_count(), 2);
+        let (_, stream, alice) = &mut clients[0];
+        game_server_state.client_disconnect(stream, *alice);
+        assert!(!game_server_state.user_state.contains_key(alice));
+        assert_eq!(game_server_state.game_rooms[&1].codenames().unwrap().player_count(), 1);
+    }
+
+    #[test]
+    fn dual_stack_takes_both_ip_versions() {
+        let listener = bind_listener(IpMode::DualStack, 0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_shutdown = shutdown.clone();
+        let server = thread::spawn(move || {
+            event_loop_with_state(listener, GameServerState::new(), &server_shutdown)
+        });
+        for addr in [SocketAddr::from((Ipv4Addr::LOCALHOST, port)), SocketAddr::from((Ipv6Addr::LOCALHOST, port))] {
+            let mut client = TcpStream::connect(addr).unwrap();
+            let mut buf = [0; 64];
+            assert!(client.read(&mut buf).unwrap() > 0);
+        }
+        shutdown.store(true, Ordering::SeqCst);
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn reset_connections_dont_stop_the_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_shutdown = shutdown.clone();
+        let server = thread::spawn(move || {
+            event_loop_with_state(listener, GameServerState::new(), &server_shutdown)
+        });
+        for _ in 0..5 {
+            let client = TcpStream::connect(server_addr).unwrap();
+            // let the server send its prompt, closing with it unread resets the connection
+            thread::sleep(std::time::Duration::from_millis(50));
+            drop(client);
+        }
+        // and some that go away before the server even gets to them
+        for _ in 0..5 {
+            drop(TcpStream::connect(server_addr).unwrap());
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!server.is_finished());
+        // new clients are still served
+        let mut client = TcpStream::connect(server_addr).unwrap();
+        let mut buf = [0; 64];
+        assert!(client.read(&mut buf).unwrap() > 0);
+        shutdown.store(true, Ordering::SeqCst);
+        assert!(server.join().unwrap().is_ok());
+    }
 }
\ No newline at end of file
@@ -1,7 +1,16 @@
-use std::{net::TcpListener, io::Result};
+use std::io::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use telnet_codenames::{GameServerState, ServerConfig};
 
 fn main() -> Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:1234")?;
-    return telnet_codenames::event_loop(listener);
+    let config = ServerConfig::from_args(std::env::args().skip(1))?;
+    let game_server_state = GameServerState::with_config(config)?;
+    let listener = telnet_codenames::bind_listener(game_server_state.config.ip_mode, 1234)?;
+    // stop the event loop cleanly on Ctrl-C so clients are told the server is going away
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let handler_shutdown = shutdown.clone();
+    ctrlc::set_handler(move || handler_shutdown.store(true, Ordering::SeqCst))
+        .expect("Couldn't install the Ctrl-C handler");
+    telnet_codenames::event_loop_with_state(listener, game_server_state, &shutdown)
 }
-
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::codenames::{CodenamesRoom, RoomVariant, SavedGame, WordLists};
+use crate::game::{Game, GameKind, GameRoom, RoomPassword, User};
+
+/// A room as it's kept in the save file
+#[derive(Serialize, Deserialize)]
+struct SavedRoom {
+    key : i32,
+    name : String,
+    password : Option<RoomPassword>,
+    // saves from before chat rooms are all Codenames
+    #[serde(default)]
+    kind : GameKind,
+    // only Codenames games are kept, a chat room comes back empty
+    game : Option<SavedGame>
+}
+
+/// Writes every room that's still worth coming back to out to the given file,
+/// finished games are left out since there's nothing left to play, and so are
+/// rooms nobody is in or holding a seat in since there's nobody to come back
+/// the file is replaced in one go so a crash mid write doesn't lose the last save
+pub fn save_rooms(path : &Path, game_rooms : &HashMap<i32, GameRoom>,
+                  user_state_map : &HashMap<SocketAddr, User>) -> io::Result<()> {
+    let mut rooms : Vec<SavedRoom> = game_rooms.iter()
+        .filter(|(_, room)| {
+            !room.game.has_ended() && (!room.game.players().is_empty() || room.game.holds_seats())
+        })
+        .map(|(key, room)| SavedRoom {
+            key: *key,
+            name: room.name.to_string(),
+            password: room.password.clone(),
+            kind: room.kind,
+            game: room.codenames().map(|r| r.snapshot(user_state_map))
+        })
+        .collect();
+    rooms.sort_by_key(|room| room.key);
+    let json = serde_json::to_vec_pretty(&rooms).map_err(io::Error::other)?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, json)?;
+    fs::rename(&temp_path, path)
+}
+
+/// Reads back the rooms written by save_rooms, there are none if the file doesn't exist yet
+pub fn load_rooms(path : &Path, word_lists : &Arc<WordLists>) -> io::Result<HashMap<i32, GameRoom>> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(io::Error::new(e.kind(), format!("Couldn't read save file {}: {}", path.display(), e)))
+    };
+    let rooms : Vec<SavedRoom> = serde_json::from_slice(&json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                                    format!("Save file {} is invalid: {}", path.display(), e)))?;
+    Ok(rooms.into_iter()
+        .filter_map(|room| {
+            let game : Box<dyn Game> = match (room.kind, room.game) {
+                (GameKind::Codenames, Some(game)) => Box::new(CodenamesRoom::restore(game, word_lists)),
+                // older saves kept rooms nobody had gone into, there's nothing to come back to
+                (GameKind::Codenames, None) => return None,
+                (GameKind::Chat, _) => GameKind::Chat.new_game(RoomVariant::Standard, word_lists)
+            };
+            Some((room.key, GameRoom {
+                name: room.name,
+                game,
+                kind: room.kind,
+                password: room.password
+            }))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use crate::codenames::RECONNECT_GRACE;
+    use crate::config::ServerConfig;
+    use crate::game::{GameServerState, ServerState, RECLAIM_WINDOW};
+    use crate::game::tests::{create_room, login, new_addr, send, start_game};
+
+    fn save_path(name : &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("codenames-save-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn games_resume_after_a_restart() {
+        let path = save_path("resume");
+        let config = ServerConfig { save_path: Some(path.clone()), ..ServerConfig::default() };
+        let mut game_server_state = GameServerState::with_config(config.clone()).unwrap();
+        let players = start_game(&mut game_server_state, &[]);
+        send(&mut game_server_state, players[0], "fruit,2");
+        game_server_state.save_rooms().unwrap();
+
+        let mut game_server_state = GameServerState::with_config(config).unwrap();
+        let room = game_server_state.game_rooms[&1].codenames().unwrap();
+        assert!(room.is_empty() && room.has_reserved_seat("carol") && room.has_reserved_seat("bob"));
+        // nobody loses their seat while the game waits for the first player back
+        game_server_state.release_expired_seats(Instant::now() + RECONNECT_GRACE * 2);
+        assert!(game_server_state.game_rooms[&1].codenames().unwrap().has_reserved_seat("bob"));
+
+        let bob = new_addr();
+        login(&mut game_server_state, bob, "bob");
+        assert_eq!(game_server_state.user_state[&bob].state, ServerState::RejoinOffer);
+        send(&mut game_server_state, bob, "y");
+        let prompt = game_server_state.prompt_for(bob).unwrap();
+        assert!(prompt.contains("Red Team's Turn"));
+        // the clue comes back with the replayed chat
+        assert!(game_server_state.prompt_for(bob).unwrap().contains("* Spymaster Clue: fruit, 2"));
+        // once play resumes the others get the usual grace period
+        game_server_state.release_expired_seats(Instant::now() + RECONNECT_GRACE);
+        assert!(!game_server_state.game_rooms[&1].codenames().unwrap().has_reserved_seat("carol"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rooms_nobody_comes_back_to_are_closed() {
+        let path = save_path("unclaimed");
+        let config = ServerConfig { save_path: Some(path.clone()), ..ServerConfig::default() };
+        let mut game_server_state = GameServerState::with_config(config.clone()).unwrap();
+        start_game(&mut game_server_state, &[]);
+        let dave = new_addr();
+        login(&mut game_server_state, dave, "dave");
+        create_room(&mut game_server_state, dave);
+        game_server_state.handle_input(dave, None).unwrap();
+        // nobody has been in it, so there's nobody to come back to it
+        game_server_state.create_room("empty");
+        assert_eq!(game_server_state.game_rooms.len(), 3);
+        game_server_state.save_rooms().unwrap();
+
+        let mut game_server_state = GameServerState::with_config(config.clone()).unwrap();
+        assert_eq!(game_server_state.game_rooms.len(), 2);
+        let bob = new_addr();
+        login(&mut game_server_state, bob, "bob");
+        send(&mut game_server_state, bob, "y");
+        game_server_state.handle_input(bob, None).unwrap();
+        game_server_state.release_expired_seats(Instant::now() + RECLAIM_WINDOW);
+        // dave's room waited for him, and nobody came
+        assert_eq!(game_server_state.game_rooms.keys().collect::<Vec<_>>(), [&1]);
+        // the rest had the usual time to come back once bob did
+        assert!(!game_server_state.game_rooms[&1].codenames().unwrap().has_reserved_seat("carol"));
+
+        // without anyone coming back everything goes, and isn't saved again
+        let mut game_server_state = GameServerState::with_config(config.clone()).unwrap();
+        game_server_state.release_expired_seats(Instant::now() + RECLAIM_WINDOW);
+        assert!(game_server_state.game_rooms.is_empty());
+        game_server_state.save_rooms().unwrap();
+        assert!(GameServerState::with_config(config).unwrap().game_rooms.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_and_invalid_save_files() {
+        let path = save_path("invalid");
+        let config = ServerConfig { save_path: Some(path.clone()), ..ServerConfig::default() };
+        assert!(GameServerState::with_config(config.clone()).unwrap().game_rooms.is_empty());
+        std::fs::write(&path, "not json").unwrap();
+        let err = GameServerState::with_config(config).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+}
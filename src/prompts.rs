@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// built in text of every prompt, by the key a prompts file replaces it with,
+// {placeholders} are filled in when the prompt is shown
+const DEFAULT_PROMPTS : [(&str, &str); 26] = [
+    ("welcome", "Connected to Telnet Codenames\r\n"),
+    ("username_entry", "Enter in your username, maximum of {max} characters\r\n"),
+    ("lobby", "Which lobby do you want to join? Or create a new lobby\r\n\
+        Type 'recent' to see rooms you were recently in, 'stats' to see how your games went, \
+        or 'spectate <number>' to watch a room without playing\r\n\
+        Anything else is chat to everyone else in the lobby\r\n"),
+    ("recent_rooms", "Enter a room number to rejoin it, anything else goes back\r\n"),
+    ("invalid_input", "Invalid input, please try again\r\n"),
+    ("username_taken", "{name} is already taken in that room, please pick another username\r\n"),
+    ("password_setup", "Enter a password for your room, or leave it blank to let anyone join\r\n"),
+    ("password_entry", "This room needs a password, enter it or leave it blank to go back\r\n"),
+    ("room_full", "Room is full, please pick another room\r\n"),
+    ("kicked", "You were voted out of the room\r\n"),
+    ("admin_login", "Enter the admin password, or leave it blank to go back\r\n"),
+    ("rejoin_offer", "You dropped out of a game in {room}, rejoin it? (y/n)\r\n"),
+    ("join_in_progress", "Game in progress, join as spectator? (y/n)\r\n"),
+    ("fatal_error", "A fatal error has occurred, disconnecting...\r\n"),
+    ("turn", "{team} Team's Turn:\r\n"),
+    ("give_clue", "Type in your clue in the format 'clue,number' where a clue is a single word \
+        and the number is the number of guesses your team has. Keep in mind you can't use \
+        the word you would like them to choose in the guess. A number of 0 or 'unlimited' \
+        lets your team guess as much as they like. Type 'pass' to give no clue and hand the turn \
+        to the other team\r\n"),
+    ("await_clue", "Waiting for your spymaster's clue, you can chat with everyone until it comes in\r\n"),
+    ("team_guessing", "Your team is guessing, you can't give another clue until your next turn\r\n"),
+    ("guess", "Use chat to talk to everyone but the spymaster on the {team} team. \
+        Guess by submitting your guess word with a '!' in front, \
+        or its coordinate like '!B3' when the board shows them. \
+        End your turn with '!!', after at least one guess unless the clue was 0 or unlimited.\r\n"),
+    ("confirm_guesses", "Guesses need to be confirmed with '!!yes' before the card is flipped.\r\n"),
+    ("training_risk", "Type 'risk' to see how many unflipped cards would end your turn.\r\n"),
+    ("other_team_clue", "Continue to talk to everyone, it's not your turn. The {team} spymaster is thinking of a clue\r\n"),
+    ("other_team_guessing", "Continue to talk to everyone, it's not your turn. The {team} team is guessing\r\n"),
+    ("clue", "Clue: {clue}\r\n"),
+    ("score", "Score: {red}-{blue} (R-B), type 'status' to see it again without the board \
+        or 'remaining' for the agents left, 'board' shows the board again \
+        and 'clue' the clues given so far\r\n"),
+    ("restart_hint", "Type '/restart' to vote for a new board if the game is stuck\r\n")
+];
+
+/// The text players are prompted with, any of the built in prompts can be replaced
+/// by a prompts file to translate or customize them
+#[derive(Clone, Default)]
+pub struct Prompts {
+    // prompts from the prompts file, anything not in it is built in
+    custom : HashMap<String, String>
+}
+
+impl Prompts {
+    /// Reads prompts from a JSON object of prompt keys to their text, the built in
+    /// prompts are used if there's no file, a key that isn't a prompt is an error
+    /// so a typo doesn't silently leave the built in text in place
+    pub fn load(path : Option<&Path>) -> io::Result<Prompts> {
+        let Some(path) = path else {
+            return Ok(Prompts::default());
+        };
+        let json = fs::read(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Couldn't read prompts file {}: {}", path.display(), e)))?;
+        let custom : HashMap<String, String> = serde_json::from_slice(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                                        format!("Prompts file {} is invalid: {}", path.display(), e)))?;
+        if let Some(key) = custom.keys().find(|key| !DEFAULT_PROMPTS.iter().any(|(default, _)| default == key)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("Prompts file {} has an unknown prompt {}", path.display(), key)));
+        }
+        // telnet wants \r\n whatever line endings the file was written with
+        let custom = custom.into_iter()
+            .map(|(key, text)| (key, text.replace("\r\n", "\n").replace('\n', "\r\n")))
+            .collect();
+        Ok(Prompts { custom })
+    }
+
+    /// The prompt with the given key, with each {placeholder} replaced by its value
+    pub fn get(&self, key : &str, values : &[(&str, &dyn fmt::Display)]) -> String {
+        let text = self.custom.get(key).map(String::as_str)
+            .or_else(|| DEFAULT_PROMPTS.iter().find(|(default, _)| *default == key).map(|(_, text)| *text))
+            .expect("Every prompt has a built in default");
+        values.iter().fold(text.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::config::ServerConfig;
+    use crate::game::GameServerState;
+    use crate::game::tests::new_addr;
+    use super::Prompts;
+
+    #[test]
+    fn prompts_file_replaces_built_in_text() {
+        let path = std::env::temp_dir().join(format!("codenames-prompts-{}.json", std::process::id()));
+        fs::write(&path, r#"{"turn": "Au tour de l'équipe {team} :\n", "welcome": "Bienvenue\n"}"#).unwrap();
+        let prompts = Prompts::load(Some(&path)).unwrap();
+        assert_eq!(prompts.get("turn", &[("team", &"Red")]), "Au tour de l'équipe Red :\r\n");
+        // anything the file leaves out is built in
+        assert_eq!(prompts.get("score", &[("red", &3), ("blue", &2)])
+                       .split(',').next().unwrap(), "Score: 3-2 (R-B)");
+
+        let config = ServerConfig { prompts_path: Some(path.clone()), ..ServerConfig::default() };
+        let mut game_server_state = GameServerState::with_config(config).unwrap();
+        assert_eq!(game_server_state.prompt_for(new_addr()).unwrap(), "Bienvenue\r\n");
+
+        fs::write(&path, r#"{"welcom": "Bienvenue\n"}"#).unwrap();
+        let error = Prompts::load(Some(&path)).err().unwrap();
+        assert!(error.to_string().ends_with("has an unknown prompt welcom"));
+        fs::remove_file(&path).unwrap();
+        assert!(Prompts::load(Some(&path)).is_err());
+    }
+}
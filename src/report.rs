@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::event_log::{log_event, Level};
+use crate::game::{User, get_user_state};
+
+/// A player's report about another, written as one JSON object per line of the report file
+#[derive(Serialize)]
+struct Report<'a> {
+    // seconds since the unix epoch
+    time : u64,
+    room : Option<i32>,
+    reporter : &'a str,
+    target : &'a str,
+    reason : &'a str
+}
+
+/// Adds the report to the end of the file, creating it if it isn't there yet
+fn append_report(path : &Path, report : &Report) -> io::Result<()> {
+    let mut line = serde_json::to_string(report).map_err(io::Error::other)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())
+}
+
+/// Handles '/report <username> <reason>', the report goes to the report file
+/// and only the reporter hears back about it
+pub fn report_logic(addr : SocketAddr, args : &str, report_path : Option<&Path>,
+                    user_state_map : &mut HashMap<SocketAddr, User>) {
+    let user_state = get_user_state(user_state_map, addr);
+    let Some((target, reason)) = args.trim().split_once(' ').map(|(target, reason)| (target, reason.trim())) else {
+        user_state.queue("Usage: /report <username> <reason>\r\n");
+        return;
+    };
+    let Some(report_path) = report_path else {
+        user_state.queue("Reports aren't being taken on this server\r\n");
+        return;
+    };
+    let report = Report {
+        time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+        room: user_state.game_room_key,
+        reporter: &user_state.user_name,
+        target,
+        reason
+    };
+    match append_report(report_path, &report) {
+        Ok(()) => {
+            log_event(Level::Info, &format!("{} reported {}", user_state.user_name, target));
+            user_state.queue(&format!("Thanks, your report about {} has been filed\r\n", target));
+        },
+        Err(e) => {
+            log_event(Level::Error, &format!("Couldn't write a report to {}: {}", report_path.display(), e));
+            user_state.queue("Your report couldn't be filed, please try again later\r\n");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::config::ServerConfig;
+    use crate::game::GameServerState;
+    use crate::game::tests::{create_room, login, new_addr, send};
+
+    #[test]
+    fn reports_are_appended_and_only_acknowledged() {
+        let path = std::env::temp_dir().join(format!("codenames-reports-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = ServerConfig { report_path: Some(path.clone()), ..ServerConfig::default() };
+        let mut game_server_state = GameServerState::with_config(config).unwrap();
+        let alice = new_addr();
+        let bob = new_addr();
+        login(&mut game_server_state, alice, "alice");
+        create_room(&mut game_server_state, alice);
+        game_server_state.handle_input(alice, None).unwrap();
+        login(&mut game_server_state, bob, "bob");
+        send(&mut game_server_state, bob, "1");
+        game_server_state.handle_input(bob, None).unwrap();
+        let _ = game_server_state.prompt_for(alice);
+        game_server_state.user_state.get_mut(&alice).unwrap().outbound.clear();
+
+        send(&mut game_server_state, bob, "/report alice");
+        assert!(game_server_state.user_state[&bob].outbound.ends_with(b"Usage: /report <username> <reason>\r\n"));
+        send(&mut game_server_state, bob, "/report alice spoiling the \"answers\"");
+        send(&mut game_server_state, bob, "/report alice still at it");
+        assert!(game_server_state.user_state[&bob].outbound
+            .ends_with(b"Thanks, your report about alice has been filed\r\n"));
+        // nobody else hears about it
+        assert!(game_server_state.user_state[&alice].outbound.is_empty());
+        assert_eq!(game_server_state.prompt_for(alice), None);
+
+        let reports = fs::read_to_string(&path).unwrap();
+        let reports : Vec<serde_json::Value> = reports.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(reports.len(), 2);
+        assert_eq!((&reports[0]["reporter"], &reports[0]["target"], &reports[0]["room"]), (&"bob".into(), &"alice".into(), &1.into()));
+        assert_eq!(reports[0]["reason"], "spoiling the \"answers\"");
+        fs::remove_file(&path).unwrap();
+
+        // a file that can't be written to is reported back instead of taking the game down
+        game_server_state.config.report_path = Some(std::env::temp_dir().join("codenames-missing-dir").join("reports.jsonl"));
+        send(&mut game_server_state, bob, "/report alice again");
+        assert!(game_server_state.user_state[&bob].outbound
+            .ends_with(b"Your report couldn't be filed, please try again later\r\n"));
+    }
+}
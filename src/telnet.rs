@@ -0,0 +1,129 @@
+// telnet command bytes, see RFC 854
+const IAC : u8 = 255;
+const DONT : u8 = 254;
+const DO : u8 = 253;
+const WONT : u8 = 252;
+const WILL : u8 = 251;
+const SB : u8 = 250;
+const SE : u8 = 240;
+const NOP : u8 = 241;
+// interrupt process and end of file, what some clients send for Ctrl-C and Ctrl-D
+const IP : u8 = 244;
+const EOF : u8 = 236;
+// window size option, see RFC 1073
+const NAWS : u8 = 31;
+/// Control characters a client sends on a line of their own to hang up, Ctrl-C and Ctrl-D
+pub const END_OF_TEXT : u8 = 0x03;
+pub const END_OF_TRANSMISSION : u8 = 0x04;
+
+/// Sent to new clients to ask them to report their window size
+pub const DO_NAWS : [u8; 3] = [IAC, DO, NAWS];
+/// Sent to quiet clients to check the connection's still there, clients don't show it
+pub const KEEPALIVE : [u8; 2] = [IAC, NOP];
+
+/// Takes the telnet commands out of data read from a client so only the text is left,
+/// returns the text, the window width if the client reported one (the latest if there
+/// were several) and any command cut off at the end, to go in front of the next read
+/// interrupt process and end of file commands come through as Ctrl-C and Ctrl-D
+pub fn strip_commands(data : &[u8]) -> (Vec<u8>, Option<u16>, Vec<u8>) {
+    let mut text = Vec::with_capacity(data.len());
+    let mut window_width = None;
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != IAC {
+            text.push(data[i]);
+            i += 1;
+            continue;
+        }
+        let command_len = match data.get(i + 1) {
+            None => None,
+            // an escaped 255 is part of the text
+            Some(&IAC) => {
+                text.push(IAC);
+                Some(2)
+            },
+            Some(&(WILL | WONT | DO | DONT)) => (i + 2 < data.len()).then_some(3),
+            Some(&IP) => {
+                text.push(END_OF_TEXT);
+                Some(2)
+            },
+            Some(&EOF) => {
+                text.push(END_OF_TRANSMISSION);
+                Some(2)
+            },
+            Some(&SB) => subnegotiation(&data[i + 2..]).map(|(payload, len)| {
+                if let [NAWS, w1, w2, _, _] = payload[..] {
+                    // a width of 0 means the client doesn't know it
+                    window_width = Some(u16::from_be_bytes([w1, w2])).filter(|w| *w > 0).or(window_width);
+                }
+                len + 2
+            }),
+            // the rest are two bytes, like no-op or go ahead
+            Some(_) => Some(2)
+        };
+        match command_len {
+            Some(len) => i += len,
+            None => return (text, window_width, data[i..].to_vec())
+        }
+    }
+    (text, window_width, Vec::new())
+}
+
+/// Reads a subnegotiation's contents up to the IAC SE that ends it,
+/// returns them along with the number of bytes used, None if the end hasn't arrived yet
+fn subnegotiation(data : &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut payload = Vec::new();
+    let mut i = 0;
+    while i + 1 < data.len() {
+        match (data[i], data[i + 1]) {
+            (IAC, SE) => return Some((payload, i + 2)),
+            (IAC, IAC) => {
+                payload.push(IAC);
+                i += 2;
+            },
+            (b, _) => {
+                payload.push(b);
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_stripped_from_text() {
+        let data = [b"al".as_slice(), &[IAC, WILL, NAWS], b"ice", &[IAC, IAC], b"\r\n"].concat();
+        assert_eq!(strip_commands(&data), ([b"alice".as_slice(), &[IAC], b"\r\n"].concat(), None, Vec::new()));
+    }
+
+    #[test]
+    fn window_width_is_reported() {
+        let data = [[IAC, SB, NAWS, 0, 80, 0, 24, IAC, SE].as_slice(), b"hi",
+                    &[IAC, SB, NAWS, 0, IAC, IAC, 0, 50, IAC, SE]].concat();
+        assert_eq!(strip_commands(&data), (b"hi".to_vec(), Some(255), Vec::new()));
+        // unknown widths are ignored
+        assert_eq!(strip_commands(&[IAC, SB, NAWS, 0, 0, 0, 0, IAC, SE]).1, None);
+    }
+
+    #[test]
+    fn interrupt_and_end_of_file_become_control_characters() {
+        assert_eq!(strip_commands(&[IAC, IP]).0, vec![END_OF_TEXT]);
+        assert_eq!(strip_commands(&[IAC, EOF, b'\r', b'\n']).0, vec![END_OF_TRANSMISSION, b'\r', b'\n']);
+    }
+
+    #[test]
+    fn keepalives_are_not_text() {
+        assert_eq!(strip_commands(&[b"hi".as_slice(), &KEEPALIVE].concat()), (b"hi".to_vec(), None, Vec::new()));
+    }
+
+    #[test]
+    fn unfinished_commands_are_kept() {
+        let data = [b"hi".as_slice(), &[IAC, SB, NAWS, 0, 80]].concat();
+        assert_eq!(strip_commands(&data), (b"hi".to_vec(), None, vec![IAC, SB, NAWS, 0, 80]));
+        assert_eq!(strip_commands(&[IAC, DO]), (Vec::new(), None, vec![IAC, DO]));
+    }
+}
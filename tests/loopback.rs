@@ -0,0 +1,220 @@
+//! Plays whole games against the server over loopback, the way telnet clients would
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use telnet_codenames::{event_loop_with_state, GameServerState};
+
+// how long a client waits for text before the scenario is given up on
+const WAIT_LIMIT : Duration = Duration::from_secs(5);
+
+/// An event loop running on an ephemeral port, stopped when the scenario is done
+struct TestServer {
+    port : u16,
+    shutdown : Arc<AtomicBool>,
+    handle : Option<JoinHandle<std::io::Result<()>>>
+}
+
+impl TestServer {
+    fn start() -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_shutdown = shutdown.clone();
+        let handle = thread::spawn(move || {
+            event_loop_with_state(listener, GameServerState::new(), &server_shutdown)
+        });
+        TestServer { port, shutdown, handle: Some(handle) }
+    }
+
+    fn connect(&self, user_name : &str) -> Client {
+        let stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let mut client = Client { stream, received : String::new() };
+        client.wait_for("Enter in your username");
+        client.send(user_name);
+        client.wait_for("Which lobby do you want to join?");
+        client
+    }
+
+    fn stop(mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.handle.take().unwrap().join().unwrap().unwrap();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // a failed scenario mustn't leave the loop running
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+}
+
+/// One player's connection, with everything received that hasn't been waited for yet
+struct Client {
+    stream : TcpStream,
+    received : String
+}
+
+impl Client {
+    fn send(&mut self, line : &str) {
+        self.stream.write_all(format!("{}\r\n", line).as_bytes()).unwrap();
+    }
+
+    /// Reads until the text shows up, returns everything up to and including it
+    fn wait_for(&mut self, text : &str) -> String {
+        let deadline = Instant::now() + WAIT_LIMIT;
+        loop {
+            if let Some(i) = self.received.find(text) {
+                return self.received.drain(..i + text.len()).collect();
+            }
+            assert!(Instant::now() < deadline, "gave up waiting for {:?}, got {:?}", text, self.received);
+            let mut buf = [0; 1024];
+            match self.stream.read(&mut buf) {
+                Ok(0) => panic!("server closed the connection waiting for {:?}", text),
+                // the server's telnet negotiation isn't text, so don't insist on UTF-8
+                Ok(n) => self.received += &String::from_utf8_lossy(&buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
+                Err(e) => panic!("{}", e)
+            }
+        }
+    }
+
+    /// Sends a line and waits for the reply the server gives to it
+    fn command(&mut self, line : &str, reply : &str) -> String {
+        self.send(line);
+        self.wait_for(reply)
+    }
+}
+
+/// Word, whether it's flipped and the type marker of every card on a rendered board,
+/// read cell by cell from the lines between the dashes, a cell being the flipped mark,
+/// the word and the marker, with the marks taking two columns each
+fn read_board(board : &str) -> Vec<(String, bool, char)> {
+    board.lines()
+        .filter(|line| line.starts_with('|'))
+        .flat_map(|line| line.trim_matches('|').split('|').map(str::to_string).collect::<Vec<String>>())
+        .map(|cell| {
+            let chars : Vec<char> = cell.chars().collect();
+            let word : String = chars[2..chars.len() - 2].iter().collect();
+            (word.trim().to_string(), chars[0] == 'X', chars[chars.len() - 2])
+        })
+        .collect()
+}
+
+/// Puts a client in the room with the given team and role
+fn take_seat(client : &mut Client, team : &str, role : &str) {
+    client.command(team, "Available Options");
+    client.command(role, "Available Options");
+}
+
+/// Seats alice and bob on red and carol and dave on blue, spymasters first, and starts
+/// the game, returns the clients in that order with the board as red's spymaster sees it
+fn start_game(server : &TestServer) -> (Vec<Client>, Vec<(String, bool, char)>) {
+    let mut alice = server.connect("alice");
+    alice.command("0", "Enter a password for your room");
+    alice.command("", "Available Options");
+    take_seat(&mut alice, "red", "spymaster");
+    let mut players = vec![alice];
+    for (user_name, team, role) in [("bob", "red", "teammate"), ("carol", "blue", "spymaster"),
+                                    ("dave", "blue", "teammate")] {
+        let mut client = server.connect(user_name);
+        client.command("1", "Available Options");
+        take_seat(&mut client, team, role);
+        players.push(client);
+    }
+
+    players[0].send("start");
+    let mut boards = Vec::new();
+    for client in players.iter_mut() {
+        client.wait_for("Red Team's Turn");
+        boards.push(client.wait_for("\r\n\r\n"));
+    }
+    let spymaster_view = read_board(&boards[0]);
+    assert_eq!(spymaster_view.len(), 25);
+    assert_eq!(spymaster_view.iter().filter(|(_, _, marker)| *marker == 'R').count(), 9);
+    assert_eq!(spymaster_view.iter().filter(|(_, _, marker)| *marker == 'B').count(), 8);
+    // teammates get the same words without the types
+    let teammate_view = read_board(&boards[1]);
+    assert!(teammate_view.iter().zip(&spymaster_view).all(|(seen, card)| seen.0 == card.0 && seen.2 == ' '));
+    (players, spymaster_view)
+}
+
+/// The first word on the board with the given type marker
+fn word_of(board : &[(String, bool, char)], marker : char) -> String {
+    board.iter().find(|card| card.2 == marker).unwrap().0.clone()
+}
+
+#[test]
+fn full_game_over_loopback() {
+    let server = TestServer::start();
+    let (mut players, spymaster_view) = start_game(&server);
+    let (red_word, assassin) = (word_of(&spymaster_view, 'R'), word_of(&spymaster_view, 'A'));
+
+    players[0].send("fruit,2");
+    players[1].wait_for("Clue: fruit, 2");
+    players[1].send(&format!("!{}", red_word));
+    let board = players[1].wait_for("Score: 1-0 (R-B)");
+    let board = board + &players[1].wait_for("\r\n\r\n");
+    // the guessed card is flipped over and its type shown to everyone
+    assert!(read_board(&board).contains(&(red_word.clone(), true, 'R')));
+
+    players[1].command("!!", "Blue Team's Turn");
+    players[2].wait_for("Blue Team's Turn");
+    players[2].send("ocean,1");
+    players[3].wait_for("Clue: ocean, 1");
+    players[3].send(&format!("!{}", assassin));
+    for client in players.iter_mut() {
+        client.wait_for("The game has ended, thanks for playing!");
+    }
+    let transcript = players[0].command("transcript", "Final score 1-0 (R-B)");
+    assert!(transcript.contains("The Blue team found the assassin"));
+
+    server.stop();
+    for client in players.iter_mut() {
+        client.wait_for("Server shutting down");
+    }
+}
+
+#[test]
+fn guess_and_end_turn_sent_together() {
+    let server = TestServer::start();
+    let (mut players, spymaster_view) = start_game(&server);
+    let red_word = word_of(&spymaster_view, 'R');
+    players[0].send("fruit,2");
+    players[1].wait_for("Clue: fruit, 2");
+    // both lines go out in the one write
+    players[1].send(&format!("!{}\r\n!!", red_word));
+    // both are run before the next prompt, the guess first and then the end of the turn
+    for client in players.iter_mut() {
+        client.wait_for("Blue Team's Turn");
+        let board = client.wait_for("Score: 1-0 (R-B)") + &client.wait_for("\r\n\r\n");
+        assert!(read_board(&board).contains(&(red_word.clone(), true, 'R')));
+    }
+    server.stop();
+}
+
+#[test]
+fn observers_see_flips_without_typing() {
+    let server = TestServer::start();
+    let (mut players, spymaster_view) = start_game(&server);
+    // joining mid game lets erin watch, with the types hidden like a teammate's
+    let mut erin = server.connect("erin");
+    erin.command("1", "Game in progress, join as spectator? (y/n)");
+    erin.command("y", "Red Team's Turn");
+    let board = erin.wait_for("\r\n\r\n");
+    assert!(read_board(&board).iter().zip(&spymaster_view).all(|(seen, card)| seen.0 == card.0 && seen.2 == ' '));
+
+    let red_word = word_of(&spymaster_view, 'R');
+    players[0].send("fruit,2");
+    players[1].wait_for("Clue: fruit, 2");
+    players[1].send(&format!("!{}", red_word));
+    // erin only ever sent the room number
+    erin.wait_for("Score: 1-0 (R-B)");
+    let board = erin.wait_for("\r\n\r\n");
+    assert!(read_board(&board).contains(&(red_word, true, 'R')));
+    server.stop();
+}